@@ -0,0 +1,120 @@
+//! Compares the tree-walking interpreter with the bytecode VM.
+//!
+//! Run with `cargo bench`. Each workload runs a few times on both engines and
+//! the fastest run of each is reported, along with how much faster the VM was.
+
+use std::time::{Duration, Instant};
+
+use elden::{
+    analyze_program, bytecode::Module, get_program, interpreter::Interpreter, interpreter::Value,
+    program::Program, run_lexer, vm::Vm,
+};
+
+const RUNS: usize = 5;
+
+const WORKLOADS: &[(&str, &str)] = &[
+    (
+        "loop",
+        "func main() {
+            let total = 0;
+            let i = 0;
+            while (i < 1_000_000) {
+                total += i % 7;
+                i += 1;
+            }
+            return total;
+        }",
+    ),
+    (
+        "fib",
+        "func fib(n) {
+            if (n < 2) { return n; }
+            return fib(n - 1) + fib(n - 2);
+        }
+        func main() { return fib(24); }",
+    ),
+    (
+        "sort",
+        "func main() {
+            let values = [];
+            let seed = 7;
+            for (let i = 0; i < 400; i += 1) {
+                seed = (seed * 1103515245 + 12345) % 2147483648;
+                values.append(seed % 1000);
+            }
+            for (let i = 0; i < values.length; i += 1) {
+                for (let j = 0; j < values.length - 1 - i; j += 1) {
+                    if (values[j] > values[j + 1]) {
+                        let swap = values[j];
+                        values[j] = values[j + 1];
+                        values[j + 1] = swap;
+                    }
+                }
+            }
+            return values[0];
+        }",
+    ),
+    (
+        "strings",
+        "func main() {
+            let words = [];
+            for (let i = 0; i < 20_000; i += 1) {
+                words.append(\"item ${i}\");
+            }
+            let letters = 0;
+            for word in words {
+                letters += word.length;
+            }
+            return letters;
+        }",
+    ),
+];
+
+fn parse(source: &str) -> Program {
+    let (tokens, _) = run_lexer(source).expect("workloads lex");
+    let program = get_program(&tokens).expect("workloads parse");
+    let (program, _) = analyze_program(program).expect("workloads type check");
+    program
+}
+
+// The fastest of a few runs, and what the last one returned.
+fn time(mut run: impl FnMut() -> Value) -> (Duration, Value) {
+    let mut best = Duration::MAX;
+    let mut value = Value::Void;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        value = run();
+        best = best.min(start.elapsed());
+    }
+    (best, value)
+}
+
+fn main() {
+    println!(
+        "{:<10} {:>14} {:>14} {:>14} {:>8}",
+        "workload", "interpreter", "compile", "vm", "speedup"
+    );
+    for (name, source) in WORKLOADS {
+        let program = parse(source);
+
+        let (interpreted, expected) = time(|| {
+            Interpreter::new(&program)
+                .run()
+                .expect("workloads run on the interpreter")
+        });
+        let start = Instant::now();
+        let module = Module::compile(&program);
+        let compile = start.elapsed();
+        let (vm, value) = time(|| Vm::new(&module).run().expect("workloads run on the vm"));
+        assert_eq!(value, expected, "the engines disagree on {}", name);
+
+        println!(
+            "{:<10} {:>14?} {:>14?} {:>14?} {:>7.2}x",
+            name,
+            interpreted,
+            compile,
+            vm,
+            interpreted.as_secs_f64() / vm.as_secs_f64()
+        );
+    }
+}
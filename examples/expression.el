@@ -1,4 +1,5 @@
 func main() {
-    let arr = [[1 * 5] * 6];
+    let arr = [[1 * 5], [6]];
+    print(arr[0][0] * arr[1][0]);
     return 1 * 2 - 4 % 6;
 }
\ No newline at end of file
@@ -0,0 +1,15 @@
+func square(x) {
+    return x * x;
+}
+
+func main() {
+    let squares = [];
+    let i = 1;
+    while (i <= 5) {
+        squares.append(square(i));
+        i = i + 1;
+    }
+    print("squares:", squares);
+    print("half of the last:", 25.0 / 2.0, "is whole:", 25 % 2 == 0);
+    return 0;
+}
@@ -1,9 +1,11 @@
+/// Sums the numbers below ten.
 func main() {
     let i = 0;
     let sum = 0;
     while (i < 10) {
-        sum = sum + i;
-        i = i + 1;
+        sum += i; // running total
+        i += 1;
     }
+    /* the exit code carries the result */
     return sum;
 }
@@ -0,0 +1,980 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    diagnostic::{codes, Diagnostic},
+    expression::{Expression, ExpressionKind, FormatPart},
+    function::{function_name, Function},
+    interpreter::{arity_mismatch, identifier_name, type_error, undefined_variable, Value},
+    program::Program,
+    span::Span,
+    statement::{Statement, StatementKind},
+    token::Token,
+};
+
+/// A single VM instruction. Operands index into the module's constants and
+/// functions or the current function's locals, code and paths.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    /// Pushes a constant from the pool.
+    Constant(u32),
+    /// Pushes `Value::Void`.
+    Void,
+    /// Drops the top of the stack.
+    Pop,
+    LoadLocal(u32),
+    /// Pops a value into a local.
+    StoreLocal(u32),
+    /// Pops a value and combines it into a local, as in `x += value`.
+    UpdateLocal(u32, BinaryOp),
+    /// Pops one index per level of the path and pushes the element of the
+    /// local they lead to.
+    LoadElement {
+        local: u32,
+        path: u32,
+    },
+    /// Pops a value, then one index per level of the path, and stores the
+    /// value into the element of the local they lead to. With an operator the
+    /// value is combined into the element instead.
+    StoreElement {
+        local: u32,
+        path: u32,
+        operator: Option<BinaryOp>,
+    },
+    /// Pops an index and an array and pushes the element, for arrays that
+    /// aren't stored in a variable.
+    Index(u32),
+    /// Pushes the length of the array or string in a local.
+    Length(u32),
+    /// Pops a value, appends it to the array in a local and pushes `Void`.
+    Append(u32),
+    /// Pops that many values and pushes them as an array.
+    MakeArray(u32),
+    /// Pops that many values and pushes them joined into a string.
+    Format(u32),
+    Binary(BinaryOp),
+    Unary(UnaryOp),
+    /// Pops the left side of `&&` or `||`. If that decides the result, pushes
+    /// it and jumps.
+    ShortCircuit {
+        operator: BinaryOp,
+        target: u32,
+    },
+    /// Checks that the right side of `&&` or `||` is a boolean.
+    CheckBool(BinaryOp),
+    Jump(u32),
+    /// Pops a condition and jumps if it is false.
+    JumpIfFalse(u32),
+    /// Pops an array to loop over into a local, and a counter into the one
+    /// after it.
+    IntoIter(u32),
+    /// Pushes the next element of the array being looped over, or jumps once
+    /// there are none left.
+    IterNext {
+        local: u32,
+        exit: u32,
+    },
+    /// Calls a function with the arguments on top of the stack.
+    Call {
+        function: u32,
+        argc: u32,
+    },
+    /// Returns the top of the stack from the current function.
+    Return,
+    /// Pops that many values and prints them on a line.
+    Print(u32),
+    /// Raises one of the module's failures.
+    Fail(u32),
+}
+
+/// The binary operators the VM can apply, including `&&` and `||`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    pub fn from_token(token: &Token) -> Option<Self> {
+        let op = match token {
+            Token::Add => BinaryOp::Add,
+            Token::Sub => BinaryOp::Sub,
+            Token::Mul => BinaryOp::Mul,
+            Token::Div => BinaryOp::Div,
+            Token::Mod => BinaryOp::Mod,
+            Token::EqualEqual => BinaryOp::Equal,
+            Token::NotEqual => BinaryOp::NotEqual,
+            Token::Greater => BinaryOp::Greater,
+            Token::GreaterEqual => BinaryOp::GreaterEqual,
+            Token::Less => BinaryOp::Less,
+            Token::LessEqual => BinaryOp::LessEqual,
+            Token::BitAnd => BinaryOp::BitAnd,
+            Token::BitOr => BinaryOp::BitOr,
+            Token::BitXor => BinaryOp::BitXor,
+            Token::ShiftLeft => BinaryOp::ShiftLeft,
+            Token::ShiftRight => BinaryOp::ShiftRight,
+            Token::And => BinaryOp::And,
+            Token::Or => BinaryOp::Or,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    pub fn token(self) -> Token {
+        match self {
+            BinaryOp::Add => Token::Add,
+            BinaryOp::Sub => Token::Sub,
+            BinaryOp::Mul => Token::Mul,
+            BinaryOp::Div => Token::Div,
+            BinaryOp::Mod => Token::Mod,
+            BinaryOp::Equal => Token::EqualEqual,
+            BinaryOp::NotEqual => Token::NotEqual,
+            BinaryOp::Greater => Token::Greater,
+            BinaryOp::GreaterEqual => Token::GreaterEqual,
+            BinaryOp::Less => Token::Less,
+            BinaryOp::LessEqual => Token::LessEqual,
+            BinaryOp::BitAnd => Token::BitAnd,
+            BinaryOp::BitOr => Token::BitOr,
+            BinaryOp::BitXor => Token::BitXor,
+            BinaryOp::ShiftLeft => Token::ShiftLeft,
+            BinaryOp::ShiftRight => Token::ShiftRight,
+            BinaryOp::And => Token::And,
+            BinaryOp::Or => Token::Or,
+        }
+    }
+
+    /// How the operator is written in source.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::ShiftLeft => "<<",
+            BinaryOp::ShiftRight => ">>",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+    BitNot,
+}
+
+impl UnaryOp {
+    pub fn from_token(token: &Token) -> Option<Self> {
+        match token {
+            Token::Sub => Some(UnaryOp::Negate),
+            Token::Not => Some(UnaryOp::Not),
+            Token::BitNot => Some(UnaryOp::BitNot),
+            _ => None,
+        }
+    }
+
+    pub fn token(self) -> Token {
+        match self {
+            UnaryOp::Negate => Token::Sub,
+            UnaryOp::Not => Token::Not,
+            UnaryOp::BitNot => Token::BitNot,
+        }
+    }
+
+    /// How the operator is written in source.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Negate => "-",
+            UnaryOp::Not => "!",
+            UnaryOp::BitNot => "~",
+        }
+    }
+}
+
+/// The compiled form of one function.
+#[derive(Debug)]
+pub struct CodeObject {
+    pub name: String,
+    pub name_span: Span,
+    pub arity: usize,
+    /// How many local slots a call needs, parameters first.
+    pub locals: usize,
+    pub code: Vec<Instruction>,
+    /// The source span of each instruction, for runtime errors.
+    pub spans: Vec<Span>,
+    /// The index span and access span of every level of an indexing chain,
+    /// outermost array first. Indexing instructions refer to these.
+    pub paths: Vec<Vec<(Span, Span)>>,
+}
+
+/// A whole program lowered to bytecode.
+#[derive(Debug)]
+pub struct Module {
+    /// Literals used by every function, each stored once.
+    pub constants: Vec<Value>,
+    pub functions: Vec<CodeObject>,
+    /// Errors that are already known at compile time, like a call to a
+    /// function that doesn't exist. They are only raised if execution gets
+    /// to them, just like in the tree-walking interpreter.
+    pub failures: Vec<Diagnostic>,
+    names: HashMap<String, usize>,
+}
+
+impl Module {
+    pub fn compile(program: &Program) -> Self {
+        // Like the interpreter, a later function replaces an earlier one with
+        // the same name
+        let mut names = HashMap::new();
+        for (index, function) in program.functions.iter().enumerate() {
+            if let Some(name) = function_name(&function.name.node) {
+                names.insert(name.to_string(), index);
+            }
+        }
+
+        let mut module = Module {
+            constants: Vec::new(),
+            functions: Vec::new(),
+            failures: Vec::new(),
+            names,
+        };
+        for function in &program.functions {
+            let code = Compiler::new(&mut module, program, function).compile(function);
+            module.functions.push(code);
+        }
+        module
+    }
+
+    /// Finds the function called by `name`.
+    pub fn function(&self, name: &str) -> Option<usize> {
+        self.names.get(name).copied()
+    }
+
+    fn constant(&mut self, value: Value) -> u32 {
+        let index = self
+            .constants
+            .iter()
+            .position(|constant| same_constant(constant, &value))
+            .unwrap_or_else(|| {
+                self.constants.push(value);
+                self.constants.len() - 1
+            });
+        index as u32
+    }
+
+    fn failure(&mut self, diagnostic: Diagnostic) -> u32 {
+        self.failures.push(diagnostic);
+        (self.failures.len() - 1) as u32
+    }
+}
+
+// `==` on floats would merge `0.0` with `-0.0`, which print differently.
+pub(crate) fn same_constant(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+        (Value::Float(_), _) | (_, Value::Float(_)) => false,
+        _ => a == b,
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "constants:")?;
+        for (index, constant) in self.constants.iter().enumerate() {
+            writeln!(f, "    {:>4}  {:?}", index, constant)?;
+        }
+        for function in &self.functions {
+            writeln!(
+                f,
+                "func {} (arity {}, {} locals):",
+                function.name, function.arity, function.locals
+            )?;
+            for (offset, instruction) in function.code.iter().enumerate() {
+                match instruction {
+                    Instruction::Constant(index) => writeln!(
+                        f,
+                        "    {:>4}  {:?}  ; {:?}",
+                        offset, instruction, self.constants[*index as usize]
+                    )?,
+                    Instruction::Call { function, .. } => writeln!(
+                        f,
+                        "    {:>4}  {:?}  ; {}",
+                        offset, instruction, self.functions[*function as usize].name
+                    )?,
+                    _ => writeln!(f, "    {:>4}  {:?}", offset, instruction)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Jumps waiting for the end of a loop, and for the place `continue` goes to.
+#[derive(Default)]
+struct Loop {
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+// Lowers one function. Variables are resolved to slots as they are declared,
+// so scopes only exist while compiling.
+struct Compiler<'a> {
+    module: &'a mut Module,
+    program: &'a Program,
+    code: CodeObject,
+    scopes: Vec<HashMap<String, u32>>,
+    loops: Vec<Loop>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(module: &'a mut Module, program: &'a Program, function: &Function) -> Self {
+        let name = function_name(&function.name.node).unwrap_or_default();
+        Compiler {
+            module,
+            program,
+            code: CodeObject {
+                name: name.to_string(),
+                name_span: function.name.span,
+                arity: function.params.len(),
+                locals: 0,
+                code: Vec::new(),
+                spans: Vec::new(),
+                paths: Vec::new(),
+            },
+            scopes: vec![HashMap::new()],
+            loops: Vec::new(),
+        }
+    }
+
+    fn compile(mut self, function: &Function) -> CodeObject {
+        for param in &function.params {
+            match identifier_name(&param.node, param.span) {
+                Ok(name) => {
+                    self.declare(name);
+                }
+                Err(err) => self.fail(err, param.span),
+            }
+        }
+        self.block(&function.body);
+        // Falling off the end returns `Void`
+        self.emit(Instruction::Void, function.span);
+        self.emit(Instruction::Return, function.span);
+        self.code
+    }
+
+    fn emit(&mut self, instruction: Instruction, span: Span) -> usize {
+        self.code.code.push(instruction);
+        self.code.spans.push(span);
+        self.code.code.len() - 1
+    }
+
+    fn fail(&mut self, diagnostic: Diagnostic, span: Span) {
+        let index = self.module.failure(diagnostic);
+        self.emit(Instruction::Fail(index), span);
+    }
+
+    fn constant(&mut self, value: Value, span: Span) {
+        let index = self.module.constant(value);
+        self.emit(Instruction::Constant(index), span);
+    }
+
+    fn here(&self) -> u32 {
+        self.code.code.len() as u32
+    }
+
+    // Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        match &mut self.code.code[at] {
+            Instruction::Jump(to)
+            | Instruction::JumpIfFalse(to)
+            | Instruction::ShortCircuit { target: to, .. }
+            | Instruction::IterNext { exit: to, .. } => *to = target,
+            other => unreachable!("patching {:?}, which isn't a jump", other),
+        }
+    }
+
+    fn path(&mut self, levels: Vec<(Span, Span)>) -> u32 {
+        self.code.paths.push(levels);
+        (self.code.paths.len() - 1) as u32
+    }
+
+    // Declares a variable in the innermost scope. Declaring it again in the
+    // same scope reuses the slot, as the interpreter overwrites it.
+    fn declare(&mut self, name: &str) -> u32 {
+        if let Some(&slot) = self.scopes.last().and_then(|scope| scope.get(name)) {
+            return slot;
+        }
+        let slot = self.temporary();
+        self.scopes
+            .last_mut()
+            .expect("a function always has a scope")
+            .insert(name.to_string(), slot);
+        slot
+    }
+
+    // A slot that no name refers to.
+    fn temporary(&mut self) -> u32 {
+        self.code.locals += 1;
+        (self.code.locals - 1) as u32
+    }
+
+    // Finds the slot of the variable `token` names. If there isn't one, emits
+    // the error the interpreter would raise at `span` instead.
+    fn variable(&mut self, token: &Token, span: Span) -> Option<u32> {
+        let name = match identifier_name(token, span) {
+            Ok(name) => name,
+            Err(err) => {
+                self.fail(err, span);
+                return None;
+            }
+        };
+        let slot = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied());
+        if slot.is_none() {
+            self.fail(undefined_variable(name, span), span);
+        }
+        slot
+    }
+
+    fn block(&mut self, statements: &[Statement]) {
+        self.scopes.push(HashMap::new());
+        for statement in statements {
+            self.statement(statement);
+        }
+        self.scopes.pop();
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        let span = statement.span;
+        match &statement.kind {
+            StatementKind::DeclareStatement { identifier, value } => {
+                match value {
+                    Some(expr) => self.expression(expr),
+                    None => {
+                        self.emit(Instruction::Void, span);
+                    }
+                }
+                match identifier_name(identifier, span) {
+                    Ok(name) => {
+                        let slot = self.declare(name);
+                        self.emit(Instruction::StoreLocal(slot), span);
+                    }
+                    Err(err) => self.fail(err, span),
+                }
+            }
+            StatementKind::AssignStatement { target, value } => {
+                self.assign(target, None, value, span);
+            }
+            StatementKind::CompoundAssign {
+                target,
+                operator,
+                value,
+            } => {
+                self.assign(target, Some(operator), value, span);
+            }
+            StatementKind::ArrayAppend { identifier, value } => {
+                self.append(&identifier.node, value, span);
+                self.emit(Instruction::Pop, span);
+            }
+            StatementKind::IfStatement {
+                cond,
+                if_then,
+                else_then,
+            } => {
+                self.expression(cond);
+                let skip_then = self.emit(Instruction::JumpIfFalse(0), cond.span);
+                self.block(if_then);
+                match else_then {
+                    Some(else_then) => {
+                        let skip_else = self.emit(Instruction::Jump(0), span);
+                        self.patch(skip_then);
+                        self.block(else_then);
+                        self.patch(skip_else);
+                    }
+                    None => self.patch(skip_then),
+                }
+            }
+            StatementKind::WhileStatement { cond, loop_stmt } => {
+                let start = self.here();
+                self.expression(cond);
+                let exit = self.emit(Instruction::JumpIfFalse(0), cond.span);
+                self.loops.push(Loop::default());
+                self.block(loop_stmt);
+                self.end_loop(start, start, span);
+                self.patch(exit);
+                self.patch_breaks();
+            }
+            StatementKind::ForStatement {
+                init,
+                cond,
+                step,
+                body,
+            } => {
+                // Variables declared in the header live in a scope around the loop
+                self.scopes.push(HashMap::new());
+                if let Some(init) = init {
+                    self.statement(init);
+                }
+                let start = self.here();
+                let exit = cond.as_ref().map(|cond| {
+                    self.expression(cond);
+                    self.emit(Instruction::JumpIfFalse(0), cond.span)
+                });
+                self.loops.push(Loop::default());
+                self.block(body);
+                // `continue` still runs the step
+                let step_start = self.here();
+                if let Some(step) = step {
+                    self.statement(step);
+                }
+                self.end_loop(start, step_start, span);
+                if let Some(exit) = exit {
+                    self.patch(exit);
+                }
+                self.patch_breaks();
+                self.scopes.pop();
+            }
+            StatementKind::ForInStatement {
+                variable,
+                iterable,
+                body,
+            } => {
+                // The array and the position in it live in two hidden slots,
+                // so the loop walks a snapshot like the interpreter does
+                self.expression(iterable);
+                let iter = self.temporary();
+                self.temporary();
+                self.emit(Instruction::IntoIter(iter), iterable.span);
+                let start = self.here();
+                let exit = self.emit(
+                    Instruction::IterNext {
+                        local: iter,
+                        exit: 0,
+                    },
+                    span,
+                );
+                self.scopes.push(HashMap::new());
+                match identifier_name(&variable.node, variable.span) {
+                    Ok(name) => {
+                        let slot = self.declare(name);
+                        self.emit(Instruction::StoreLocal(slot), span);
+                    }
+                    Err(err) => self.fail(err, span),
+                }
+                self.loops.push(Loop::default());
+                self.block(body);
+                self.end_loop(start, start, span);
+                self.scopes.pop();
+                self.patch(exit);
+                self.patch_breaks();
+            }
+            StatementKind::ReturnStatement { value } => {
+                self.expression(value);
+                self.emit(Instruction::Return, span);
+            }
+            StatementKind::ExpressionStatement { expr } => {
+                self.expression(expr);
+                self.emit(Instruction::Pop, span);
+            }
+            StatementKind::BreakStatement | StatementKind::ContinueStatement => {
+                // Outside of a loop they end the function, as they do in the
+                // interpreter
+                if self.loops.is_empty() {
+                    self.emit(Instruction::Void, span);
+                    self.emit(Instruction::Return, span);
+                    return;
+                }
+                let jump = self.emit(Instruction::Jump(0), span);
+                let innermost = self.loops.last_mut().expect("checked above");
+                if let StatementKind::BreakStatement = statement.kind {
+                    innermost.breaks.push(jump);
+                } else {
+                    innermost.continues.push(jump);
+                }
+            }
+            StatementKind::PrintStatement { args } => {
+                for arg in args {
+                    self.expression(arg);
+                }
+                self.emit(Instruction::Print(args.len() as u32), span);
+            }
+        }
+    }
+
+    // Jumps back to `start` at the end of a loop body and sends the loop's
+    // `continue`s to `next`. The loop's `break`s are left to patch_breaks.
+    fn end_loop(&mut self, start: u32, next: u32, span: Span) {
+        self.emit(Instruction::Jump(start), span);
+        let innermost = self.loops.last_mut().expect("inside a loop");
+        for &jump in &innermost.continues {
+            self.code.code[jump] = Instruction::Jump(next);
+        }
+    }
+
+    fn patch_breaks(&mut self) {
+        let innermost = self.loops.pop().expect("inside a loop");
+        for jump in innermost.breaks {
+            self.patch(jump);
+        }
+    }
+
+    fn assign(
+        &mut self,
+        target: &Expression,
+        operator: Option<&Token>,
+        value: &Expression,
+        span: Span,
+    ) {
+        let (root, accesses) = match target.place() {
+            Some(place) => place,
+            None => {
+                let err = type_error("Cannot assign to this expression".to_string(), target.span);
+                return self.fail(err, target.span);
+            }
+        };
+        let levels = self.indices(&accesses);
+        self.expression(value);
+
+        let Some(slot) = self.variable(root, span) else {
+            return;
+        };
+        let operator = match operator {
+            Some(operator) => match self.operator(operator, span) {
+                Some(operator) => Some(operator),
+                None => return,
+            },
+            None => None,
+        };
+        let instruction = match (levels.is_empty(), operator) {
+            (true, None) => Instruction::StoreLocal(slot),
+            (true, Some(operator)) => Instruction::UpdateLocal(slot, operator),
+            (false, operator) => Instruction::StoreElement {
+                local: slot,
+                path: self.path(levels),
+                operator,
+            },
+        };
+        self.emit(instruction, span);
+    }
+
+    // Maps an operator token to the VM's operator, emitting an error in its
+    // place if the VM has none for it.
+    fn operator(&mut self, token: &Token, span: Span) -> Option<BinaryOp> {
+        let operator = BinaryOp::from_token(token);
+        if operator.is_none() {
+            self.fail(
+                Diagnostic::error(
+                    codes::TYPE_ERROR,
+                    format!("Unsupported operator '{}'", token),
+                    span,
+                ),
+                span,
+            );
+        }
+        operator
+    }
+
+    // Compiles the index of each access in a chain, outermost array first,
+    // and returns the spans to report a bad index at.
+    fn indices(&mut self, accesses: &[&Expression]) -> Vec<(Span, Span)> {
+        let mut levels = Vec::with_capacity(accesses.len());
+        for access in accesses {
+            if let ExpressionKind::AccessIndex { index, .. } = &access.kind {
+                self.expression(index);
+                levels.push((index.span, access.span));
+            }
+        }
+        levels
+    }
+
+    fn append(&mut self, identifier: &Token, value: &Expression, span: Span) {
+        self.expression(value);
+        if let Some(slot) = self.variable(identifier, span) {
+            self.emit(Instruction::Append(slot), span);
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression) {
+        let span = expr.span;
+        match &expr.kind {
+            ExpressionKind::Token(token) => match token {
+                Token::Integer(n) => self.constant(Value::Integer(*n), span),
+                Token::Float(n) => self.constant(Value::Float(*n), span),
+                Token::Boolean(b) => self.constant(Value::Boolean(*b), span),
+                Token::String(s) => self.constant(Value::String(s.clone()), span),
+                Token::Identifier(_) => {
+                    if let Some(slot) = self.variable(token, span) {
+                        self.emit(Instruction::LoadLocal(slot), span);
+                    }
+                }
+                _ => self.fail(
+                    type_error(format!("Cannot evaluate token '{}'", token), span),
+                    span,
+                ),
+            },
+            ExpressionKind::Grouping(inner) => self.expression(inner),
+            ExpressionKind::Format { parts } => {
+                for part in parts {
+                    match part {
+                        FormatPart::Text(text) => self.constant(Value::String(text.clone()), span),
+                        FormatPart::Expr(inner) => self.expression(inner),
+                    }
+                }
+                self.emit(Instruction::Format(parts.len() as u32), span);
+            }
+            ExpressionKind::ArrayDec { arr_expr } => {
+                for element in arr_expr {
+                    self.expression(element);
+                }
+                self.emit(Instruction::MakeArray(arr_expr.len() as u32), span);
+            }
+            ExpressionKind::AccessIndex { array, index } => match expr.place() {
+                // Walk into the variable in place so only the element is copied
+                Some((root, accesses)) => {
+                    let levels = self.indices(&accesses);
+                    if let Some(slot) = self.variable(root, span) {
+                        let path = self.path(levels);
+                        self.emit(Instruction::LoadElement { local: slot, path }, span);
+                    }
+                }
+                None => {
+                    self.expression(array);
+                    self.expression(index);
+                    let path = self.path(vec![(index.span, span)]);
+                    self.emit(Instruction::Index(path), span);
+                }
+            },
+            ExpressionKind::GetLength { ident } => {
+                if let Some(slot) = self.variable(ident, span) {
+                    self.emit(Instruction::Length(slot), span);
+                }
+            }
+            ExpressionKind::ArrayAppend { array, value } => self.append(array, value, span),
+            ExpressionKind::FunctionCall { identifier, args } => {
+                for arg in args {
+                    self.expression(arg);
+                }
+                self.call(identifier, args.len(), span);
+            }
+            ExpressionKind::Unary { operator, operand } => {
+                self.expression(operand);
+                match UnaryOp::from_token(operator) {
+                    Some(operator) => {
+                        self.emit(Instruction::Unary(operator), span);
+                    }
+                    None => self.fail(
+                        Diagnostic::error(
+                            codes::TYPE_ERROR,
+                            format!("Unsupported operator '{}'", operator),
+                            span,
+                        ),
+                        span,
+                    ),
+                }
+            }
+            ExpressionKind::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left);
+                let Some(operator) = self.operator(operator, span) else {
+                    return;
+                };
+                // `&&` and `||` only evaluate their right side when needed
+                if let BinaryOp::And | BinaryOp::Or = operator {
+                    let short_circuit = self.emit(
+                        Instruction::ShortCircuit {
+                            operator,
+                            target: 0,
+                        },
+                        span,
+                    );
+                    self.expression(right);
+                    self.emit(Instruction::CheckBool(operator), span);
+                    self.patch(short_circuit);
+                } else {
+                    self.expression(right);
+                    self.emit(Instruction::Binary(operator), span);
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, identifier: &Token, argc: usize, span: Span) {
+        let name = match identifier_name(identifier, span) {
+            Ok(name) => name,
+            Err(err) => return self.fail(err, span),
+        };
+        let Some(index) = self.module.function(name) else {
+            let err = Diagnostic::error(
+                codes::UNDEFINED_FUNCTION,
+                format!("Undefined function '{}'", name),
+                span,
+            );
+            return self.fail(err, span);
+        };
+        let function = &self.program.functions[index];
+        if function.params.len() != argc {
+            let err = arity_mismatch(name, function.params.len(), function.name.span, argc, span);
+            return self.fail(err, span);
+        }
+        self.emit(
+            Instruction::Call {
+                function: index as u32,
+                argc: argc as u32,
+            },
+            span,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_program, run_lexer};
+
+    fn compile(source: &str) -> Module {
+        let (tokens, _) = run_lexer(source).unwrap();
+        let program = get_program(&tokens).unwrap();
+        Module::compile(&program)
+    }
+
+    #[test]
+    fn test_constants_are_shared() {
+        let module = compile(
+            "func helper() { return 1.0 + 2; }
+            func main() { let s = \"hi\"; return 2 + 1.0 + s.length + 0.0; }",
+        );
+        assert_eq!(
+            module.constants,
+            vec![
+                Value::Float(1.0),
+                Value::Integer(2),
+                Value::String("hi".to_string()),
+                Value::Float(0.0),
+            ]
+        );
+        assert!(!same_constant(&Value::Float(0.0), &Value::Float(-0.0)));
+        assert!(!same_constant(&Value::Float(1.0), &Value::Integer(1)));
+    }
+
+    #[test]
+    fn test_while_loop_code() {
+        let module = compile("func main() { let i = 0; while (i < 3) { i += 1; } return i; }");
+        let main = &module.functions[module.function("main").unwrap()];
+        assert_eq!(main.locals, 1);
+        assert_eq!(
+            main.code,
+            vec![
+                Instruction::Constant(0),
+                Instruction::StoreLocal(0),
+                Instruction::LoadLocal(0),
+                Instruction::Constant(1),
+                Instruction::Binary(BinaryOp::Less),
+                Instruction::JumpIfFalse(9),
+                Instruction::Constant(2),
+                Instruction::UpdateLocal(0, BinaryOp::Add),
+                Instruction::Jump(2),
+                Instruction::LoadLocal(0),
+                Instruction::Return,
+                Instruction::Void,
+                Instruction::Return,
+            ][..]
+        );
+        assert_eq!(main.spans.len(), main.code.len());
+    }
+
+    #[test]
+    fn test_break_and_continue_targets() {
+        let module = compile(
+            "func main() {
+                for (let i = 0; i < 3; i += 1) {
+                    if (i == 1) { continue; }
+                    break;
+                }
+                return 0;
+            }",
+        );
+        let main = &module.functions[0];
+        let jumps: Vec<_> = main
+            .code
+            .iter()
+            .filter(|instruction| matches!(instruction, Instruction::Jump(_)))
+            .collect();
+        // `continue` goes to the step, `break` past the loop, and the end of
+        // the body back to the condition
+        assert_eq!(
+            jumps,
+            vec![
+                &Instruction::Jump(12),
+                &Instruction::Jump(15),
+                &Instruction::Jump(2)
+            ]
+        );
+        assert_eq!(main.code[12], Instruction::Constant(2));
+        assert_eq!(main.code[15], Instruction::Constant(0));
+    }
+
+    #[test]
+    fn test_errors_known_at_compile_time() {
+        let module = compile(
+            "func pair(a, b) { return [a, b]; }
+            func main() { return pair(1) + missing; }",
+        );
+        let main = &module.functions[1];
+        assert_eq!(module.failures.len(), 2);
+        assert_eq!(module.failures[0].code, codes::ARITY_MISMATCH);
+        assert_eq!(module.failures[1].code, codes::UNDEFINED_VARIABLE);
+        assert_eq!(main.code[1], Instruction::Fail(0));
+        assert_eq!(main.code[2], Instruction::Fail(1));
+    }
+
+    #[test]
+    fn test_disassembly() {
+        let module = compile("func main() { print(\"hi\", 1); }");
+        assert_eq!(
+            module.to_string(),
+            "constants:
+       0  String(\"hi\")
+       1  Integer(1)
+func main (arity 0, 0 locals):
+       0  Constant(0)  ; String(\"hi\")
+       1  Constant(1)  ; Integer(1)
+       2  Print(2)
+       3  Void
+       4  Return
+"
+        );
+    }
+}
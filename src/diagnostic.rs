@@ -0,0 +1,261 @@
+use std::fmt;
+
+use crate::span::Span;
+
+/// Stable codes for every diagnostic the compiler can emit, grouped by phase.
+/// Tools should match on these rather than on message text.
+pub mod codes {
+    // Lexer
+    pub const UNKNOWN_TOKEN: &str = "E0001";
+    pub const UNTERMINATED_STRING: &str = "E0002";
+    pub const INVALID_NUMBER: &str = "E0003";
+    pub const UNTERMINATED_COMMENT: &str = "E0004";
+    pub const INVALID_ESCAPE: &str = "E0005";
+
+    // Parser
+    pub const EXPECTED_TOKEN: &str = "E0100";
+    pub const UNEXPECTED_TOKEN: &str = "E0101";
+    pub const EXPECTED_EXPRESSION: &str = "E0102";
+    pub const EXPECTED_IDENTIFIER: &str = "E0103";
+    pub const EXPECTED_FUNCTION: &str = "E0104";
+    pub const UNEXPECTED_EOF: &str = "E0105";
+
+    // Semantic analysis
+    pub const INVALID_NAME: &str = "E0200";
+    pub const REDECLARATION: &str = "E0201";
+    pub const UNDECLARED_VARIABLE: &str = "E0202";
+    pub const UNKNOWN_FUNCTION: &str = "E0203";
+    pub const TYPE_MISMATCH: &str = "E0204";
+    pub const ARGUMENT_COUNT: &str = "E0205";
+    pub const OUTSIDE_LOOP: &str = "E0206";
+    pub const CONSTANT_DIVISION_BY_ZERO: &str = "E0207";
+
+    // Runtime
+    pub const UNDEFINED_VARIABLE: &str = "E0500";
+    pub const UNDEFINED_FUNCTION: &str = "E0501";
+    pub const TYPE_ERROR: &str = "E0502";
+    pub const INDEX_OUT_OF_BOUNDS: &str = "E0503";
+    pub const DIVISION_BY_ZERO: &str = "E0504";
+    pub const INTEGER_OVERFLOW: &str = "E0505";
+    pub const ARITY_MISMATCH: &str = "E0506";
+    pub const OUTPUT_ERROR: &str = "E0507";
+    pub const CALL_DEPTH_EXCEEDED: &str = "E0508";
+
+    // Warnings
+    pub const UNUSED_RESULT: &str = "W0001";
+    pub const UNREACHABLE_CODE: &str = "W0002";
+    pub const UNUSED_VARIABLE: &str = "W0003";
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A secondary source range with its own short explanation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            span,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(code, message, span)
+        }
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Renders the diagnostic the way rustc does: a header, the location, and
+    /// every affected source line with the primary span underlined by `^` and
+    /// secondary labels by `-`.
+    pub fn render(&self, source: &str, file_name: &str) -> String {
+        let mut out = format!("{}\n", self);
+
+        let mut marks = vec![(self.span, '^', "")];
+        for label in &self.labels {
+            marks.push((label.span, '-', label.message.as_str()));
+        }
+        marks.sort_by_key(|(span, _, _)| (span.line, span.column));
+
+        let gutter = marks
+            .iter()
+            .map(|(span, _, _)| span.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let blank = " ".repeat(gutter);
+
+        out.push_str(&format!(
+            "{}--> {}:{}:{}\n",
+            blank, file_name, self.span.line, self.span.column
+        ));
+        out.push_str(&format!("{} |\n", blank));
+
+        let mut last_line = None;
+        for (span, marker, message) in &marks {
+            let line_text = match source.lines().nth(span.line.saturating_sub(1)) {
+                Some(text) => text,
+                None => continue,
+            };
+            if last_line != Some(span.line) {
+                out.push_str(&format!("{:>gutter$} | {}\n", span.line, line_text));
+                last_line = Some(span.line);
+            }
+
+            // Underline up to the end of the first line the span touches.
+            let line_start = source[..span.start.min(source.len())]
+                .rfind('\n')
+                .map_or(0, |i| i + 1);
+            let line_end = line_start + line_text.len();
+            let end = span.end.clamp(span.start, line_end);
+            let width = source
+                .get(span.start..end)
+                .map_or(0, |text| text.chars().count())
+                .max(1);
+
+            let mut underline = format!(
+                "{} | {}{}",
+                blank,
+                " ".repeat(span.column.saturating_sub(1)),
+                marker.to_string().repeat(width)
+            );
+            if !message.is_empty() {
+                underline.push(' ');
+                underline.push_str(message);
+            }
+            out.push_str(underline.trim_end());
+            out.push('\n');
+        }
+
+        if !self.notes.is_empty() {
+            out.push_str(&format!("{} |\n", blank));
+            for note in &self.notes {
+                out.push_str(&format!("{} = note: {}\n", blank, note));
+            }
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)
+    }
+}
+
+/// `noun` after "a" or "an", whichever it reads with.
+pub fn with_article(noun: impl fmt::Display) -> String {
+    let noun = noun.to_string();
+    let article = match noun.chars().next() {
+        Some('a' | 'e' | 'i' | 'o' | 'u') => "an",
+        _ => "a",
+    };
+    format!("{} {}", article, noun)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_helpers() {
+        assert_eq!(with_article("integer"), "an integer");
+        assert_eq!(with_article("[integer]"), "a [integer]");
+        assert_eq!(with_article("boolean"), "a boolean");
+    }
+
+    #[test]
+    fn test_display() {
+        let diagnostic = Diagnostic::error(codes::UNKNOWN_TOKEN, "Unknown token", Span::default());
+        assert_eq!(diagnostic.to_string(), "error[E0001]: Unknown token");
+    }
+
+    #[test]
+    fn test_render_primary_span() {
+        let source = "func main() {\n    return arr[1;\n}";
+        let diagnostic = Diagnostic::error(
+            codes::EXPECTED_TOKEN,
+            "Expected ']' after array index",
+            Span::new(30, 31, 2, 17),
+        )
+        .with_note("array indexes are closed with ']'");
+
+        let expected = "\
+error[E0100]: Expected ']' after array index
+ --> main.el:2:17
+  |
+2 |     return arr[1;
+  |                 ^
+  |
+  = note: array indexes are closed with ']'
+";
+        assert_eq!(diagnostic.render(source, "main.el"), expected);
+    }
+
+    #[test]
+    fn test_render_secondary_label() {
+        let source = "let x = 1;\nlet x = 2;";
+        let diagnostic = Diagnostic::error(
+            codes::REDECLARATION,
+            "Symbol 'x' already declared in this scope",
+            Span::new(15, 16, 2, 5),
+        )
+        .with_label(Span::new(4, 5, 1, 5), "first declared here");
+
+        let expected = "\
+error[E0201]: Symbol 'x' already declared in this scope
+ --> main.el:2:5
+  |
+1 | let x = 1;
+  |     - first declared here
+2 | let x = 2;
+  |     ^
+";
+        assert_eq!(diagnostic.render(source, "main.el"), expected);
+    }
+}
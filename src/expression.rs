@@ -1,12 +1,34 @@
-use crate::token::Token;
+use crate::{
+    diagnostic::{codes, Diagnostic},
+    span::{span_at, span_of, Span, Spanned},
+    symbol::{DataType, SymbolTable, SymbolType},
+    token::{token_at, TemplatePart, Token},
+};
+
+#[derive(Debug)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+    /// The type of the value, filled in by semantic analysis. `None` until
+    /// then, or when inference couldn't pin it down.
+    pub ty: Option<DataType>,
+}
+
+// Spans and types don't take part in comparisons, so parsed trees can be
+// checked against hand-built ones.
+impl PartialEq for Expression {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
 
 #[derive(Debug, PartialEq)]
-pub enum Expression {
+pub enum ExpressionKind {
     ArrayDec {
-        arr_expr: Vec<Token>,
+        arr_expr: Vec<Expression>,
     },
     AccessIndex {
-        ident: Token,
+        array: Box<Expression>,
         index: Box<Expression>,
     },
     GetLength {
@@ -18,7 +40,7 @@ pub enum Expression {
     },
     FunctionCall {
         identifier: Token,
-        args: Vec<Token>,
+        args: Vec<Expression>,
     },
     Token(Token),
     Binary {
@@ -31,48 +53,86 @@ pub enum Expression {
         operand: Box<Expression>,
     },
     Grouping(Box<Expression>),
+    // An interpolated string, `"text ${expr} text"`
+    Format {
+        parts: Vec<FormatPart>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FormatPart {
+    Text(String),
+    Expr(Expression),
 }
 
-fn parse_postfix(tokens: &[Token]) -> Result<(Expression, usize), String> {
+// Builds an expression whose span covers `tokens`.
+fn spanning(kind: ExpressionKind, tokens: &[Spanned<Token>]) -> Expression {
+    Expression {
+        kind,
+        span: span_of(tokens),
+        ty: None,
+    }
+}
+
+fn parse_postfix(tokens: &[Spanned<Token>]) -> Result<(Expression, usize), Diagnostic> {
     // first thing is the identifier
     let mut curr_index = 1;
 
     if curr_index < tokens.len() {
-        match tokens[curr_index] {
+        match tokens[curr_index].node {
             Token::LeftSquare => {
-                curr_index += 1; // Skip the '[' token
+                let mut expr =
+                    spanning(ExpressionKind::Token(tokens[0].node.clone()), &tokens[..1]);
+
+                // Each `[index]` indexes into the result of the previous one
+                while token_at(tokens, curr_index) == Some(&Token::LeftSquare) {
+                    curr_index += 1; // Skip the '[' token
+
+                    let (index_expr, consumed) = parse_logical_or(&tokens[curr_index..])?;
+                    curr_index += consumed;
+
+                    if curr_index >= tokens.len() || tokens[curr_index] != Token::RightSquare {
+                        return Err(Diagnostic::error(
+                            codes::EXPECTED_TOKEN,
+                            "Expected ']' after array index",
+                            span_at(tokens, curr_index),
+                        ));
+                    }
 
-                let (index_expr, consumed) = parse_logical_or(&tokens[curr_index..])?;
-                curr_index += consumed;
+                    curr_index += 1; // Skip the ']' token
 
-                if curr_index >= tokens.len() || tokens[curr_index] != Token::RightSquare {
-                    return Err("Expected ']' after array index".into());
+                    expr = spanning(
+                        ExpressionKind::AccessIndex {
+                            array: Box::new(expr),
+                            index: Box::new(index_expr),
+                        },
+                        &tokens[..curr_index],
+                    );
                 }
 
-                curr_index += 1; // Skip the ']' token
-
-                Ok((
-                    Expression::AccessIndex {
-                        ident: tokens[0].clone(),
-                        index: Box::new(index_expr),
-                    },
-                    curr_index,
-                ))
+                Ok((expr, curr_index))
             }
             Token::Dot => {
                 curr_index += 1; // Skip the '.' token
                 if curr_index >= tokens.len() {
-                    return Err("Expected property or method name after '.'".into());
+                    return Err(Diagnostic::error(
+                        codes::EXPECTED_IDENTIFIER,
+                        "Expected property or method name after '.'",
+                        span_at(tokens, curr_index),
+                    ));
                 }
 
-                match &tokens[curr_index] {
+                match &tokens[curr_index].node {
                     Token::Length => {
                         curr_index += 1;
 
                         Ok((
-                            Expression::GetLength {
-                                ident: tokens[0].clone(),
-                            },
+                            spanning(
+                                ExpressionKind::GetLength {
+                                    ident: tokens[0].node.clone(),
+                                },
+                                &tokens[..curr_index],
+                            ),
                             curr_index,
                         ))
                     }
@@ -80,7 +140,11 @@ fn parse_postfix(tokens: &[Token]) -> Result<(Expression, usize), String> {
                         curr_index += 1;
                         // Handle appending a value
                         if curr_index >= tokens.len() || tokens[curr_index] != Token::LeftParen {
-                            return Err("Expected '(' after 'append'".into());
+                            return Err(Diagnostic::error(
+                                codes::EXPECTED_TOKEN,
+                                "Expected '(' after 'append'",
+                                span_at(tokens, curr_index),
+                            ));
                         }
                         curr_index += 1; // skip the (
 
@@ -88,90 +152,142 @@ fn parse_postfix(tokens: &[Token]) -> Result<(Expression, usize), String> {
                         curr_index += consumed;
 
                         if curr_index >= tokens.len() || tokens[curr_index] != Token::RightParen {
-                            return Err("Expected ')' after 'append'".into());
+                            return Err(Diagnostic::error(
+                                codes::EXPECTED_TOKEN,
+                                "Expected ')' after 'append'",
+                                span_at(tokens, curr_index),
+                            ));
                         }
                         curr_index += 1; // skip the )
 
                         Ok((
-                            Expression::ArrayAppend {
-                                array: tokens[0].clone(),
-                                value: Box::new(value_expr),
-                            },
+                            spanning(
+                                ExpressionKind::ArrayAppend {
+                                    array: tokens[0].node.clone(),
+                                    value: Box::new(value_expr),
+                                },
+                                &tokens[..curr_index],
+                            ),
                             curr_index,
                         ))
                     }
-                    _ => return Err("Expected Length, Append, or identifier after '.'".into()),
+                    _ => Err(Diagnostic::error(
+                        codes::EXPECTED_IDENTIFIER,
+                        "Expected Length, Append, or identifier after '.'",
+                        span_at(tokens, curr_index),
+                    )),
                 }
             }
-            _ => return Err("Expected Length, Append, or identifier after '.'".into()),
+            _ => Err(Diagnostic::error(
+                codes::EXPECTED_IDENTIFIER,
+                "Expected Length, Append, or identifier after '.'",
+                span_at(tokens, curr_index),
+            )),
         }
     } else {
-        return Err("Expected end while parse".into());
-    }
-}
-fn parse_array_dec(tokens: &[Token]) -> Result<(Expression, usize), String> {
-    // since first token is a left square bracket
-    let mut index = 1;
-    let mut args = Vec::new();
-
-    while index < tokens.len() {
-        if tokens.get(index) == Some(&Token::SemiColon) {
-            return Err("Expected ']' in the array declaration".to_string());
-        } else if tokens.get(index) != Some(&Token::Comma) {
-            args.push(tokens[index].clone());
-        }
-        index += 1;
+        Err(Diagnostic::error(
+            codes::UNEXPECTED_EOF,
+            "Expected end while parse",
+            span_at(tokens, curr_index),
+        ))
     }
-
-    Ok((Expression::ArrayDec { arr_expr: args }, index))
 }
-fn parse_function_call(tokens: &[Token]) -> Result<(Expression, usize), String> {
-    // We know the first token is an identifier.
-    let identifier = tokens[0].clone();
-    // The next token must be a left parenthesis.
-    if tokens.len() < 2 || tokens[1] != Token::LeftParen {
-        return Err("Expected '(' after identifier for function call".to_string());
+/// Parses comma-separated expressions starting at `start` up to and including
+/// the `close` token. Returns the expressions and the index just past `close`.
+pub fn parse_expression_list(
+    tokens: &[Spanned<Token>],
+    start: usize,
+    close: Token,
+    missing_close: &str,
+) -> Result<(Vec<Expression>, usize), Diagnostic> {
+    let mut index = start;
+    let mut items = Vec::new();
+
+    // An empty list
+    if token_at(tokens, index) == Some(&close) {
+        return Ok((items, index + 1));
     }
-    let mut consumed = 2; // Consumed the identifier and LeftParen
-    let mut args = Vec::new();
 
-    // If the next token is a right parenthesis, it's a call with no arguments
-    if tokens.len() > consumed && tokens[consumed] == Token::RightParen {
-        consumed += 1;
-        return Ok((Expression::FunctionCall { identifier, args }, consumed));
-    }
-    // Otherwise, parse arguments separated by commas
     loop {
-        if consumed >= tokens.len() {
-            return Err("Expected ')' in function call".to_string());
-        }
-        args.push(tokens[consumed].clone());
-        consumed += 1;
-        if consumed >= tokens.len() {
-            return Err("Expected ')' in function call".to_string());
+        if index >= tokens.len() || tokens[index] == Token::SemiColon {
+            return Err(Diagnostic::error(
+                codes::EXPECTED_TOKEN,
+                missing_close,
+                span_at(tokens, index),
+            ));
         }
-        match tokens[consumed] {
-            Token::Comma => {
-                consumed += 1;
-            }
-            Token::RightParen => {
-                consumed += 1;
-                break;
+        let (item, consumed) = parse_logical_or(&tokens[index..])?;
+        items.push(item);
+        index += consumed;
+
+        match token_at(tokens, index) {
+            Some(Token::Comma) => index += 1,
+            Some(token) if *token == close => return Ok((items, index + 1)),
+            Some(Token::SemiColon) | None => {
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_TOKEN,
+                    missing_close,
+                    span_at(tokens, index),
+                ))
             }
-            _ => {
-                return Err(format!("Unexpected token in argument list"));
+            Some(_) => {
+                return Err(Diagnostic::error(
+                    codes::UNEXPECTED_TOKEN,
+                    format!("Unexpected token, expected ',' or '{}'", close),
+                    span_at(tokens, index),
+                ))
             }
         }
     }
+}
+
+fn parse_array_dec(tokens: &[Spanned<Token>]) -> Result<(Expression, usize), Diagnostic> {
+    // since first token is a left square bracket
+    let (arr_expr, consumed) = parse_expression_list(
+        tokens,
+        1,
+        Token::RightSquare,
+        "Expected ']' in the array declaration",
+    )?;
+    let expr = spanning(ExpressionKind::ArrayDec { arr_expr }, &tokens[..consumed]);
+    Ok((expr, consumed))
+}
 
-    Ok((Expression::FunctionCall { identifier, args }, consumed))
+fn parse_function_call(tokens: &[Spanned<Token>]) -> Result<(Expression, usize), Diagnostic> {
+    // We know the first token is an identifier.
+    let identifier = tokens[0].node.clone();
+    // The next token must be a left parenthesis.
+    if tokens.len() < 2 || tokens[1] != Token::LeftParen {
+        return Err(Diagnostic::error(
+            codes::EXPECTED_TOKEN,
+            "Expected '(' after identifier for function call",
+            span_at(tokens, 1),
+        ));
+    }
+    // Arguments start after the identifier and LeftParen
+    let (args, consumed) = parse_expression_list(
+        tokens,
+        2,
+        Token::RightParen,
+        "Expected ')' in function call",
+    )?;
+
+    let expr = spanning(
+        ExpressionKind::FunctionCall { identifier, args },
+        &tokens[..consumed],
+    );
+    Ok((expr, consumed))
 }
 
-fn parse_primary(tokens: &[Token]) -> Result<(Expression, usize), String> {
+fn parse_primary(tokens: &[Spanned<Token>]) -> Result<(Expression, usize), Diagnostic> {
     if tokens.is_empty() {
-        return Err("Unexpected end of input while parsing logical AND expression.".to_string());
+        return Err(Diagnostic::error(
+            codes::EXPECTED_EXPRESSION,
+            "Unexpected end of input while parsing logical AND expression.",
+            Span::default(),
+        ));
     }
-    match tokens.get(0) {
+    match token_at(tokens, 0) {
         //parse if there is a function call
         Some(Token::Identifier(_)) => {
             if tokens.len() > 1 && tokens[1] == Token::LeftParen {
@@ -181,42 +297,97 @@ fn parse_primary(tokens: &[Token]) -> Result<(Expression, usize), String> {
             {
                 parse_postfix(tokens)
             } else {
-                Ok((Expression::Token(tokens[0].clone()), 1))
+                Ok((
+                    spanning(ExpressionKind::Token(tokens[0].node.clone()), &tokens[..1]),
+                    1,
+                ))
             }
         }
         Some(Token::LeftSquare) => parse_array_dec(tokens),
-        Some(Token::Integer(_)) | Some(Token::String(_)) => {
-            Ok((Expression::Token(tokens[0].clone()), 1))
+        Some(Token::Integer(_))
+        | Some(Token::Float(_))
+        | Some(Token::Boolean(_))
+        | Some(Token::String(_)) => Ok((
+            spanning(ExpressionKind::Token(tokens[0].node.clone()), &tokens[..1]),
+            1,
+        )),
+        Some(Token::Template(parts)) => {
+            let mut format = Vec::with_capacity(parts.len());
+            for part in parts {
+                format.push(match part {
+                    TemplatePart::Text(text) => FormatPart::Text(text.clone()),
+                    TemplatePart::Code(code) if code.is_empty() => {
+                        return Err(Diagnostic::error(
+                            codes::EXPECTED_EXPRESSION,
+                            "Expected an expression inside '${}'",
+                            tokens[0].span,
+                        ))
+                    }
+                    TemplatePart::Code(code) => FormatPart::Expr(Expression::new(code)?.0),
+                });
+            }
+            Ok((
+                spanning(ExpressionKind::Format { parts: format }, &tokens[..1]),
+                1,
+            ))
         }
         Some(Token::LeftParen) => {
             // Parse an expression inside parentheses.
             let (expr, consumed) = parse_logical_or(&tokens[1..])?;
             if consumed + 1 >= tokens.len() || tokens[consumed + 1] != Token::RightParen {
-                return Err("Expected ')' after grouped expression".to_string());
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_TOKEN,
+                    "Expected ')' after grouped expression",
+                    span_at(tokens, consumed + 1),
+                ));
             }
-            Ok((Expression::Grouping(Box::new(expr)), consumed + 2))
+            Ok((
+                spanning(
+                    ExpressionKind::Grouping(Box::new(expr)),
+                    &tokens[..consumed + 2],
+                ),
+                consumed + 2,
+            ))
         }
-        _ => return Err("Unexpected end of input while parsing.".to_string()),
+        Some(token) => Err(Diagnostic::error(
+            codes::EXPECTED_EXPRESSION,
+            format!("Expected an expression, found '{}'", token),
+            span_at(tokens, 0),
+        )),
+        None => Err(Diagnostic::error(
+            codes::EXPECTED_EXPRESSION,
+            "Unexpected end of input while parsing.",
+            span_at(tokens, 0),
+        )),
     }
 }
 
-fn parse_unary(tokens: &[Token]) -> Result<(Expression, usize), String> {
+fn parse_unary(tokens: &[Spanned<Token>]) -> Result<(Expression, usize), Diagnostic> {
     if tokens.is_empty() {
-        return Err("Unexpected end of input while parsing logical unary expression.".to_string());
+        return Err(Diagnostic::error(
+            codes::EXPECTED_EXPRESSION,
+            "Unexpected end of input while parsing logical unary expression.",
+            Span::default(),
+        ));
     }
 
-    if tokens.get(0) == Some(&Token::Not) {
-        let operator = tokens[0].clone();
+    // Prefix operators bind tighter than any binary operator, so `-a * b` is
+    // `(-a) * b`.
+    if let Some(Token::Not | Token::Sub | Token::BitNot) = token_at(tokens, 0) {
+        let operator = tokens[0].node.clone();
         let (right, right_consumed) = match parse_unary(&tokens[1..]) {
             Ok((expr, consumed)) => (expr, consumed),
             Err(e) => return Err(e),
         };
 
         Ok((
-            Expression::Unary {
-                operator,
-                operand: Box::new(right),
-            },
+            spanning(
+                ExpressionKind::Unary {
+                    operator,
+                    operand: Box::new(right),
+                },
+                &tokens[..right_consumed + 1],
+            ),
             right_consumed + 1,
         ))
     } else {
@@ -224,11 +395,13 @@ fn parse_unary(tokens: &[Token]) -> Result<(Expression, usize), String> {
     }
 }
 
-fn parse_multiplicative(tokens: &[Token]) -> Result<(Expression, usize), String> {
+fn parse_multiplicative(tokens: &[Spanned<Token>]) -> Result<(Expression, usize), Diagnostic> {
     if tokens.is_empty() {
-        return Err(
-            "Unexpected end of input while parsing logical multiplicative expression.".to_string(),
-        );
+        return Err(Diagnostic::error(
+            codes::EXPECTED_EXPRESSION,
+            "Unexpected end of input while parsing logical multiplicative expression.",
+            Span::default(),
+        ));
     }
 
     let (mut left, mut consumed) = match parse_unary(tokens) {
@@ -237,23 +410,31 @@ fn parse_multiplicative(tokens: &[Token]) -> Result<(Expression, usize), String>
     };
 
     while consumed < tokens.len() {
-        if tokens.get(consumed) == Some(&Token::Div)
-            || tokens.get(consumed) == Some(&Token::Mul)
-            || tokens.get(consumed) == Some(&Token::Mod)
+        if token_at(tokens, consumed) == Some(&Token::Div)
+            || token_at(tokens, consumed) == Some(&Token::Mul)
+            || token_at(tokens, consumed) == Some(&Token::Mod)
         {
             if consumed + 1 >= tokens.len() {
-                return Err("Expected expression after operator.".to_string());
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_EXPRESSION,
+                    "Expected expression after operator.",
+                    span_at(tokens, consumed),
+                ));
             }
-            let operator = tokens[consumed].clone();
+            let operator = tokens[consumed].node.clone();
             let (right, right_consumed) = match parse_unary(&tokens[consumed + 1..]) {
                 Ok((expr, consumed)) => (expr, consumed),
                 Err(e) => return Err(e),
             };
 
-            left = Expression::Binary {
-                left: Box::new(left),
-                operator,
-                right: Box::new(right),
+            left = Expression {
+                span: left.span.to(right.span),
+                kind: ExpressionKind::Binary {
+                    left: Box::new(left),
+                    operator,
+                    right: Box::new(right),
+                },
+                ty: None,
             };
             consumed += right_consumed + 1;
         } else {
@@ -263,11 +444,13 @@ fn parse_multiplicative(tokens: &[Token]) -> Result<(Expression, usize), String>
     Ok((left, consumed))
 }
 
-fn parse_additive(tokens: &[Token]) -> Result<(Expression, usize), String> {
+fn parse_additive(tokens: &[Spanned<Token>]) -> Result<(Expression, usize), Diagnostic> {
     if tokens.is_empty() {
-        return Err(
-            "Unexpected end of input while parsing logical additive expression.".to_string(),
-        );
+        return Err(Diagnostic::error(
+            codes::EXPECTED_EXPRESSION,
+            "Unexpected end of input while parsing logical additive expression.",
+            Span::default(),
+        ));
     }
 
     let (mut left, mut consumed) = match parse_multiplicative(tokens) {
@@ -276,20 +459,30 @@ fn parse_additive(tokens: &[Token]) -> Result<(Expression, usize), String> {
     };
 
     while consumed < tokens.len() {
-        if tokens.get(consumed) == Some(&Token::Add) || tokens.get(consumed) == Some(&Token::Sub) {
+        if token_at(tokens, consumed) == Some(&Token::Add)
+            || token_at(tokens, consumed) == Some(&Token::Sub)
+        {
             if consumed + 1 >= tokens.len() {
-                return Err("Expected expression after operator.".to_string());
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_EXPRESSION,
+                    "Expected expression after operator.",
+                    span_at(tokens, consumed),
+                ));
             }
-            let operator = tokens[consumed].clone();
+            let operator = tokens[consumed].node.clone();
             let (right, right_consumed) = match parse_multiplicative(&tokens[consumed + 1..]) {
                 Ok((expr, consumed)) => (expr, consumed),
                 Err(e) => return Err(e),
             };
 
-            left = Expression::Binary {
-                left: Box::new(left),
-                operator,
-                right: Box::new(right),
+            left = Expression {
+                span: left.span.to(right.span),
+                kind: ExpressionKind::Binary {
+                    left: Box::new(left),
+                    operator,
+                    right: Box::new(right),
+                },
+                ty: None,
             };
             consumed += right_consumed + 1;
         } else {
@@ -299,11 +492,13 @@ fn parse_additive(tokens: &[Token]) -> Result<(Expression, usize), String> {
     Ok((left, consumed))
 }
 
-fn parse_relational(tokens: &[Token]) -> Result<(Expression, usize), String> {
+fn parse_shift(tokens: &[Spanned<Token>]) -> Result<(Expression, usize), Diagnostic> {
     if tokens.is_empty() {
-        return Err(
-            "Unexpected end of input while parsing logical relational expression.".to_string(),
-        );
+        return Err(Diagnostic::error(
+            codes::EXPECTED_EXPRESSION,
+            "Unexpected end of input while parsing shift expression.",
+            Span::default(),
+        ));
     }
 
     let (mut left, mut consumed) = match parse_additive(tokens) {
@@ -312,24 +507,30 @@ fn parse_relational(tokens: &[Token]) -> Result<(Expression, usize), String> {
     };
 
     while consumed < tokens.len() {
-        if tokens.get(consumed) == Some(&Token::Greater)
-            || tokens.get(consumed) == Some(&Token::GreaterEqual)
-            || tokens.get(consumed) == Some(&Token::Less)
-            || tokens.get(consumed) == Some(&Token::LessEqual)
+        if token_at(tokens, consumed) == Some(&Token::ShiftLeft)
+            || token_at(tokens, consumed) == Some(&Token::ShiftRight)
         {
             if consumed + 1 >= tokens.len() {
-                return Err("Expected expression after operator.".to_string());
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_EXPRESSION,
+                    "Expected expression after operator.",
+                    span_at(tokens, consumed),
+                ));
             }
-            let operator = tokens[consumed].clone();
+            let operator = tokens[consumed].node.clone();
             let (right, right_consumed) = match parse_additive(&tokens[consumed + 1..]) {
                 Ok((expr, consumed)) => (expr, consumed),
                 Err(e) => return Err(e),
             };
 
-            left = Expression::Binary {
-                left: Box::new(left),
-                operator,
-                right: Box::new(right),
+            left = Expression {
+                span: left.span.to(right.span),
+                kind: ExpressionKind::Binary {
+                    left: Box::new(left),
+                    operator,
+                    right: Box::new(right),
+                },
+                ty: None,
             };
             consumed += right_consumed + 1;
         } else {
@@ -338,11 +539,63 @@ fn parse_relational(tokens: &[Token]) -> Result<(Expression, usize), String> {
     }
     Ok((left, consumed))
 }
-fn parse_logical_equality(tokens: &[Token]) -> Result<(Expression, usize), String> {
+
+fn parse_relational(tokens: &[Spanned<Token>]) -> Result<(Expression, usize), Diagnostic> {
     if tokens.is_empty() {
-        return Err(
-            "Unexpected end of input while parsing logical equality expression.".to_string(),
-        );
+        return Err(Diagnostic::error(
+            codes::EXPECTED_EXPRESSION,
+            "Unexpected end of input while parsing logical relational expression.",
+            Span::default(),
+        ));
+    }
+
+    let (mut left, mut consumed) = match parse_shift(tokens) {
+        Ok((expr, consumed)) => (expr, consumed),
+        Err(e) => return Err(e),
+    };
+
+    while consumed < tokens.len() {
+        if token_at(tokens, consumed) == Some(&Token::Greater)
+            || token_at(tokens, consumed) == Some(&Token::GreaterEqual)
+            || token_at(tokens, consumed) == Some(&Token::Less)
+            || token_at(tokens, consumed) == Some(&Token::LessEqual)
+        {
+            if consumed + 1 >= tokens.len() {
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_EXPRESSION,
+                    "Expected expression after operator.",
+                    span_at(tokens, consumed),
+                ));
+            }
+            let operator = tokens[consumed].node.clone();
+            let (right, right_consumed) = match parse_shift(&tokens[consumed + 1..]) {
+                Ok((expr, consumed)) => (expr, consumed),
+                Err(e) => return Err(e),
+            };
+
+            left = Expression {
+                span: left.span.to(right.span),
+                kind: ExpressionKind::Binary {
+                    left: Box::new(left),
+                    operator,
+                    right: Box::new(right),
+                },
+                ty: None,
+            };
+            consumed += right_consumed + 1;
+        } else {
+            break;
+        }
+    }
+    Ok((left, consumed))
+}
+fn parse_logical_equality(tokens: &[Spanned<Token>]) -> Result<(Expression, usize), Diagnostic> {
+    if tokens.is_empty() {
+        return Err(Diagnostic::error(
+            codes::EXPECTED_EXPRESSION,
+            "Unexpected end of input while parsing logical equality expression.",
+            Span::default(),
+        ));
     }
 
     let (mut left, mut consumed) = match parse_relational(tokens) {
@@ -351,22 +604,30 @@ fn parse_logical_equality(tokens: &[Token]) -> Result<(Expression, usize), Strin
     };
 
     while consumed < tokens.len() {
-        if tokens.get(consumed) == Some(&Token::EqualEqual)
-            || tokens.get(consumed) == Some(&Token::NotEqual)
+        if token_at(tokens, consumed) == Some(&Token::EqualEqual)
+            || token_at(tokens, consumed) == Some(&Token::NotEqual)
         {
             if consumed + 1 >= tokens.len() {
-                return Err("Expected expression after operator.".to_string());
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_EXPRESSION,
+                    "Expected expression after operator.",
+                    span_at(tokens, consumed),
+                ));
             }
-            let operator = tokens[consumed].clone();
+            let operator = tokens[consumed].node.clone();
             let (right, right_consumed) = match parse_relational(&tokens[consumed + 1..]) {
                 Ok((expr, consumed)) => (expr, consumed),
                 Err(e) => return Err(e),
             };
 
-            left = Expression::Binary {
-                left: Box::new(left),
-                operator,
-                right: Box::new(right),
+            left = Expression {
+                span: left.span.to(right.span),
+                kind: ExpressionKind::Binary {
+                    left: Box::new(left),
+                    operator,
+                    right: Box::new(right),
+                },
+                ty: None,
             };
             consumed += right_consumed + 1;
         } else {
@@ -375,9 +636,13 @@ fn parse_logical_equality(tokens: &[Token]) -> Result<(Expression, usize), Strin
     }
     Ok((left, consumed))
 }
-fn parse_logical_and(tokens: &[Token]) -> Result<(Expression, usize), String> {
+fn parse_bitwise_and(tokens: &[Spanned<Token>]) -> Result<(Expression, usize), Diagnostic> {
     if tokens.is_empty() {
-        return Err("Unexpected end of input while parsing logical AND expression.".to_string());
+        return Err(Diagnostic::error(
+            codes::EXPECTED_EXPRESSION,
+            "Unexpected end of input while parsing bitwise AND expression.",
+            Span::default(),
+        ));
     }
 
     let (mut left, mut consumed) = match parse_logical_equality(tokens) {
@@ -386,20 +651,28 @@ fn parse_logical_and(tokens: &[Token]) -> Result<(Expression, usize), String> {
     };
 
     while consumed < tokens.len() {
-        if tokens.get(consumed) == Some(&Token::And) {
+        if token_at(tokens, consumed) == Some(&Token::BitAnd) {
             if consumed + 1 >= tokens.len() {
-                return Err("Expected expression after operator.".to_string());
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_EXPRESSION,
+                    "Expected expression after operator.",
+                    span_at(tokens, consumed),
+                ));
             }
-            let operator = tokens[consumed].clone();
+            let operator = tokens[consumed].node.clone();
             let (right, right_consumed) = match parse_logical_equality(&tokens[consumed + 1..]) {
                 Ok((expr, consumed)) => (expr, consumed),
                 Err(e) => return Err(e),
             };
 
-            left = Expression::Binary {
-                left: Box::new(left),
-                operator,
-                right: Box::new(right),
+            left = Expression {
+                span: left.span.to(right.span),
+                kind: ExpressionKind::Binary {
+                    left: Box::new(left),
+                    operator,
+                    right: Box::new(right),
+                },
+                ty: None,
             };
             consumed += right_consumed + 1;
         } else {
@@ -408,9 +681,151 @@ fn parse_logical_and(tokens: &[Token]) -> Result<(Expression, usize), String> {
     }
     Ok((left, consumed))
 }
-fn parse_logical_or(tokens: &[Token]) -> Result<(Expression, usize), String> {
+
+fn parse_bitwise_xor(tokens: &[Spanned<Token>]) -> Result<(Expression, usize), Diagnostic> {
     if tokens.is_empty() {
-        return Err("Unexpected end of input while parsing logical OR expression.".to_string());
+        return Err(Diagnostic::error(
+            codes::EXPECTED_EXPRESSION,
+            "Unexpected end of input while parsing bitwise XOR expression.",
+            Span::default(),
+        ));
+    }
+
+    let (mut left, mut consumed) = match parse_bitwise_and(tokens) {
+        Ok((expr, consumed)) => (expr, consumed),
+        Err(e) => return Err(e),
+    };
+
+    while consumed < tokens.len() {
+        if token_at(tokens, consumed) == Some(&Token::BitXor) {
+            if consumed + 1 >= tokens.len() {
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_EXPRESSION,
+                    "Expected expression after operator.",
+                    span_at(tokens, consumed),
+                ));
+            }
+            let operator = tokens[consumed].node.clone();
+            let (right, right_consumed) = match parse_bitwise_and(&tokens[consumed + 1..]) {
+                Ok((expr, consumed)) => (expr, consumed),
+                Err(e) => return Err(e),
+            };
+
+            left = Expression {
+                span: left.span.to(right.span),
+                kind: ExpressionKind::Binary {
+                    left: Box::new(left),
+                    operator,
+                    right: Box::new(right),
+                },
+                ty: None,
+            };
+            consumed += right_consumed + 1;
+        } else {
+            break;
+        }
+    }
+    Ok((left, consumed))
+}
+
+fn parse_bitwise_or(tokens: &[Spanned<Token>]) -> Result<(Expression, usize), Diagnostic> {
+    if tokens.is_empty() {
+        return Err(Diagnostic::error(
+            codes::EXPECTED_EXPRESSION,
+            "Unexpected end of input while parsing bitwise OR expression.",
+            Span::default(),
+        ));
+    }
+
+    let (mut left, mut consumed) = match parse_bitwise_xor(tokens) {
+        Ok((expr, consumed)) => (expr, consumed),
+        Err(e) => return Err(e),
+    };
+
+    while consumed < tokens.len() {
+        if token_at(tokens, consumed) == Some(&Token::BitOr) {
+            if consumed + 1 >= tokens.len() {
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_EXPRESSION,
+                    "Expected expression after operator.",
+                    span_at(tokens, consumed),
+                ));
+            }
+            let operator = tokens[consumed].node.clone();
+            let (right, right_consumed) = match parse_bitwise_xor(&tokens[consumed + 1..]) {
+                Ok((expr, consumed)) => (expr, consumed),
+                Err(e) => return Err(e),
+            };
+
+            left = Expression {
+                span: left.span.to(right.span),
+                kind: ExpressionKind::Binary {
+                    left: Box::new(left),
+                    operator,
+                    right: Box::new(right),
+                },
+                ty: None,
+            };
+            consumed += right_consumed + 1;
+        } else {
+            break;
+        }
+    }
+    Ok((left, consumed))
+}
+
+fn parse_logical_and(tokens: &[Spanned<Token>]) -> Result<(Expression, usize), Diagnostic> {
+    if tokens.is_empty() {
+        return Err(Diagnostic::error(
+            codes::EXPECTED_EXPRESSION,
+            "Unexpected end of input while parsing logical AND expression.",
+            Span::default(),
+        ));
+    }
+
+    let (mut left, mut consumed) = match parse_bitwise_or(tokens) {
+        Ok((expr, consumed)) => (expr, consumed),
+        Err(e) => return Err(e),
+    };
+
+    while consumed < tokens.len() {
+        if token_at(tokens, consumed) == Some(&Token::And) {
+            if consumed + 1 >= tokens.len() {
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_EXPRESSION,
+                    "Expected expression after operator.",
+                    span_at(tokens, consumed),
+                ));
+            }
+            let operator = tokens[consumed].node.clone();
+            let (right, right_consumed) = match parse_bitwise_or(&tokens[consumed + 1..]) {
+                Ok((expr, consumed)) => (expr, consumed),
+                Err(e) => return Err(e),
+            };
+
+            left = Expression {
+                span: left.span.to(right.span),
+                kind: ExpressionKind::Binary {
+                    left: Box::new(left),
+                    operator,
+                    right: Box::new(right),
+                },
+                ty: None,
+            };
+            consumed += right_consumed + 1;
+        } else {
+            break;
+        }
+    }
+    Ok((left, consumed))
+}
+fn parse_logical_or(tokens: &[Spanned<Token>]) -> Result<(Expression, usize), Diagnostic> {
+    if tokens.is_empty() {
+        return Err(Diagnostic::error(
+            codes::EXPECTED_EXPRESSION,
+            "Unexpected end of input while parsing logical OR expression.",
+            Span::default(),
+        ));
     }
 
     let (mut left, mut consumed) = match parse_logical_and(tokens) {
@@ -419,21 +834,29 @@ fn parse_logical_or(tokens: &[Token]) -> Result<(Expression, usize), String> {
     };
 
     while consumed < tokens.len() {
-        if tokens.get(consumed) == Some(&Token::Or) {
+        if token_at(tokens, consumed) == Some(&Token::Or) {
             if consumed + 1 >= tokens.len() {
-                return Err("Expected expression after operator".to_string());
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_EXPRESSION,
+                    "Expected expression after operator",
+                    span_at(tokens, consumed),
+                ));
             }
-            let operator = tokens[consumed].clone();
+            let operator = tokens[consumed].node.clone();
 
             let (right, right_consumed) = match parse_logical_and(&tokens[consumed + 1..]) {
                 Ok((expr, consumed)) => (expr, consumed),
                 Err(e) => return Err(e),
             };
 
-            left = Expression::Binary {
-                left: Box::new(left),
-                operator,
-                right: Box::new(right),
+            left = Expression {
+                span: left.span.to(right.span),
+                kind: ExpressionKind::Binary {
+                    left: Box::new(left),
+                    operator,
+                    right: Box::new(right),
+                },
+                ty: None,
             };
             consumed += right_consumed + 1;
         } else {
@@ -444,9 +867,13 @@ fn parse_logical_or(tokens: &[Token]) -> Result<(Expression, usize), String> {
 }
 
 impl Expression {
-    pub fn new(tokens: &[Token]) -> Result<(Self, usize), String> {
+    pub fn new(tokens: &[Spanned<Token>]) -> Result<(Self, usize), Diagnostic> {
         if tokens.is_empty() {
-            return Err("Expected an expression".into());
+            return Err(Diagnostic::error(
+                codes::EXPECTED_EXPRESSION,
+                "Expected an expression",
+                Span::default(),
+            ));
         }
 
         // Start at the lowest precedence level: logical OR.
@@ -456,66 +883,197 @@ impl Expression {
         };
 
         if consumed != tokens.len() {
-            return Err(format!("Unexpected token: {:?}", tokens[consumed]));
+            return Err(Diagnostic::error(
+                codes::UNEXPECTED_TOKEN,
+                format!("Unexpected token '{}'", tokens[consumed].node),
+                tokens[consumed].span,
+            ));
         }
 
         Ok((expr, consumed))
     }
 
-    pub fn resolve_symbols(&self) -> Result<bool, String> {
-        match self {
-            Expression::ArrayDec { arr_expr } => {}
-            Expression::FunctionCall { identifier, args } => todo!(),
-            Expression::Token(token) => todo!(),
-            Expression::Binary {
-                left,
-                operator,
-                right,
-            } => todo!(),
-            Expression::Unary { operator, operand } => todo!(),
-            Expression::Grouping(expression) => todo!(),
-            Expression::AccessIndex { ident, index } => todo!(),
-            Expression::GetLength { ident } => todo!(),
-            Expression::ArrayAppend { array, value } => todo!(),
+    /// Splits an assignable expression into the variable it starts from and the
+    /// chain of index accesses applied to it, innermost first. `grid[i][j]`
+    /// gives `grid` with the accesses `grid[i]` and `grid[i][j]`. Returns
+    /// `None` for anything that can't be assigned to.
+    pub fn place(&self) -> Option<(&Token, Vec<&Expression>)> {
+        match &self.kind {
+            ExpressionKind::Token(token @ Token::Identifier(_)) => Some((token, Vec::new())),
+            ExpressionKind::AccessIndex { array, .. } => {
+                let (root, mut accesses) = array.place()?;
+                accesses.push(self);
+                Some((root, accesses))
+            }
+            _ => None,
         }
+    }
 
-        todo!()
+    /// Whether evaluating the expression can change anything, which is only
+    /// true if it calls a function or appends to an array somewhere.
+    pub fn has_side_effects(&self) -> bool {
+        match &self.kind {
+            ExpressionKind::FunctionCall { .. } | ExpressionKind::ArrayAppend { .. } => true,
+            ExpressionKind::ArrayDec { arr_expr } => {
+                arr_expr.iter().any(Expression::has_side_effects)
+            }
+            ExpressionKind::AccessIndex { array, index } => {
+                array.has_side_effects() || index.has_side_effects()
+            }
+            ExpressionKind::Binary { left, right, .. } => {
+                left.has_side_effects() || right.has_side_effects()
+            }
+            ExpressionKind::Unary { operand, .. } => operand.has_side_effects(),
+            ExpressionKind::Grouping(inner) => inner.has_side_effects(),
+            ExpressionKind::Format { parts } => parts.iter().any(|part| match part {
+                FormatPart::Expr(expr) => expr.has_side_effects(),
+                FormatPart::Text(_) => false,
+            }),
+            ExpressionKind::GetLength { .. } | ExpressionKind::Token(_) => false,
+        }
+    }
+
+    /// Checks that every variable and function this expression refers to is
+    /// declared in `symbols`, pushing a diagnostic for each one that isn't.
+    pub fn resolve_symbols(&self, symbols: &SymbolTable, errors: &mut Vec<Diagnostic>) {
+        match &self.kind {
+            ExpressionKind::ArrayDec { arr_expr } => {
+                for element in arr_expr {
+                    element.resolve_symbols(symbols, errors);
+                }
+            }
+            ExpressionKind::FunctionCall { identifier, args } => {
+                if let Token::Identifier(name) = identifier {
+                    let is_function = matches!(
+                        symbols.lookup_global(name),
+                        Some(symbol) if symbol.symbol_type == SymbolType::Function
+                    );
+                    if !is_function {
+                        errors.push(Diagnostic::error(
+                            codes::UNKNOWN_FUNCTION,
+                            format!("Call to unknown function '{}'", name),
+                            self.span,
+                        ));
+                    }
+                }
+                for arg in args {
+                    arg.resolve_symbols(symbols, errors);
+                }
+            }
+            ExpressionKind::Token(token) => resolve_variable(token, self.span, symbols, errors),
+            ExpressionKind::Binary { left, right, .. } => {
+                left.resolve_symbols(symbols, errors);
+                right.resolve_symbols(symbols, errors);
+            }
+            ExpressionKind::Unary { operand, .. } => operand.resolve_symbols(symbols, errors),
+            ExpressionKind::Grouping(inner) => inner.resolve_symbols(symbols, errors),
+            ExpressionKind::Format { parts } => {
+                for part in parts {
+                    if let FormatPart::Expr(expr) = part {
+                        expr.resolve_symbols(symbols, errors);
+                    }
+                }
+            }
+            ExpressionKind::AccessIndex { array, index } => {
+                array.resolve_symbols(symbols, errors);
+                index.resolve_symbols(symbols, errors);
+            }
+            ExpressionKind::GetLength { ident } => {
+                resolve_variable(ident, self.span, symbols, errors)
+            }
+            ExpressionKind::ArrayAppend { array, value } => {
+                resolve_variable(array, self.span, symbols, errors);
+                value.resolve_symbols(symbols, errors);
+            }
+        }
+    }
+}
+
+// Reports `token` if it names a variable that isn't in scope. Literals and
+// other non-identifier tokens always resolve.
+fn resolve_variable(
+    token: &Token,
+    span: Span,
+    symbols: &SymbolTable,
+    errors: &mut Vec<Diagnostic>,
+) {
+    if let Token::Identifier(name) = token {
+        let is_variable = matches!(
+            symbols.lookup(name),
+            Some(symbol) if symbol.symbol_type == SymbolType::Variable
+        );
+        if !is_variable {
+            errors.push(Diagnostic::error(
+                codes::UNDECLARED_VARIABLE,
+                format!("Use of undeclared variable '{}'", name),
+                span,
+            ));
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::token::Token;
+    use crate::span::spanned;
+    use crate::token::{TemplatePart, Token};
+
+    fn expr(kind: ExpressionKind) -> Expression {
+        Expression {
+            kind,
+            span: Span::default(),
+            ty: None,
+        }
+    }
 
     // ----- Primary Expression Tests -----
     #[test]
     fn test_parse_primary_identifier() {
-        let tokens = vec![Token::Identifier("x".to_string())];
-        let expected = Expression::Token(Token::Identifier("x".to_string()));
+        let tokens = spanned(vec![Token::Identifier("x".to_string())]);
+        let expected = expr(ExpressionKind::Token(Token::Identifier("x".to_string())));
         assert_eq!(parse_primary(&tokens), Ok((expected, 1)));
     }
 
     #[test]
     fn test_parse_primary_number() {
-        let tokens = vec![Token::Integer(42)];
-        let expected = Expression::Token(Token::Integer(42));
+        let tokens = spanned(vec![Token::Integer(42)]);
+        let expected = expr(ExpressionKind::Token(Token::Integer(42)));
         assert_eq!(parse_primary(&tokens), Ok((expected, 1)));
     }
 
+    #[test]
+    fn test_parse_primary_literals() {
+        for token in [
+            Token::Float(2.5),
+            Token::Boolean(true),
+            Token::String("hi".to_string()),
+        ] {
+            let tokens = spanned(vec![token.clone()]);
+            let expected = expr(ExpressionKind::Token(token));
+            assert_eq!(parse_primary(&tokens), Ok((expected, 1)));
+        }
+    }
+
+    #[test]
+    fn test_parse_primary_not_an_expression() {
+        let tokens = spanned(vec![Token::Comma]);
+        let err = parse_primary(&tokens).unwrap_err();
+        assert_eq!(err.message, "Expected an expression, found ','");
+    }
+
     #[test]
     fn test_parse_primary_grouping() {
         // Test a grouped expression: ( x ).
         // In this simple case, that returns the identifier and consumes 1 token.
         // Then the grouped expression consumes 1 (LeftParen) + 1 (inner expr) + 1 (RightParen) = 3 tokens.
 
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::LeftParen,
             Token::Identifier("x".to_string()),
             Token::RightParen,
-        ];
-        let expected = Expression::Grouping(Box::new(Expression::Token(Token::Identifier(
-            "x".to_string(),
+        ]);
+        let expected = expr(ExpressionKind::Grouping(Box::new(expr(
+            ExpressionKind::Token(Token::Identifier("x".to_string())),
         ))));
 
         assert_eq!(parse_primary(&tokens), Ok((expected, 3)));
@@ -524,57 +1082,109 @@ mod tests {
     // ----- Unary Expression Tests -----
     #[test]
     fn test_parse_unary_not() {
-        let tokens = vec![Token::Not, Token::Identifier("x".to_string())];
-        let expected = Expression::Unary {
+        let tokens = spanned(vec![Token::Not, Token::Identifier("x".to_string())]);
+        let expected = expr(ExpressionKind::Unary {
             operator: Token::Not,
-            operand: Box::new(Expression::Token(Token::Identifier("x".to_string()))),
-        };
+            operand: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "x".to_string(),
+            )))),
+        });
         assert_eq!(parse_unary(&tokens), Ok((expected, 2)));
     }
 
+    #[test]
+    fn test_parse_unary_negation_precedence() {
+        // -a * b + -c parses as ((-a) * b) + (-c)
+        let (tokens, _) = crate::run_lexer("-a * b + -c").unwrap();
+        let ident = |name: &str| expr(ExpressionKind::Token(Token::Identifier(name.to_string())));
+        let negate = |operand| {
+            expr(ExpressionKind::Unary {
+                operator: Token::Sub,
+                operand: Box::new(operand),
+            })
+        };
+        let expected = expr(ExpressionKind::Binary {
+            left: Box::new(expr(ExpressionKind::Binary {
+                left: Box::new(negate(ident("a"))),
+                operator: Token::Mul,
+                right: Box::new(ident("b")),
+            })),
+            operator: Token::Add,
+            right: Box::new(negate(ident("c"))),
+        });
+        assert_eq!(Expression::new(&tokens), Ok((expected, 7)));
+    }
+
+    #[test]
+    fn test_parse_subtract_negative() {
+        // 1 - -2 is a subtraction whose right side is a negation
+        let (tokens, _) = crate::run_lexer("1 - -2").unwrap();
+        let expected = expr(ExpressionKind::Binary {
+            left: Box::new(expr(ExpressionKind::Token(Token::Integer(1)))),
+            operator: Token::Sub,
+            right: Box::new(expr(ExpressionKind::Unary {
+                operator: Token::Sub,
+                operand: Box::new(expr(ExpressionKind::Token(Token::Integer(2)))),
+            })),
+        });
+        assert_eq!(Expression::new(&tokens), Ok((expected, 4)));
+    }
+
     // ----- Multiplicative Expression Tests -----
     #[test]
     fn test_parse_multiplicative_mul() {
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::Identifier("x".to_string()),
             Token::Mul,
             Token::Identifier("y".to_string()),
-        ];
-        let expected = Expression::Binary {
-            left: Box::new(Expression::Token(Token::Identifier("x".to_string()))),
+        ]);
+        let expected = expr(ExpressionKind::Binary {
+            left: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "x".to_string(),
+            )))),
             operator: Token::Mul,
-            right: Box::new(Expression::Token(Token::Identifier("y".to_string()))),
-        };
+            right: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "y".to_string(),
+            )))),
+        });
         assert_eq!(parse_multiplicative(&tokens), Ok((expected, 3)));
     }
 
     #[test]
     fn test_parse_multiplicative_div() {
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::Identifier("x".to_string()),
             Token::Div,
             Token::Identifier("y".to_string()),
-        ];
-        let expected = Expression::Binary {
-            left: Box::new(Expression::Token(Token::Identifier("x".to_string()))),
+        ]);
+        let expected = expr(ExpressionKind::Binary {
+            left: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "x".to_string(),
+            )))),
             operator: Token::Div,
-            right: Box::new(Expression::Token(Token::Identifier("y".to_string()))),
-        };
+            right: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "y".to_string(),
+            )))),
+        });
         assert_eq!(parse_multiplicative(&tokens), Ok((expected, 3)));
     }
 
     #[test]
     fn test_parse_multiplicative_mod() {
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::Identifier("x".to_string()),
             Token::Mod,
             Token::Identifier("y".to_string()),
-        ];
-        let expected = Expression::Binary {
-            left: Box::new(Expression::Token(Token::Identifier("x".to_string()))),
+        ]);
+        let expected = expr(ExpressionKind::Binary {
+            left: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "x".to_string(),
+            )))),
             operator: Token::Mod,
-            right: Box::new(Expression::Token(Token::Identifier("y".to_string()))),
-        };
+            right: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "y".to_string(),
+            )))),
+        });
         assert_eq!(parse_multiplicative(&tokens), Ok((expected, 3)));
     }
 
@@ -582,23 +1192,29 @@ mod tests {
     #[test]
     fn test_parse_additive_chained() {
         // Test: x + y - z, which should be parsed as ((x + y) - z)
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::Identifier("x".to_string()),
             Token::Add,
             Token::Identifier("y".to_string()),
             Token::Sub,
             Token::Identifier("z".to_string()),
-        ];
-        let intermediate = Expression::Binary {
-            left: Box::new(Expression::Token(Token::Identifier("x".to_string()))),
+        ]);
+        let intermediate = expr(ExpressionKind::Binary {
+            left: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "x".to_string(),
+            )))),
             operator: Token::Add,
-            right: Box::new(Expression::Token(Token::Identifier("y".to_string()))),
-        };
-        let expected = Expression::Binary {
+            right: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "y".to_string(),
+            )))),
+        });
+        let expected = expr(ExpressionKind::Binary {
             left: Box::new(intermediate),
             operator: Token::Sub,
-            right: Box::new(Expression::Token(Token::Identifier("z".to_string()))),
-        };
+            right: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "z".to_string(),
+            )))),
+        });
         assert_eq!(parse_additive(&tokens), Ok((expected, 5)));
     }
 
@@ -606,16 +1222,20 @@ mod tests {
     #[test]
     fn test_parse_relational() {
         // Test: x < y
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::Identifier("x".to_string()),
             Token::Less,
             Token::Identifier("y".to_string()),
-        ];
-        let expected = Expression::Binary {
-            left: Box::new(Expression::Token(Token::Identifier("x".to_string()))),
+        ]);
+        let expected = expr(ExpressionKind::Binary {
+            left: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "x".to_string(),
+            )))),
             operator: Token::Less,
-            right: Box::new(Expression::Token(Token::Identifier("y".to_string()))),
-        };
+            right: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "y".to_string(),
+            )))),
+        });
         assert_eq!(parse_relational(&tokens), Ok((expected, 3)));
     }
 
@@ -623,23 +1243,29 @@ mod tests {
     #[test]
     fn test_parse_logical_equality() {
         // Test: x == y != z  which should be parsed as ((x == y) != z)
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::Identifier("x".to_string()),
             Token::EqualEqual,
             Token::Identifier("y".to_string()),
             Token::NotEqual,
             Token::Identifier("z".to_string()),
-        ];
-        let left = Expression::Binary {
-            left: Box::new(Expression::Token(Token::Identifier("x".to_string()))),
+        ]);
+        let left = expr(ExpressionKind::Binary {
+            left: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "x".to_string(),
+            )))),
             operator: Token::EqualEqual,
-            right: Box::new(Expression::Token(Token::Identifier("y".to_string()))),
-        };
-        let expected = Expression::Binary {
+            right: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "y".to_string(),
+            )))),
+        });
+        let expected = expr(ExpressionKind::Binary {
             left: Box::new(left),
             operator: Token::NotEqual,
-            right: Box::new(Expression::Token(Token::Identifier("z".to_string()))),
-        };
+            right: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "z".to_string(),
+            )))),
+        });
         assert_eq!(parse_logical_equality(&tokens), Ok((expected, 5)));
     }
 
@@ -647,23 +1273,29 @@ mod tests {
     #[test]
     fn test_parse_logical_and() {
         // Test: x && y && z should be parsed as ((x && y) && z)
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::Identifier("x".to_string()),
             Token::And,
             Token::Identifier("y".to_string()),
             Token::And,
             Token::Identifier("z".to_string()),
-        ];
-        let left = Expression::Binary {
-            left: Box::new(Expression::Token(Token::Identifier("x".to_string()))),
+        ]);
+        let left = expr(ExpressionKind::Binary {
+            left: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "x".to_string(),
+            )))),
             operator: Token::And,
-            right: Box::new(Expression::Token(Token::Identifier("y".to_string()))),
-        };
-        let expected = Expression::Binary {
+            right: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "y".to_string(),
+            )))),
+        });
+        let expected = expr(ExpressionKind::Binary {
             left: Box::new(left),
             operator: Token::And,
-            right: Box::new(Expression::Token(Token::Identifier("z".to_string()))),
-        };
+            right: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "z".to_string(),
+            )))),
+        });
         assert_eq!(parse_logical_and(&tokens), Ok((expected, 5)));
     }
 
@@ -671,23 +1303,29 @@ mod tests {
     #[test]
     fn test_parse_logical_or() {
         // Test: x || y || z should be parsed as ((x || y) || z)
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::Identifier("x".to_string()),
             Token::Or,
             Token::Identifier("y".to_string()),
             Token::Or,
             Token::Identifier("z".to_string()),
-        ];
-        let left = Expression::Binary {
-            left: Box::new(Expression::Token(Token::Identifier("x".to_string()))),
+        ]);
+        let left = expr(ExpressionKind::Binary {
+            left: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "x".to_string(),
+            )))),
             operator: Token::Or,
-            right: Box::new(Expression::Token(Token::Identifier("y".to_string()))),
-        };
-        let expected = Expression::Binary {
+            right: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "y".to_string(),
+            )))),
+        });
+        let expected = expr(ExpressionKind::Binary {
             left: Box::new(left),
             operator: Token::Or,
-            right: Box::new(Expression::Token(Token::Identifier("z".to_string()))),
-        };
+            right: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "z".to_string(),
+            )))),
+        });
         assert_eq!(parse_logical_or(&tokens), Ok((expected, 5)));
     }
 
@@ -695,86 +1333,108 @@ mod tests {
     #[test]
     fn test_expression_new_complete() {
         // expression: x + y * z
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::Identifier("x".to_string()),
             Token::Add,
             Token::Identifier("y".to_string()),
             Token::Mul,
             Token::Identifier("z".to_string()),
-        ];
-        let mul = Expression::Binary {
-            left: Box::new(Expression::Token(Token::Identifier("y".to_string()))),
+        ]);
+        let mul = expr(ExpressionKind::Binary {
+            left: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "y".to_string(),
+            )))),
             operator: Token::Mul,
-            right: Box::new(Expression::Token(Token::Identifier("z".to_string()))),
-        };
-        let expected = Expression::Binary {
-            left: Box::new(Expression::Token(Token::Identifier("x".to_string()))),
+            right: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "z".to_string(),
+            )))),
+        });
+        let expected = expr(ExpressionKind::Binary {
+            left: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                "x".to_string(),
+            )))),
             operator: Token::Add,
             right: Box::new(mul),
-        };
+        });
         assert_eq!(Expression::new(&tokens), Ok((expected, tokens.len())));
     }
 
     #[test]
     fn test_expression_new_extra_tokens() {
         // Test error when extra tokens remain: "x y"
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::Identifier("x".to_string()),
             Token::Identifier("y".to_string()),
-        ];
+        ]);
         let result = Expression::new(&tokens);
         assert!(result.is_err());
         if let Err(msg) = result {
-            assert!(msg.contains("Unexpected token"));
+            assert!(msg.message.contains("Unexpected token"));
         }
     }
 
+    #[test]
+    fn test_expression_new_extra_template() {
+        // A stray template is shown as written, not as its parts
+        let tokens = spanned(vec![
+            Token::Identifier("x".to_string()),
+            Token::Template(vec![
+                TemplatePart::Text("n = ".to_string()),
+                TemplatePart::Code(spanned(vec![Token::Identifier("n".to_string())])),
+            ]),
+        ]);
+        let err = Expression::new(&tokens).unwrap_err();
+        assert_eq!(err.message, "Unexpected token '\"n = ${...}\"'");
+    }
+
     #[test]
     fn test_expression_new_trailing_operator() {
         // Test error for a trailing operator: "x &&" (missing right-hand operand)
-        let tokens = vec![Token::Identifier("x".to_string()), Token::And];
+        let tokens = spanned(vec![Token::Identifier("x".to_string()), Token::And]);
         let result = Expression::new(&tokens);
         assert!(result.is_err());
         if let Err(msg) = result {
-            assert!(msg.contains("Expected expression after operator"));
+            assert!(msg.message.contains("Expected expression after operator"));
         }
     }
     // ----- Function Call Tests -----
     #[test]
     fn test_parse_function_call_no_args() {
         // Test: foo()
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::Identifier("foo".to_string()),
             Token::LeftParen,
             Token::RightParen,
-        ];
-        let expected = Expression::FunctionCall {
+        ]);
+        let expected = expr(ExpressionKind::FunctionCall {
             identifier: Token::Identifier("foo".to_string()),
             args: vec![],
-        };
+        });
         assert_eq!(parse_function_call(&tokens), Ok((expected, 3)));
     }
 
     #[test]
     fn test_parse_function_call_single_arg() {
         // Test: foo(x)
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::Identifier("foo".to_string()),
             Token::LeftParen,
             Token::Identifier("x".to_string()),
             Token::RightParen,
-        ];
-        let expected = Expression::FunctionCall {
+        ]);
+        let expected = expr(ExpressionKind::FunctionCall {
             identifier: Token::Identifier("foo".to_string()),
-            args: vec![Token::Identifier("x".to_string())],
-        };
+            args: vec![expr(ExpressionKind::Token(Token::Identifier(
+                "x".to_string(),
+            )))],
+        });
         assert_eq!(parse_function_call(&tokens), Ok((expected, 4)));
     }
 
     #[test]
     fn test_parse_function_call_multiple_args() {
         // Test: foo(x, y, z)
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::Identifier("foo".to_string()),
             Token::LeftParen,
             Token::Identifier("x".to_string()),
@@ -783,15 +1443,198 @@ mod tests {
             Token::Comma,
             Token::Identifier("z".to_string()),
             Token::RightParen,
-        ];
-        let expected = Expression::FunctionCall {
+        ]);
+        let expected = expr(ExpressionKind::FunctionCall {
             identifier: Token::Identifier("foo".to_string()),
             args: vec![
-                Token::Identifier("x".to_string()),
-                Token::Identifier("y".to_string()),
-                Token::Identifier("z".to_string()),
+                expr(ExpressionKind::Token(Token::Identifier("x".to_string()))),
+                expr(ExpressionKind::Token(Token::Identifier("y".to_string()))),
+                expr(ExpressionKind::Token(Token::Identifier("z".to_string()))),
             ],
-        };
+        });
         assert_eq!(parse_function_call(&tokens), Ok((expected, 8)));
     }
+
+    #[test]
+    fn test_parse_function_call_expression_args() {
+        // Test: f(x + 1, g(y))
+        let (tokens, _) = crate::run_lexer("f(x + 1, g(y))").unwrap();
+        let ident = |name: &str| expr(ExpressionKind::Token(Token::Identifier(name.to_string())));
+        let expected = expr(ExpressionKind::FunctionCall {
+            identifier: Token::Identifier("f".to_string()),
+            args: vec![
+                expr(ExpressionKind::Binary {
+                    left: Box::new(ident("x")),
+                    operator: Token::Add,
+                    right: Box::new(expr(ExpressionKind::Token(Token::Integer(1)))),
+                }),
+                expr(ExpressionKind::FunctionCall {
+                    identifier: Token::Identifier("g".to_string()),
+                    args: vec![ident("y")],
+                }),
+            ],
+        });
+        assert_eq!(parse_function_call(&tokens), Ok((expected, tokens.len())));
+    }
+
+    #[test]
+    fn test_parse_function_call_missing_paren() {
+        // Test: foo(x, y
+        let (tokens, _) = crate::run_lexer("foo(x, y").unwrap();
+        let err = parse_function_call(&tokens).unwrap_err();
+        assert_eq!(err.message, "Expected ')' in function call");
+    }
+
+    // ----- Array Declaration Tests -----
+    #[test]
+    fn test_parse_nested_array() {
+        // Test: [[1 * 5], []]
+        let (tokens, _) = crate::run_lexer("[[1 * 5], []]").unwrap();
+        let expected = expr(ExpressionKind::ArrayDec {
+            arr_expr: vec![
+                expr(ExpressionKind::ArrayDec {
+                    arr_expr: vec![expr(ExpressionKind::Binary {
+                        left: Box::new(expr(ExpressionKind::Token(Token::Integer(1)))),
+                        operator: Token::Mul,
+                        right: Box::new(expr(ExpressionKind::Token(Token::Integer(5)))),
+                    })],
+                }),
+                expr(ExpressionKind::ArrayDec { arr_expr: vec![] }),
+            ],
+        });
+        assert_eq!(parse_array_dec(&tokens), Ok((expected, tokens.len())));
+    }
+
+    #[test]
+    fn test_parse_operator_on_nested_array() {
+        // Test: [[1 * 5] * 6], the original line of examples/expression.el.
+        // It's ill-typed, but it has to parse
+        let (tokens, _) = crate::run_lexer("[[1 * 5] * 6]").unwrap();
+        let expected = expr(ExpressionKind::ArrayDec {
+            arr_expr: vec![expr(ExpressionKind::Binary {
+                left: Box::new(expr(ExpressionKind::ArrayDec {
+                    arr_expr: vec![expr(ExpressionKind::Binary {
+                        left: Box::new(expr(ExpressionKind::Token(Token::Integer(1)))),
+                        operator: Token::Mul,
+                        right: Box::new(expr(ExpressionKind::Token(Token::Integer(5)))),
+                    })],
+                })),
+                operator: Token::Mul,
+                right: Box::new(expr(ExpressionKind::Token(Token::Integer(6)))),
+            })],
+        });
+        assert_eq!(parse_array_dec(&tokens), Ok((expected, tokens.len())));
+    }
+
+    #[test]
+    fn test_parse_array_unexpected_token() {
+        // Test: [1 2]
+        let (tokens, _) = crate::run_lexer("[1 2]").unwrap();
+        let err = parse_array_dec(&tokens).unwrap_err();
+        assert_eq!(err.code, codes::UNEXPECTED_TOKEN);
+        assert_eq!(err.span.column, 4);
+    }
+
+    #[test]
+    fn test_parse_format() {
+        let (tokens, _) = crate::run_lexer(r#""n = ${n * 2}!""#).unwrap();
+        let expected = expr(ExpressionKind::Format {
+            parts: vec![
+                FormatPart::Text("n = ".to_string()),
+                FormatPart::Expr(expr(ExpressionKind::Binary {
+                    left: Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                        "n".to_string(),
+                    )))),
+                    operator: Token::Mul,
+                    right: Box::new(expr(ExpressionKind::Token(Token::Integer(2)))),
+                })),
+                FormatPart::Text("!".to_string()),
+            ],
+        });
+        assert_eq!(Expression::new(&tokens), Ok((expected, 1)));
+
+        let (tokens, _) = crate::run_lexer(r#""${}""#).unwrap();
+        assert_eq!(
+            Expression::new(&tokens).unwrap_err().code,
+            codes::EXPECTED_EXPRESSION
+        );
+
+        let (tokens, _) = crate::run_lexer(r#""${a b}""#).unwrap();
+        let err = Expression::new(&tokens).unwrap_err();
+        assert_eq!(err.code, codes::UNEXPECTED_TOKEN);
+        assert_eq!((err.span.start, err.span.end), (5, 6));
+    }
+
+    #[test]
+    fn test_parse_bitwise_precedence() {
+        // a | b ^ c & d == e  parses as  a | (b ^ (c & (d == e)))
+        let (tokens, _) = crate::run_lexer("a | b ^ c & d == e").unwrap();
+        let ident = |name: &str| {
+            Box::new(expr(ExpressionKind::Token(Token::Identifier(
+                name.to_string(),
+            ))))
+        };
+        let binary = |left, operator, right| {
+            Box::new(expr(ExpressionKind::Binary {
+                left,
+                operator,
+                right,
+            }))
+        };
+        let expected = binary(
+            ident("a"),
+            Token::BitOr,
+            binary(
+                ident("b"),
+                Token::BitXor,
+                binary(
+                    ident("c"),
+                    Token::BitAnd,
+                    binary(ident("d"), Token::EqualEqual, ident("e")),
+                ),
+            ),
+        );
+        assert_eq!(Expression::new(&tokens), Ok((*expected, tokens.len())));
+
+        // 1 << 2 + 3 < ~x  parses as  (1 << (2 + 3)) < (~x)
+        let (tokens, _) = crate::run_lexer("1 << 2 + 3 < ~x").unwrap();
+        let int = |n| Box::new(expr(ExpressionKind::Token(Token::Integer(n))));
+        let expected = binary(
+            binary(int(1), Token::ShiftLeft, binary(int(2), Token::Add, int(3))),
+            Token::Less,
+            Box::new(expr(ExpressionKind::Unary {
+                operator: Token::BitNot,
+                operand: ident("x"),
+            })),
+        );
+        assert_eq!(Expression::new(&tokens), Ok((*expected, tokens.len())));
+
+        // && binds looser than |
+        let (tokens, _) = crate::run_lexer("a && b | c").unwrap();
+        let (parsed, _) = Expression::new(&tokens).unwrap();
+        assert!(matches!(
+            parsed.kind,
+            ExpressionKind::Binary {
+                operator: Token::And,
+                ..
+            }
+        ));
+    }
+
+    // ----- Span Tests -----
+    #[test]
+    fn test_expression_spans() {
+        // source: "a + (b * c)"
+        let (tokens, _) = crate::run_lexer("a + (b * c)").unwrap();
+        let (expr, _) = Expression::new(&tokens).unwrap();
+        assert_eq!((expr.span.start, expr.span.end), (0, 11));
+        match expr.kind {
+            ExpressionKind::Binary { left, right, .. } => {
+                assert_eq!((left.span.start, left.span.end), (0, 1));
+                assert_eq!((right.span.start, right.span.end), (4, 11));
+                assert_eq!(right.span.column, 5);
+            }
+            other => panic!("expected a binary expression, got {:?}", other),
+        }
+    }
 }
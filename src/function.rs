@@ -1,22 +1,37 @@
-use crate::expression::Expression;
-use crate::statement::Statement;
+use crate::diagnostic::{codes, Diagnostic};
+use crate::expression::{Expression, ExpressionKind, FormatPart};
+use crate::span::{span_at, span_of, Span, Spanned};
+use crate::statement::{parse_statements, Statement, StatementKind};
 use crate::token::Token;
 
 #[derive(Debug)]
 pub struct Function {
-    pub name: Token,
-    pub params: Vec<Token>,
+    /// The `///` comment written above the function, if any.
+    pub doc: Option<String>,
+    pub name: Spanned<Token>,
+    pub params: Vec<Spanned<Token>>,
     pub body: Vec<Statement>,
+    pub span: Span,
+}
+
+/// The name a function is declared and called by. `main` is lexed as its own
+/// keyword, so it is the one name that isn't an identifier token.
+pub fn function_name(token: &Token) -> Option<&str> {
+    match token {
+        Token::Identifier(name) => Some(name),
+        Token::Main => Some("main"),
+        _ => None,
+    }
 }
 
 fn print_expression(expr: &Expression, indent: usize) {
     let prefix = "│   ".repeat(indent);
 
-    match expr {
-        Expression::Token(token) => {
+    match &expr.kind {
+        ExpressionKind::Token(token) => {
             println!("{}├── {:?}", prefix, token);
         }
-        Expression::Binary {
+        ExpressionKind::Binary {
             left,
             operator,
             right,
@@ -27,38 +42,51 @@ fn print_expression(expr: &Expression, indent: usize) {
             println!("{}├── Right:", prefix);
             print_expression(right, indent + 1);
         }
-        Expression::Unary { operator, operand } => {
+        ExpressionKind::Unary { operator, operand } => {
             println!("{}├── Unary:", prefix);
             println!("{}│   ├── Operator: {:?}", prefix, operator);
             println!("{}│   ├── Operand:", prefix);
             print_expression(operand, indent + 2);
         }
-        Expression::Grouping(inner) => {
+        ExpressionKind::Grouping(inner) => {
             println!("{}├── Grouping:", prefix);
             print_expression(inner, indent + 1);
         }
-        Expression::FunctionCall { identifier, args } => {
+        ExpressionKind::Format { parts } => {
+            println!("{}├── Format:", prefix);
+            for part in parts {
+                match part {
+                    FormatPart::Text(text) => println!("{}│   ├── Text: {:?}", prefix, text),
+                    FormatPart::Expr(inner) => print_expression(inner, indent + 1),
+                }
+            }
+        }
+        ExpressionKind::FunctionCall { identifier, args } => {
             println!("{}├── Function Call:", prefix);
             println!("{}│   ├── Identifier: {:?}", prefix, identifier);
             println!("{}│   ├── Arguments:", prefix);
             for arg in args {
-                println!("{}│   │   ├── {:?}", prefix, arg);
+                print_expression(arg, indent + 2);
             }
         }
-        Expression::ArrayDec { arr_expr } => {
-            println!("{}│   ├── Value: {:?}", prefix, arr_expr);
+        ExpressionKind::ArrayDec { arr_expr } => {
+            println!("{}├── Array:", prefix);
+            for element in arr_expr {
+                print_expression(element, indent + 1);
+            }
         }
-        Expression::AccessIndex { ident, index } => {
+        ExpressionKind::AccessIndex { array, index } => {
             println!("{}├── Access Index:", prefix);
-            println!("{}│   ├── Identifier: {:?} ", prefix, ident);
+            println!("{}│   ├── Array:", prefix);
+            print_expression(array, indent + 2);
             println!("{}│   ├── Index:", prefix);
             print_expression(index, indent + 2);
         }
-        Expression::GetLength { ident } => {
+        ExpressionKind::GetLength { ident } => {
             println!("{}├── Get Length:", prefix);
             println!("{}│   ├── Identifier: {:?}", prefix, ident);
         }
-        Expression::ArrayAppend { array, value } => {
+        ExpressionKind::ArrayAppend { array, value } => {
             println!("{}├── Array Append:", prefix);
             println!("{}│   ├── Array: {:?} ", prefix, array);
             println!("{}│   ├── Value:", prefix);
@@ -70,26 +98,43 @@ fn print_expression(expr: &Expression, indent: usize) {
 fn print_statement(stmt: &Statement, indent: usize) {
     let prefix = "│   ".repeat(indent);
 
-    match stmt {
-        Statement::AssignStatement { identifier, value } => {
-            println!("{}├── AssignStatement: {:?}", prefix, identifier);
+    match &stmt.kind {
+        StatementKind::AssignStatement { target, value } => {
+            println!("{}├── AssignStatement", prefix);
+            println!("{}│   ├── Target:", prefix);
+            print_expression(target, indent + 2);
             println!("{}│   ├── Value:", prefix);
             print_expression(value, indent + 2);
         }
-        Statement::DeclareStatement { identifier, value } => {
+        StatementKind::CompoundAssign {
+            target,
+            operator,
+            value,
+        } => {
+            println!("{}├── CompoundAssign: {:?}", prefix, operator);
+            println!("{}│   ├── Target:", prefix);
+            print_expression(target, indent + 2);
+            println!("{}│   ├── Value:", prefix);
+            print_expression(value, indent + 2);
+        }
+        StatementKind::DeclareStatement { identifier, value } => {
             println!("{}├── DeclareStatement: {:?}", prefix, identifier);
             println!("{}│   ├── Value:", prefix);
             if let Some(expr) = value {
                 print_expression(expr, indent + 2);
             }
         }
-        Statement::ReturnStatement { value } => {
+        StatementKind::ReturnStatement { value } => {
             println!("{}├── ReturnStatement", prefix);
             println!("{}│   ├── Value:", prefix);
             print_expression(value, indent + 2);
         }
+        StatementKind::ExpressionStatement { expr } => {
+            println!("{}├── ExpressionStatement", prefix);
+            print_expression(expr, indent + 1);
+        }
 
-        Statement::IfStatement {
+        StatementKind::IfStatement {
             cond,
             if_then,
             else_then,
@@ -109,7 +154,57 @@ fn print_statement(stmt: &Statement, indent: usize) {
             }
         }
 
-        Statement::WhileStatement { cond, loop_stmt } => {
+        StatementKind::ForStatement {
+            init,
+            cond,
+            step,
+            body,
+        } => {
+            println!("{}├── ForStatement", prefix);
+            if let Some(init) = init {
+                println!("{}│   ├── Init:", prefix);
+                print_statement(init, indent + 2);
+            }
+            if let Some(cond) = cond {
+                println!("{}│   ├── Condition:", prefix);
+                print_expression(cond, indent + 2);
+            }
+            if let Some(step) = step {
+                println!("{}│   ├── Step:", prefix);
+                print_statement(step, indent + 2);
+            }
+            println!("{}│   ├── Loop Body:", prefix);
+            for stmt in body {
+                print_statement(stmt, indent + 2);
+            }
+        }
+
+        StatementKind::ForInStatement {
+            variable,
+            iterable,
+            body,
+        } => {
+            println!("{}├── ForInStatement: {:?}", prefix, variable.node);
+            println!("{}│   ├── Iterable:", prefix);
+            print_expression(iterable, indent + 2);
+            println!("{}│   ├── Loop Body:", prefix);
+            for stmt in body {
+                print_statement(stmt, indent + 2);
+            }
+        }
+
+        StatementKind::BreakStatement => println!("{}├── BreakStatement", prefix),
+        StatementKind::ContinueStatement => println!("{}├── ContinueStatement", prefix),
+
+        StatementKind::PrintStatement { args } => {
+            println!("{}├── PrintStatement", prefix);
+            println!("{}│   ├── Arguments:", prefix);
+            for arg in args {
+                print_expression(arg, indent + 2);
+            }
+        }
+
+        StatementKind::WhileStatement { cond, loop_stmt } => {
             println!("{}├── WhileStatement", prefix);
             println!("{}│   ├── Condition:", prefix);
             print_expression(cond, indent + 2);
@@ -119,8 +214,8 @@ fn print_statement(stmt: &Statement, indent: usize) {
             }
         }
 
-        Statement::ArrayAppend { identifier, value } => {
-            println!("{}├── ArrayAppend: {:?}", prefix, identifier);
+        StatementKind::ArrayAppend { identifier, value } => {
+            println!("{}├── ArrayAppend: {:?}", prefix, identifier.node);
             println!("{}│   ├── Value:", prefix);
             print_expression(value, indent + 2);
         }
@@ -128,10 +223,13 @@ fn print_statement(stmt: &Statement, indent: usize) {
 }
 
 pub fn print_function(func: &Function) {
-    println!("Function: {:?}", func.name);
+    println!("Function: {:?}", func.name.node);
+    if let Some(doc) = &func.doc {
+        println!("├── Doc: {:?}", doc);
+    }
     println!("├── Parameters:");
     for param in &func.params {
-        println!("│   ├── {:?}", param);
+        println!("│   ├── {:?}", param.node);
     }
     println!("└── Body:");
     for stmt in &func.body {
@@ -150,34 +248,70 @@ impl Function {
     // - A left brace
     // - A vector of statements, each ending with a semicolon
     // - A right brace
+    //
+    // Returns the function and the number of tokens it spans, failing on the
+    // first syntax error anywhere inside it.
+    pub fn new(tokens: &[Spanned<Token>], start: usize) -> Result<(Self, usize), Diagnostic> {
+        let mut errors = Vec::new();
+        let result = Function::parse(tokens, start, &mut errors);
+        match errors.into_iter().next() {
+            Some(err) => Err(err),
+            None => result,
+        }
+    }
 
-    pub fn new(tokens: &Vec<Token>, start: usize) -> Result<(Self, usize), String> {
+    // Like `new`, but errors in the body are pushed onto `errors` and parsing
+    // carries on, so the returned function may be missing the statements that
+    // failed. Only a malformed header is returned as an error.
+    pub fn parse(
+        tokens: &[Spanned<Token>],
+        start: usize,
+        errors: &mut Vec<Diagnostic>,
+    ) -> Result<(Self, usize), Diagnostic> {
         if tokens.is_empty() || start >= tokens.len() || tokens[start] != Token::Func {
-            return Err("Expected a function starting with the 'func' token".into());
+            return Err(Diagnostic::error(
+                codes::EXPECTED_FUNCTION,
+                "Expected a function starting with the 'func' token",
+                span_at(tokens, start),
+            ));
         }
 
         let mut new_function: Function = Function {
-            name: Token::Identifier("".to_string()),
+            doc: None,
+            name: Spanned::new(Token::Identifier("".to_string()), tokens[start].span),
             params: vec![],
             body: vec![],
+            span: tokens[start].span,
         };
 
         // Expect the function name to be an identifier
         if tokens.len() > start + 2 {
-            if let Token::Identifier(_) = tokens[start + 1] {
+            if let Token::Identifier(_) = tokens[start + 1].node {
                 new_function.name = tokens[start + 1].clone();
-            } else if let Token::Main = tokens[start + 1] {
+            } else if let Token::Main = tokens[start + 1].node {
                 new_function.name = tokens[start + 1].clone();
             } else {
-                return Err("Syntax error, expected an identifier for the function".into());
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_IDENTIFIER,
+                    "Syntax error, expected an identifier for the function",
+                    tokens[start + 1].span,
+                ));
             }
         } else {
-            return Err("Syntax error, incomplete function header".into());
+            return Err(Diagnostic::error(
+                codes::UNEXPECTED_EOF,
+                "Syntax error, incomplete function header",
+                span_at(tokens, tokens.len()),
+            ));
         }
 
         // Expect a left parenthesis after the function name
         if tokens.len() > start + 3 && tokens[start + 2] != Token::LeftParen {
-            return Err("Syntax error, expected a left parenthesis".into());
+            return Err(Diagnostic::error(
+                codes::EXPECTED_TOKEN,
+                "Syntax error, expected a left parenthesis",
+                tokens[start + 2].span,
+            ));
         }
 
         let mut curr_index = start + 2;
@@ -193,11 +327,15 @@ impl Function {
 
             // If we encounter a left brace before the closing parenthesis, it's an error
             if tokens[curr_index] == Token::LeftBrace && !found_right_paren {
-                return Err("Syntax error, expected right parenthesis before '{'".into());
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_TOKEN,
+                    "Syntax error, expected right parenthesis before '{'",
+                    tokens[curr_index].span,
+                ));
             }
 
             // If the token is an identifier, add it as a parameter
-            if let Token::Identifier(_) = tokens[curr_index] {
+            if let Token::Identifier(_) = tokens[curr_index].node {
                 new_function.params.push(tokens[curr_index].clone());
             }
 
@@ -206,47 +344,62 @@ impl Function {
                 && tokens[curr_index] == Token::Comma
                 && tokens[curr_index + 1] == Token::RightParen
             {
-                return Err("Syntax error, expected an argument between commas".into());
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_IDENTIFIER,
+                    "Syntax error, expected an argument between commas",
+                    tokens[curr_index + 1].span,
+                ));
             }
             curr_index += 1;
         }
 
         if !found_right_paren {
-            return Err("Syntax error, expected a closing parenthesis for parameters".into());
+            return Err(Diagnostic::error(
+                codes::EXPECTED_TOKEN,
+                "Syntax error, expected a closing parenthesis for parameters",
+                span_at(tokens, curr_index),
+            ));
         }
 
         // Expect a left brace to start the function body
         if curr_index < tokens.len() {
             if tokens[curr_index] != Token::LeftBrace {
-                return Err("Syntax error, expected an opening brace for function body".into());
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_TOKEN,
+                    "Syntax error, expected an opening brace for function body",
+                    tokens[curr_index].span,
+                ));
             } else {
                 // Consume the left brace
                 curr_index += 1;
             }
         } else {
-            return Err("Syntax error, expected function body".into());
+            return Err(Diagnostic::error(
+                codes::UNEXPECTED_EOF,
+                "Syntax error, expected function body",
+                span_at(tokens, curr_index),
+            ));
         }
 
         // --- Parse the function body ---
-        while curr_index < tokens.len() && tokens[curr_index] != Token::RightBrace {
-            // Convert the tokens into a Statement
-            let (statement, consumed) = match Statement::new(&tokens[curr_index..]) {
-                Ok(output) => output,
-                Err(err) => return Err(err),
-            };
-
-            new_function.body.push(statement);
-            // Consume the semicolon
-            curr_index += consumed;
-        }
+        let (body, consumed) = parse_statements(&tokens[curr_index..], errors);
+        new_function.body = body;
+        curr_index += consumed;
 
-        // After parsing the body, we expect a closing brace
+        // After parsing the body, we expect a closing brace. If the next
+        // function starts first, keep what we have and let it be parsed.
         if curr_index >= tokens.len() || tokens[curr_index] != Token::RightBrace {
-            return Err("Syntax error, expected a closing brace at end of function body".into());
+            errors.push(Diagnostic::error(
+                codes::EXPECTED_TOKEN,
+                "Syntax error, expected a closing brace at end of function body",
+                span_at(tokens, curr_index),
+            ));
+        } else {
+            // Consume the right brace.
+            curr_index += 1;
         }
-        // Consume the right brace.
-        curr_index += 1;
+        new_function.span = span_of(&tokens[start..curr_index]);
 
-        Ok((new_function, curr_index))
+        Ok((new_function, curr_index - start))
     }
 }
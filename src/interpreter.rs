@@ -0,0 +1,1172 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt,
+    io::{self, Write},
+    panic, thread,
+};
+
+use crate::{
+    diagnostic::{codes, with_article, Diagnostic},
+    expression::{Expression, ExpressionKind, FormatPart},
+    function::{function_name, Function},
+    program::Program,
+    span::Span,
+    statement::{Statement, StatementKind},
+    token::Token,
+};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    String(String),
+    Array(Vec<Value>),
+    Void,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Integer(n) => write!(f, "{}", n),
+            // Whole floats keep their decimal point so they can't be mistaken for integers
+            Value::Float(n) if n.is_finite() && n.fract() == 0.0 => write!(f, "{:.1}", n),
+            Value::Float(n) => write!(f, "{}", n),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    // Strings inside arrays are quoted, so `["a, b"]` and `["a", "b"]` differ
+                    match value {
+                        Value::String(s) => write!(f, "{:?}", s)?,
+                        value => write!(f, "{}", value)?,
+                    }
+                }
+                write!(f, "]")
+            }
+            Value::Void => write!(f, "void"),
+        }
+    }
+}
+
+impl Value {
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "boolean",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Void => "void",
+        }
+    }
+}
+
+// How control leaves a statement: fall through to the next one, unwind to
+// the enclosing loop, or unwind to the enclosing function call with a value.
+enum Flow {
+    Next,
+    Break,
+    Continue,
+    Return(Value),
+}
+
+/// How many calls can be active at once, counting `main`. Both engines stop
+/// a program that goes deeper instead of running out of memory.
+pub const MAX_CALL_DEPTH: usize = 1000;
+
+// Enough native stack for the interpreter to reach `MAX_CALL_DEPTH` in a
+// debug build, where each call takes tens of kilobytes
+const CALL_STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Runs `f` on a thread with enough stack for `MAX_CALL_DEPTH` nested calls
+/// in the interpreter. A default thread overflows long before that.
+pub fn with_call_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    thread::scope(|scope| {
+        thread::Builder::new()
+            .stack_size(CALL_STACK_SIZE)
+            .spawn_scoped(scope, f)
+            .expect("Should have been able to start a thread")
+            .join()
+            .unwrap_or_else(|payload| panic::resume_unwind(payload))
+    })
+}
+
+// Variables visible inside a single function call, innermost block last.
+struct Frame {
+    scopes: Vec<HashMap<String, Value>>,
+}
+
+impl Frame {
+    fn new() -> Self {
+        Frame {
+            scopes: vec![HashMap::new()],
+        }
+    }
+
+    fn declare(&mut self, name: &str, value: Value) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), value);
+        }
+    }
+
+    fn lookup(&self, name: &str, span: Span) -> Result<&Value, Diagnostic> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .ok_or_else(|| undefined_variable(name, span))
+    }
+
+    fn lookup_mut(&mut self, name: &str, span: Span) -> Result<&mut Value, Diagnostic> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+            .ok_or_else(|| undefined_variable(name, span))
+    }
+}
+
+pub struct Interpreter<'a> {
+    functions: HashMap<String, &'a Function>,
+    // Where `print` writes to
+    output: RefCell<Box<dyn Write + 'a>>,
+    // How many calls are running
+    depth: Cell<usize>,
+}
+
+pub(crate) fn identifier_name(token: &Token, span: Span) -> Result<&str, Diagnostic> {
+    match token {
+        Token::Identifier(name) => Ok(name),
+        _ => Err(Diagnostic::error(
+            codes::TYPE_ERROR,
+            format!("Expected an identifier, found '{}'", token),
+            span,
+        )),
+    }
+}
+
+pub(crate) fn undefined_variable(name: &str, span: Span) -> Diagnostic {
+    Diagnostic::error(
+        codes::UNDEFINED_VARIABLE,
+        format!("Undefined variable '{}'", name),
+        span,
+    )
+}
+
+/// The error for calling the function `name`, declared at `name_span` with
+/// `expected` parameters, with `got` arguments.
+pub(crate) fn arity_mismatch(
+    name: &str,
+    expected: usize,
+    name_span: Span,
+    got: usize,
+    span: Span,
+) -> Diagnostic {
+    Diagnostic::error(
+        codes::ARITY_MISMATCH,
+        format!(
+            "Function '{}' expects {} argument(s) but got {}",
+            name, expected, got
+        ),
+        span,
+    )
+    .with_label(name_span, "function defined here")
+}
+
+/// The error for a call at `span` that would go past `MAX_CALL_DEPTH`.
+pub(crate) fn call_depth_exceeded(span: Span) -> Diagnostic {
+    Diagnostic::error(
+        codes::CALL_DEPTH_EXCEEDED,
+        format!("Maximum call depth of {} exceeded", MAX_CALL_DEPTH),
+        span,
+    )
+}
+
+pub(crate) fn type_error(message: String, span: Span) -> Diagnostic {
+    Diagnostic::error(codes::TYPE_ERROR, message, span)
+}
+
+pub(crate) fn operator_error(operator: &Token, operand: &Value, span: Span) -> Diagnostic {
+    type_error(
+        format!(
+            "Cannot apply {} to {}",
+            operator,
+            with_article(operand.type_name())
+        ),
+        span,
+    )
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        Interpreter::with_output(program, io::stdout())
+    }
+
+    /// Creates an interpreter whose `print` statements write to `output`.
+    pub fn with_output(program: &'a Program, output: impl Write + 'a) -> Self {
+        let mut functions = HashMap::new();
+        for function in &program.functions {
+            if let Some(name) = function_name(&function.name.node) {
+                functions.insert(name.to_string(), function);
+            }
+        }
+        Interpreter {
+            functions,
+            output: RefCell::new(Box::new(output)),
+            depth: Cell::new(0),
+        }
+    }
+
+    /// Runs the program starting from `main` and returns whatever `main` returns.
+    pub fn run(&self) -> Result<Value, Diagnostic> {
+        self.call("main", Vec::new(), Span::default())
+    }
+
+    fn call(&self, name: &str, args: Vec<Value>, span: Span) -> Result<Value, Diagnostic> {
+        let function = match self.functions.get(name) {
+            Some(function) => *function,
+            None => {
+                return Err(Diagnostic::error(
+                    codes::UNDEFINED_FUNCTION,
+                    format!("Undefined function '{}'", name),
+                    span,
+                ))
+            }
+        };
+
+        if function.params.len() != args.len() {
+            return Err(arity_mismatch(
+                name,
+                function.params.len(),
+                function.name.span,
+                args.len(),
+                span,
+            ));
+        }
+
+        if self.depth.get() == MAX_CALL_DEPTH {
+            return Err(call_depth_exceeded(span));
+        }
+
+        let mut frame = Frame::new();
+        for (param, value) in function.params.iter().zip(args) {
+            frame.declare(identifier_name(&param.node, param.span)?, value);
+        }
+
+        self.depth.set(self.depth.get() + 1);
+        let flow = self.execute_block(&function.body, &mut frame);
+        self.depth.set(self.depth.get() - 1);
+        match flow? {
+            Flow::Return(value) => Ok(value),
+            // The analyzer rejects `break` and `continue` outside of loops
+            Flow::Next | Flow::Break | Flow::Continue => Ok(Value::Void),
+        }
+    }
+
+    fn execute_block(
+        &self,
+        statements: &[Statement],
+        frame: &mut Frame,
+    ) -> Result<Flow, Diagnostic> {
+        frame.scopes.push(HashMap::new());
+        let mut flow = Ok(Flow::Next);
+        for statement in statements {
+            flow = self.execute(statement, frame);
+            if !matches!(flow, Ok(Flow::Next)) {
+                break;
+            }
+        }
+        frame.scopes.pop();
+        flow
+    }
+
+    fn execute(&self, statement: &Statement, frame: &mut Frame) -> Result<Flow, Diagnostic> {
+        match &statement.kind {
+            StatementKind::DeclareStatement { identifier, value } => {
+                let value = match value {
+                    Some(expr) => self.evaluate(expr, frame)?,
+                    None => Value::Void,
+                };
+                frame.declare(identifier_name(identifier, statement.span)?, value);
+            }
+            StatementKind::AssignStatement { target, value } => {
+                self.assign(target, None, value, frame, statement.span)?;
+            }
+            StatementKind::CompoundAssign {
+                target,
+                operator,
+                value,
+            } => {
+                self.assign(target, Some(operator), value, frame, statement.span)?;
+            }
+            StatementKind::ArrayAppend { identifier, value } => {
+                self.append(&identifier.node, value, frame, statement.span)?;
+            }
+            StatementKind::IfStatement {
+                cond,
+                if_then,
+                else_then,
+            } => {
+                if self.evaluate_condition(cond, frame)? {
+                    return self.execute_block(if_then, frame);
+                } else if let Some(else_then) = else_then {
+                    return self.execute_block(else_then, frame);
+                }
+            }
+            StatementKind::WhileStatement { cond, loop_stmt } => {
+                while self.evaluate_condition(cond, frame)? {
+                    match self.execute_block(loop_stmt, frame)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Next | Flow::Continue => {}
+                    }
+                }
+            }
+            StatementKind::ForStatement {
+                init,
+                cond,
+                step,
+                body,
+            } => {
+                // Variables declared in the header live in a scope around the loop
+                frame.scopes.push(HashMap::new());
+                let flow = self.execute_for(init, cond, step, body, frame);
+                frame.scopes.pop();
+                return flow;
+            }
+            StatementKind::ForInStatement {
+                variable,
+                iterable,
+                body,
+            } => {
+                // The loop walks a snapshot, so appending inside the body
+                // doesn't make it run forever
+                let values = match self.evaluate(iterable, frame)? {
+                    Value::Array(values) => values,
+                    other => {
+                        return Err(type_error(
+                            format!("Cannot iterate over {}", with_article(other.type_name())),
+                            iterable.span,
+                        ))
+                    }
+                };
+                let name = identifier_name(&variable.node, variable.span)?;
+                for value in values {
+                    frame.scopes.push(HashMap::new());
+                    frame.declare(name, value);
+                    let flow = self.execute_block(body, frame);
+                    frame.scopes.pop();
+                    match flow? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Next | Flow::Continue => {}
+                    }
+                }
+            }
+            StatementKind::ReturnStatement { value } => {
+                return Ok(Flow::Return(self.evaluate(value, frame)?));
+            }
+            StatementKind::ExpressionStatement { expr } => {
+                self.evaluate(expr, frame)?;
+            }
+            StatementKind::BreakStatement => return Ok(Flow::Break),
+            StatementKind::ContinueStatement => return Ok(Flow::Continue),
+            StatementKind::PrintStatement { args } => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(self.evaluate(arg, frame)?.to_string());
+                }
+                writeln!(self.output.borrow_mut(), "{}", values.join(" ")).map_err(|err| {
+                    Diagnostic::error(
+                        codes::OUTPUT_ERROR,
+                        format!("Failed to write output: {}", err),
+                        statement.span,
+                    )
+                })?;
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn execute_for(
+        &self,
+        init: &Option<Box<Statement>>,
+        cond: &Option<Expression>,
+        step: &Option<Box<Statement>>,
+        body: &[Statement],
+        frame: &mut Frame,
+    ) -> Result<Flow, Diagnostic> {
+        if let Some(init) = init {
+            self.execute(init, frame)?;
+        }
+        loop {
+            if let Some(cond) = cond {
+                if !self.evaluate_condition(cond, frame)? {
+                    return Ok(Flow::Next);
+                }
+            }
+            match self.execute_block(body, frame)? {
+                Flow::Break => return Ok(Flow::Next),
+                Flow::Return(value) => return Ok(Flow::Return(value)),
+                // `continue` still runs the step
+                Flow::Next | Flow::Continue => {}
+            }
+            if let Some(step) = step {
+                self.execute(step, frame)?;
+            }
+        }
+    }
+
+    // Stores `value` into the variable or array element `target`. With an
+    // operator, the stored value is `target operator value` instead, and the
+    // indices along the way are only evaluated once.
+    fn assign(
+        &self,
+        target: &Expression,
+        operator: Option<&Token>,
+        value: &Expression,
+        frame: &mut Frame,
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        let (root, accesses) = target.place().ok_or_else(|| {
+            type_error("Cannot assign to this expression".to_string(), target.span)
+        })?;
+        let indices = self.evaluate_indices(&accesses, frame)?;
+        let value = self.evaluate(value, frame)?;
+
+        let name = identifier_name(root, span)?;
+        let mut slot = frame.lookup_mut(name, span)?;
+        for ((index, index_span), access) in indices.into_iter().zip(&accesses) {
+            slot = element_mut(slot, index, index_span, access.span)?;
+        }
+        *slot = match operator {
+            Some(operator) => binary_op(operator, slot.clone(), value, span)?,
+            None => value,
+        };
+        Ok(())
+    }
+
+    // Evaluates the index of each access in a chain, outermost array first,
+    // paired with the span to report a bad index at.
+    fn evaluate_indices(
+        &self,
+        accesses: &[&Expression],
+        frame: &mut Frame,
+    ) -> Result<Vec<(Value, Span)>, Diagnostic> {
+        let mut indices = Vec::with_capacity(accesses.len());
+        for access in accesses {
+            if let ExpressionKind::AccessIndex { index, .. } = &access.kind {
+                indices.push((self.evaluate(index, frame)?, index.span));
+            }
+        }
+        Ok(indices)
+    }
+
+    fn evaluate_condition(&self, cond: &Expression, frame: &mut Frame) -> Result<bool, Diagnostic> {
+        match self.evaluate(cond, frame)? {
+            Value::Boolean(b) => Ok(b),
+            other => Err(type_error(
+                format!("Condition must be a boolean, found {}", other.type_name()),
+                cond.span,
+            )),
+        }
+    }
+
+    fn append(
+        &self,
+        identifier: &Token,
+        value: &Expression,
+        frame: &mut Frame,
+        span: Span,
+    ) -> Result<Value, Diagnostic> {
+        let value = self.evaluate(value, frame)?;
+        match frame.lookup_mut(identifier_name(identifier, span)?, span)? {
+            Value::Array(values) => {
+                values.push(value);
+                Ok(Value::Void)
+            }
+            other => Err(type_error(
+                format!("Cannot append to {}", with_article(other.type_name())),
+                span,
+            )),
+        }
+    }
+
+    fn evaluate_token(
+        &self,
+        token: &Token,
+        frame: &Frame,
+        span: Span,
+    ) -> Result<Value, Diagnostic> {
+        match token {
+            Token::Integer(n) => Ok(Value::Integer(*n)),
+            Token::Float(n) => Ok(Value::Float(*n)),
+            Token::Boolean(b) => Ok(Value::Boolean(*b)),
+            Token::String(s) => Ok(Value::String(s.clone())),
+            Token::Identifier(name) => frame.lookup(name, span).cloned(),
+            _ => Err(type_error(
+                format!("Cannot evaluate token '{}'", token),
+                span,
+            )),
+        }
+    }
+
+    fn evaluate(&self, expr: &Expression, frame: &mut Frame) -> Result<Value, Diagnostic> {
+        let span = expr.span;
+        match &expr.kind {
+            ExpressionKind::Token(token) => self.evaluate_token(token, frame, span),
+            ExpressionKind::Grouping(inner) => self.evaluate(inner, frame),
+            ExpressionKind::Format { parts } => {
+                let mut text = String::new();
+                for part in parts {
+                    match part {
+                        FormatPart::Text(literal) => text.push_str(literal),
+                        FormatPart::Expr(inner) => {
+                            text.push_str(&self.evaluate(inner, frame)?.to_string())
+                        }
+                    }
+                }
+                Ok(Value::String(text))
+            }
+            ExpressionKind::ArrayDec { arr_expr } => {
+                let mut values = Vec::with_capacity(arr_expr.len());
+                for element in arr_expr {
+                    values.push(self.evaluate(element, frame)?);
+                }
+                Ok(Value::Array(values))
+            }
+            ExpressionKind::AccessIndex { array, index } => match expr.place() {
+                // Walk into the variable by reference so only the element is copied
+                Some((root, accesses)) => {
+                    let indices = self.evaluate_indices(&accesses, frame)?;
+                    let mut value = frame.lookup(identifier_name(root, span)?, span)?;
+                    for ((index, index_span), access) in indices.into_iter().zip(&accesses) {
+                        value = element(value, index, index_span, access.span)?;
+                    }
+                    Ok(value.clone())
+                }
+                None => {
+                    let array = self.evaluate(array, frame)?;
+                    let index_span = index.span;
+                    let index = self.evaluate(index, frame)?;
+                    element(&array, index, index_span, span).cloned()
+                }
+            },
+            ExpressionKind::GetLength { ident } => {
+                match frame.lookup(identifier_name(ident, span)?, span)? {
+                    Value::Array(values) => Ok(Value::Integer(values.len() as i64)),
+                    Value::String(s) => Ok(Value::Integer(s.chars().count() as i64)),
+                    other => Err(type_error(
+                        format!(
+                            "Cannot take the length of {}",
+                            with_article(other.type_name())
+                        ),
+                        span,
+                    )),
+                }
+            }
+            ExpressionKind::ArrayAppend { array, value } => self.append(array, value, frame, span),
+            ExpressionKind::FunctionCall { identifier, args } => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(self.evaluate(arg, frame)?);
+                }
+                self.call(identifier_name(identifier, span)?, values, span)
+            }
+            ExpressionKind::Unary { operator, operand } => {
+                let operand = self.evaluate(operand, frame)?;
+                unary_op(operator, operand, span)
+            }
+            ExpressionKind::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                // `&&` and `||` only evaluate their right side when needed.
+                if *operator == Token::And || *operator == Token::Or {
+                    let left = match self.evaluate(left, frame)? {
+                        Value::Boolean(b) => b,
+                        other => return Err(operator_error(operator, &other, span)),
+                    };
+                    let short_circuit = if *operator == Token::And { !left } else { left };
+                    if short_circuit {
+                        return Ok(Value::Boolean(left));
+                    }
+                    return match self.evaluate(right, frame)? {
+                        Value::Boolean(b) => Ok(Value::Boolean(b)),
+                        other => Err(operator_error(operator, &other, span)),
+                    };
+                }
+
+                let left = self.evaluate(left, frame)?;
+                let right = self.evaluate(right, frame)?;
+                binary_op(operator, left, right, span)
+            }
+        }
+    }
+}
+
+// Checks `index` against an array of `len` elements.
+fn array_index(index: Value, len: usize, index_span: Span) -> Result<usize, Diagnostic> {
+    match index {
+        Value::Integer(i) => usize::try_from(i).ok().filter(|&i| i < len).ok_or_else(|| {
+            Diagnostic::error(
+                codes::INDEX_OUT_OF_BOUNDS,
+                format!("Index {} out of bounds for array of length {}", i, len),
+                index_span,
+            )
+        }),
+        index => Err(type_error(
+            format!(
+                "Array index must be an integer, found {}",
+                index.type_name()
+            ),
+            index_span,
+        )),
+    }
+}
+
+pub(crate) fn element(
+    array: &Value,
+    index: Value,
+    index_span: Span,
+    span: Span,
+) -> Result<&Value, Diagnostic> {
+    match array {
+        Value::Array(values) => Ok(&values[array_index(index, values.len(), index_span)?]),
+        other => Err(type_error(
+            format!("Cannot index into {}", with_article(other.type_name())),
+            span,
+        )),
+    }
+}
+
+pub(crate) fn element_mut(
+    array: &mut Value,
+    index: Value,
+    index_span: Span,
+    span: Span,
+) -> Result<&mut Value, Diagnostic> {
+    match array {
+        Value::Array(values) => {
+            let i = array_index(index, values.len(), index_span)?;
+            Ok(&mut values[i])
+        }
+        other => Err(type_error(
+            format!("Cannot index into {}", with_article(other.type_name())),
+            span,
+        )),
+    }
+}
+
+pub(crate) fn unary_op(operator: &Token, operand: Value, span: Span) -> Result<Value, Diagnostic> {
+    match (operator, operand) {
+        (Token::Not, Value::Boolean(b)) => Ok(Value::Boolean(!b)),
+        (Token::Sub, Value::Integer(n)) => n.checked_neg().map(Value::Integer).ok_or_else(|| {
+            Diagnostic::error(codes::INTEGER_OVERFLOW, "Integer overflow in -", span)
+        }),
+        (Token::Sub, Value::Float(n)) => Ok(Value::Float(-n)),
+        (Token::BitNot, Value::Integer(n)) => Ok(Value::Integer(!n)),
+        (operator, operand) => Err(operator_error(operator, &operand, span)),
+    }
+}
+
+pub(crate) fn binary_op(
+    operator: &Token,
+    left: Value,
+    right: Value,
+    span: Span,
+) -> Result<Value, Diagnostic> {
+    let overflow = || {
+        Diagnostic::error(
+            codes::INTEGER_OVERFLOW,
+            format!("Integer overflow in {}", operator),
+            span,
+        )
+    };
+
+    match (left, right) {
+        (Value::Integer(a), Value::Integer(b)) => match operator {
+            Token::Add => a.checked_add(b).map(Value::Integer).ok_or_else(overflow),
+            Token::Sub => a.checked_sub(b).map(Value::Integer).ok_or_else(overflow),
+            Token::Mul => a.checked_mul(b).map(Value::Integer).ok_or_else(overflow),
+            Token::Div | Token::Mod if b == 0 => Err(Diagnostic::error(
+                codes::DIVISION_BY_ZERO,
+                "Division by zero",
+                span,
+            )),
+            Token::Div => a.checked_div(b).map(Value::Integer).ok_or_else(overflow),
+            Token::Mod => a.checked_rem(b).map(Value::Integer).ok_or_else(overflow),
+            Token::BitAnd => Ok(Value::Integer(a & b)),
+            Token::BitOr => Ok(Value::Integer(a | b)),
+            Token::BitXor => Ok(Value::Integer(a ^ b)),
+            Token::ShiftLeft | Token::ShiftRight => {
+                let shifted = u32::try_from(b).ok().and_then(|b| match operator {
+                    Token::ShiftLeft => a.checked_shl(b),
+                    _ => a.checked_shr(b),
+                });
+                shifted.map(Value::Integer).ok_or_else(|| {
+                    Diagnostic::error(
+                        codes::INTEGER_OVERFLOW,
+                        format!("Shift amount {} is out of range for a 64-bit integer", b),
+                        span,
+                    )
+                })
+            }
+            _ => compare(operator, &a, &b, span),
+        },
+        (Value::Float(a), Value::Float(b)) => match operator {
+            Token::Add => Ok(Value::Float(a + b)),
+            Token::Sub => Ok(Value::Float(a - b)),
+            Token::Mul => Ok(Value::Float(a * b)),
+            Token::Div => Ok(Value::Float(a / b)),
+            Token::Mod => Ok(Value::Float(a % b)),
+            _ => compare(operator, &a, &b, span),
+        },
+        (Value::String(a), Value::String(b)) => compare(operator, &a, &b, span),
+        (Value::Boolean(a), Value::Boolean(b)) => match operator {
+            Token::EqualEqual => Ok(Value::Boolean(a == b)),
+            Token::NotEqual => Ok(Value::Boolean(a != b)),
+            _ => Err(type_error(
+                format!("Cannot apply {} to booleans", operator),
+                span,
+            )),
+        },
+        (Value::Array(a), Value::Array(b)) => match operator {
+            Token::EqualEqual => Ok(Value::Boolean(a == b)),
+            Token::NotEqual => Ok(Value::Boolean(a != b)),
+            _ => Err(type_error(
+                format!("Cannot apply {} to arrays", operator),
+                span,
+            )),
+        },
+        (left, right) => Err(type_error(
+            format!(
+                "Cannot apply {} to {} and {}",
+                operator,
+                with_article(left.type_name()),
+                with_article(right.type_name())
+            ),
+            span,
+        )),
+    }
+}
+
+fn compare<T: PartialOrd>(operator: &Token, a: &T, b: &T, span: Span) -> Result<Value, Diagnostic> {
+    let result = match operator {
+        Token::EqualEqual => a == b,
+        Token::NotEqual => a != b,
+        Token::Greater => a > b,
+        Token::GreaterEqual => a >= b,
+        Token::Less => a < b,
+        Token::LessEqual => a <= b,
+        _ => {
+            return Err(type_error(
+                format!("Unsupported operator '{}'", operator),
+                span,
+            ))
+        }
+    };
+    Ok(Value::Boolean(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_program, run_lexer};
+
+    fn run(source: &str) -> Result<Value, Diagnostic> {
+        let (tokens, _) = run_lexer(source)?;
+        let program = get_program(&tokens).map_err(|mut errors| errors.remove(0))?;
+        // Bound first so the interpreter is dropped before `program`
+        let result = Interpreter::new(&program).run();
+        result
+    }
+
+    #[test]
+    fn test_return_arithmetic() {
+        let source = "func main() { return 1 * 2 - 4 % 6; }";
+        assert_eq!(run(source), Ok(Value::Integer(-2)));
+    }
+
+    #[test]
+    fn test_while_loop() {
+        let source = "func main() {
+            let i = 0;
+            let sum = 0;
+            while (i < 10) {
+                sum = sum + i;
+                i = i + 1;
+            }
+            return sum;
+        }";
+        assert_eq!(run(source), Ok(Value::Integer(45)));
+    }
+
+    #[test]
+    fn test_else_if() {
+        let source = "func main() {
+            let x = 2;
+            let y = 4;
+            if (x > y) {
+                return x + y;
+            } else if (x < y) {
+                return x - y;
+            } else {
+                return 0;
+            }
+        }";
+        assert_eq!(run(source), Ok(Value::Integer(-2)));
+    }
+
+    #[test]
+    fn test_function_call() {
+        let source = "func in_range(x, y) {
+            let sum = x + y;
+            return sum >= 20 && sum < 30;
+        }
+        func main() {
+            let x = 10;
+            let y = 15;
+            return in_range(x, y);
+        }";
+        assert_eq!(run(source), Ok(Value::Boolean(true)));
+    }
+
+    #[test]
+    fn test_array_operations() {
+        let source = "func main() {
+            let arr = [1, 2, 3];
+            arr.append(4);
+            let len = arr.length;
+            return arr[len - 1] + len;
+        }";
+        assert_eq!(run(source), Ok(Value::Integer(8)));
+    }
+
+    #[test]
+    fn test_nested_calls_and_arrays() {
+        let source = "func add(a, b) { return a + b; }
+        func main() {
+            let grid = [[1, 2], [add(1, 2), add(2, add(1, 1))]];
+            let row = grid[1];
+            return add(row[0] * 10, row[1]);
+        }";
+        assert_eq!(run(source), Ok(Value::Integer(34)));
+    }
+
+    #[test]
+    fn test_literals_and_negation() {
+        let source = "func main() {
+            let t = true;
+            let pi = 3.5;
+            let n = -5;
+            if (t && -pi < 0.0) {
+                return n * -2 - -1;
+            }
+            return 0;
+        }";
+        assert_eq!(run(source), Ok(Value::Integer(11)));
+    }
+
+    #[test]
+    fn test_negation_overflow() {
+        let source = "func main() { let min = -9223372036854775807 - 1; return -min; }";
+        assert_eq!(run(source).unwrap_err().code, codes::INTEGER_OVERFLOW);
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        let source = "func main() {
+            let hash = 5381;
+            for c in [104, 105] {
+                hash = ((hash << 5) + hash) ^ c;
+            }
+            let flags = 0b1010;
+            flags = flags | 1;
+            flags = flags & ~0b10;
+            return [hash, flags, -16 >> 2, 6 ^ 3, 1 << 62];
+        }";
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            "[5861124, 9, -4, 5, 4611686018427387904]"
+        );
+
+        let source = "func main() { return 1 << 64; }";
+        let err = run(source).unwrap_err();
+        assert_eq!(err.code, codes::INTEGER_OVERFLOW);
+        assert_eq!(
+            err.message,
+            "Shift amount 64 is out of range for a 64-bit integer"
+        );
+        let source = "func main() { return 1 >> -1; }";
+        assert_eq!(run(source).unwrap_err().code, codes::INTEGER_OVERFLOW);
+    }
+
+    #[test]
+    fn test_64_bit_integers() {
+        let source = "func main() { return [3_000_000_000 * 3, 0xFF + 0b1 + 0o7, .5 + 1e1]; }";
+        assert_eq!(run(source).unwrap().to_string(), "[9000000000, 263, 10.5]");
+
+        let source = "func main() { let big = 0x4000_0000_0000_0000; return big * 2; }";
+        let err = run(source).unwrap_err();
+        assert_eq!(err.code, codes::INTEGER_OVERFLOW);
+        assert_eq!(err.message, "Integer overflow in *");
+    }
+
+    #[test]
+    fn test_operator_messages() {
+        let messages = [
+            (
+                "func f() {} func main() { return f() + 1; }",
+                "Cannot apply + to a void and an integer",
+            ),
+            (
+                "func main() { return true && 1; }",
+                "Cannot apply && to an integer",
+            ),
+            (
+                "func main() { return -\"a\"; }",
+                "Cannot apply - to a string",
+            ),
+            (
+                "func main() { return [1] < [2]; }",
+                "Cannot apply < to arrays",
+            ),
+        ];
+        for (source, message) in messages {
+            assert_eq!(run(source).unwrap_err().message, message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_print() {
+        let source = "func main() {
+            let nested = [[1, 2], [3]];
+            print(1 + 1, 2.0, 0.5, 1 < 2, \"text\");
+            print(nested, [\"a\", \"b\"]);
+            print();
+            return 0;
+        }";
+        let (tokens, _) = run_lexer(source).unwrap();
+        let program = get_program(&tokens).unwrap();
+        let mut output = Vec::new();
+        Interpreter::with_output(&program, &mut output)
+            .run()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "2 2.0 0.5 true text\n[[1, 2], [3]] [\"a\", \"b\"]\n\n"
+        );
+    }
+
+    #[test]
+    fn test_for_loops() {
+        let source = "func main() {
+            let arr = [1, 2, 3];
+            let total = 0;
+            for (let i = 0; i < arr.length; i = i + 1) {
+                total = total + arr[i] * 10;
+            }
+            for x in arr {
+                arr.append(x);
+                total = total + x;
+            }
+            return total + arr.length;
+        }";
+        assert_eq!(run(source), Ok(Value::Integer(60 + 6 + 6)));
+    }
+
+    #[test]
+    fn test_return_from_for_loop() {
+        let source = "func find(arr, target) {
+            let i = 0;
+            for x in arr {
+                if (x == target) {
+                    return i;
+                }
+                i = i + 1;
+            }
+            return -1;
+        }
+        func main() {
+            let arr = [4, 5, 6];
+            return find(arr, 6) * 10 + find(arr, 7);
+        }";
+        assert_eq!(run(source), Ok(Value::Integer(19)));
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        let source = "func main() {
+            let total = 0;
+            for (let i = 0; i < 10; i = i + 1) {
+                if (i % 2 == 0) {
+                    continue;
+                }
+                if (i > 7) {
+                    break;
+                }
+                total = total + i;
+            }
+            let n = 0;
+            while (true) {
+                n = n + 1;
+                if (n == 3) {
+                    break;
+                }
+            }
+            for x in [100, 200, 300] {
+                if (x == 200) {
+                    continue;
+                }
+                total = total + x;
+            }
+            return total + n;
+        }";
+        assert_eq!(run(source), Ok(Value::Integer(1 + 3 + 5 + 7 + 3 + 400)));
+    }
+
+    #[test]
+    fn test_format() {
+        let source = r#"func main() {
+            let name = "elden";
+            let xs = [1.5, 2.0];
+            return "${name}: ${xs} (${xs.length} items, ${xs[0] > 1.0}) \${done}";
+        }"#;
+        assert_eq!(
+            run(source),
+            Ok(Value::String(
+                "elden: [1.5, 2.0] (2 items, true) ${done}".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_expression_statement() {
+        let source = "func log_value(v) { print(v); return v; }
+        func main() {
+            log_value(1);
+            log_value(log_value(2) + 1);
+            return 0;
+        }";
+        let (tokens, _) = run_lexer(source).unwrap();
+        let program = get_program(&tokens).unwrap();
+        let mut output = Vec::new();
+        let result = Interpreter::with_output(&program, &mut output).run();
+        assert_eq!(result, Ok(Value::Integer(0)));
+        assert_eq!(String::from_utf8(output).unwrap(), "1\n2\n3\n");
+    }
+
+    #[test]
+    fn test_compound_assignment() {
+        let source = "func main() {
+            let x = 10;
+            x += 5;
+            x -= 3;
+            x *= 4;
+            x /= 6;
+            x %= 5;
+            let f = 1.5;
+            f *= 2.0;
+            let arr = [[1, 2], [3, 4]];
+            arr[1][0] += x;
+            return [x, arr[1][0], f];
+        }";
+        assert_eq!(run(source).unwrap().to_string(), "[3, 6, 3.0]");
+
+        let source = "func main() { let x = 1; x /= 0; return x; }";
+        assert_eq!(run(source).unwrap_err().code, codes::DIVISION_BY_ZERO);
+    }
+
+    #[test]
+    fn test_indexed_assignment() {
+        let source = "func main() {
+            let arr = [5, 3, 4, 1, 2];
+            for (let i = 0; i < arr.length; i = i + 1) {
+                for (let j = 0; j + 1 < arr.length - i; j = j + 1) {
+                    if (arr[j] > arr[j + 1]) {
+                        let tmp = arr[j];
+                        arr[j] = arr[j + 1];
+                        arr[j + 1] = tmp;
+                    }
+                }
+            }
+            let grid = [[0, 0], [0, 0]];
+            grid[1][0] = 7;
+            return [arr, grid];
+        }";
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            "[[1, 2, 3, 4, 5], [[0, 0], [7, 0]]]"
+        );
+    }
+
+    #[test]
+    fn test_indexed_assignment_out_of_bounds() {
+        let source = "func main() { let grid = [[1]]; grid[0][1] = 2; return 0; }";
+        let err = run(source).unwrap_err();
+        assert_eq!(err.code, codes::INDEX_OUT_OF_BOUNDS);
+        assert_eq!(err.message, "Index 1 out of bounds for array of length 1");
+        assert_eq!((err.span.start, err.span.end), (40, 41));
+    }
+
+    #[test]
+    fn test_block_scope() {
+        let source = "func main() {
+            if (1 < 2) {
+                let inner = 1;
+            }
+            return inner;
+        }";
+        assert_eq!(run(source).unwrap_err().code, codes::UNDEFINED_VARIABLE);
+    }
+
+    #[test]
+    fn test_index_out_of_bounds() {
+        let source = "func main() { let arr = [1]; return arr[3]; }";
+        let err = run(source).unwrap_err();
+        assert_eq!(err.code, codes::INDEX_OUT_OF_BOUNDS);
+        assert_eq!(err.message, "Index 3 out of bounds for array of length 1");
+        // The error points at the index expression
+        assert_eq!((err.span.start, err.span.end), (40, 41));
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let source = "func main() { return 1 / 0; }";
+        assert_eq!(run(source).unwrap_err().code, codes::DIVISION_BY_ZERO);
+    }
+
+    #[test]
+    fn test_undefined_function() {
+        let source = "func main() { return check(1); }";
+        assert_eq!(run(source).unwrap_err().code, codes::UNDEFINED_FUNCTION);
+    }
+
+    #[test]
+    fn test_call_depth_limit() {
+        let source = |n| {
+            format!(
+                "func f(n) {{ if (n == 0) {{ return 0; }} return f(n - 1) + 1; }}
+                func main() {{ return f({}); }}",
+                n
+            )
+        };
+        // `main` and `f(0)` count as well
+        let depth = MAX_CALL_DEPTH - 2;
+        let result = with_call_stack(|| run(&source(depth)));
+        assert_eq!(result, Ok(Value::Integer(depth as i64)));
+        let err = with_call_stack(|| run(&source(depth + 1))).unwrap_err();
+        assert_eq!(err.code, codes::CALL_DEPTH_EXCEEDED);
+        assert_eq!(err.message, "Maximum call depth of 1000 exceeded");
+        let err = with_call_stack(|| run(&source(1_000_000))).unwrap_err();
+        assert_eq!(err.code, codes::CALL_DEPTH_EXCEEDED);
+    }
+}
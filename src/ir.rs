@@ -0,0 +1,1454 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    bytecode::{BinaryOp, UnaryOp},
+    diagnostic::Diagnostic,
+    expression::{Expression, ExpressionKind, FormatPart},
+    function::{function_name, Function},
+    interpreter::{identifier_name, type_error, undefined_variable, Value},
+    program::Program,
+    span::{Span, Spanned},
+    statement::{Statement, StatementKind},
+    token::Token,
+};
+
+/// A variable of an IR function, either one from the source or a temporary.
+/// It indexes into `IrFunction::vars`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Var(pub u32);
+
+/// A basic block of an IR function. It indexes into `IrFunction::blocks`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub u32);
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "b{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Var(Var),
+    Const(Value),
+}
+
+/// A three-address instruction. Arrays are values, so the ones that change an
+/// array produce a new one rather than updating it in place.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Copy {
+        dest: Var,
+        value: Operand,
+    },
+    Unary {
+        dest: Var,
+        operator: UnaryOp,
+        operand: Operand,
+    },
+    /// Any binary operator but `&&` and `||`, which become branches.
+    Binary {
+        dest: Var,
+        operator: BinaryOp,
+        left: Operand,
+        right: Operand,
+    },
+    Call {
+        dest: Var,
+        function: String,
+        args: Vec<Operand>,
+    },
+    Array {
+        dest: Var,
+        elements: Vec<Operand>,
+    },
+    /// Joins the parts of an interpolated string.
+    Format {
+        dest: Var,
+        parts: Vec<Operand>,
+    },
+    Index {
+        dest: Var,
+        array: Operand,
+        index: Operand,
+    },
+    /// `array` with the element at `index` replaced by `value`.
+    Insert {
+        dest: Var,
+        array: Operand,
+        index: Operand,
+        value: Operand,
+    },
+    Length {
+        dest: Var,
+        value: Operand,
+    },
+    /// `array` with `value` added to the end.
+    Append {
+        dest: Var,
+        array: Operand,
+        value: Operand,
+    },
+    Print {
+        args: Vec<Operand>,
+    },
+    /// Picks the value coming from whichever predecessor control arrived
+    /// from. Only found at the start of a block, and only in SSA form.
+    Phi {
+        dest: Var,
+        sources: Vec<(BlockId, Operand)>,
+    },
+}
+
+impl Instruction {
+    /// The variable the instruction assigns, if any.
+    pub fn dest(&self) -> Option<Var> {
+        match self {
+            Instruction::Copy { dest, .. }
+            | Instruction::Unary { dest, .. }
+            | Instruction::Binary { dest, .. }
+            | Instruction::Call { dest, .. }
+            | Instruction::Array { dest, .. }
+            | Instruction::Format { dest, .. }
+            | Instruction::Index { dest, .. }
+            | Instruction::Insert { dest, .. }
+            | Instruction::Length { dest, .. }
+            | Instruction::Append { dest, .. }
+            | Instruction::Phi { dest, .. } => Some(*dest),
+            Instruction::Print { .. } => None,
+        }
+    }
+
+    pub fn dest_mut(&mut self) -> Option<&mut Var> {
+        match self {
+            Instruction::Copy { dest, .. }
+            | Instruction::Unary { dest, .. }
+            | Instruction::Binary { dest, .. }
+            | Instruction::Call { dest, .. }
+            | Instruction::Array { dest, .. }
+            | Instruction::Format { dest, .. }
+            | Instruction::Index { dest, .. }
+            | Instruction::Insert { dest, .. }
+            | Instruction::Length { dest, .. }
+            | Instruction::Append { dest, .. }
+            | Instruction::Phi { dest, .. } => Some(dest),
+            Instruction::Print { .. } => None,
+        }
+    }
+
+    /// The operands the instruction reads, in order.
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Instruction::Copy { value, .. } | Instruction::Length { value, .. } => vec![value],
+            Instruction::Unary { operand, .. } => vec![operand],
+            Instruction::Binary { left, right, .. } => vec![left, right],
+            Instruction::Index { array, index, .. } => vec![array, index],
+            Instruction::Insert {
+                array,
+                index,
+                value,
+                ..
+            } => vec![array, index, value],
+            Instruction::Append { array, value, .. } => vec![array, value],
+            Instruction::Call { args, .. }
+            | Instruction::Array { elements: args, .. }
+            | Instruction::Format { parts: args, .. }
+            | Instruction::Print { args } => args.iter().collect(),
+            Instruction::Phi { sources, .. } => sources.iter().map(|(_, value)| value).collect(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Instruction::Copy { value, .. } | Instruction::Length { value, .. } => vec![value],
+            Instruction::Unary { operand, .. } => vec![operand],
+            Instruction::Binary { left, right, .. } => vec![left, right],
+            Instruction::Index { array, index, .. } => vec![array, index],
+            Instruction::Insert {
+                array,
+                index,
+                value,
+                ..
+            } => vec![array, index, value],
+            Instruction::Append { array, value, .. } => vec![array, value],
+            Instruction::Call { args, .. }
+            | Instruction::Array { elements: args, .. }
+            | Instruction::Format { parts: args, .. }
+            | Instruction::Print { args } => args.iter_mut().collect(),
+            Instruction::Phi { sources, .. } => {
+                sources.iter_mut().map(|(_, value)| value).collect()
+            }
+        }
+    }
+}
+
+/// How control leaves a basic block.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    Branch {
+        cond: Operand,
+        then: BlockId,
+        otherwise: BlockId,
+    },
+    Return(Operand),
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch {
+                then, otherwise, ..
+            } => vec![*then, *otherwise],
+            Terminator::Return(_) => Vec::new(),
+        }
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch {
+                then, otherwise, ..
+            } => vec![then, otherwise],
+            Terminator::Return(_) => Vec::new(),
+        }
+    }
+
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Terminator::Jump(_) => Vec::new(),
+            Terminator::Branch { cond: operand, .. } | Terminator::Return(operand) => vec![operand],
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Terminator::Jump(_) => Vec::new(),
+            Terminator::Branch { cond: operand, .. } | Terminator::Return(operand) => vec![operand],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub instructions: Vec<Spanned<Instruction>>,
+    pub terminator: Spanned<Terminator>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarInfo {
+    /// A name no other variable of the function has, used when printing.
+    pub name: String,
+    /// The source variable, or `None` for a temporary.
+    pub variable: Option<String>,
+    /// Where a source variable is declared, or the expression a temporary
+    /// holds the value of.
+    pub span: Span,
+}
+
+/// A function lowered to a control-flow graph. Execution starts in the first
+/// block.
+#[derive(Debug, Clone)]
+pub struct IrFunction {
+    pub name: String,
+    pub params: Vec<Var>,
+    pub vars: Vec<VarInfo>,
+    pub blocks: Vec<Block>,
+    pub span: Span,
+    temporaries: usize,
+    // How many variables have been given each name, to number the next one
+    names: HashMap<String, usize>,
+}
+
+impl IrFunction {
+    pub const ENTRY: BlockId = BlockId(0);
+
+    fn new(name: &str, span: Span) -> Self {
+        IrFunction {
+            name: name.to_string(),
+            params: Vec::new(),
+            vars: Vec::new(),
+            blocks: Vec::new(),
+            span,
+            temporaries: 0,
+            names: HashMap::new(),
+        }
+    }
+
+    pub fn block(&self, id: BlockId) -> &Block {
+        &self.blocks[id.0 as usize]
+    }
+
+    pub fn block_mut(&mut self, id: BlockId) -> &mut Block {
+        &mut self.blocks[id.0 as usize]
+    }
+
+    pub fn block_ids(&self) -> impl DoubleEndedIterator<Item = BlockId> {
+        (0..self.blocks.len() as u32).map(BlockId)
+    }
+
+    pub fn var(&self, var: Var) -> &VarInfo {
+        &self.vars[var.0 as usize]
+    }
+
+    /// Adds a variable. Source variables are printed by their name, with a
+    /// number after it if the name is taken; temporaries are numbered `%0`,
+    /// `%1` and so on.
+    pub fn new_var(&mut self, variable: Option<&str>, span: Span) -> Var {
+        let name = match variable {
+            Some(name) => self.unique_name(name),
+            None => {
+                self.temporaries += 1;
+                self.unique_name(&format!("%{}", self.temporaries - 1))
+            }
+        };
+        self.vars.push(VarInfo {
+            name,
+            variable: variable.map(str::to_string),
+            span,
+        });
+        Var(self.vars.len() as u32 - 1)
+    }
+
+    /// Adds another variable standing for the same source variable or
+    /// temporary as `var`, like a new version of it.
+    pub fn version_of(&mut self, var: Var) -> Var {
+        let info = self.var(var).clone();
+        let base = match info.name.split_once('.') {
+            Some((base, _)) => base.to_string(),
+            None => info.name,
+        };
+        let name = self.unique_name(&base);
+        self.vars.push(VarInfo { name, ..info });
+        Var(self.vars.len() as u32 - 1)
+    }
+
+    fn unique_name(&mut self, base: &str) -> String {
+        let count = self.names.entry(base.to_string()).or_insert(0);
+        *count += 1;
+        match *count {
+            1 => base.to_string(),
+            n => format!("{}.{}", base, n - 1),
+        }
+    }
+
+    /// The blocks that can jump to each block, in block order.
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut predecessors = vec![Vec::new(); self.blocks.len()];
+        for id in self.block_ids() {
+            for successor in self.block(id).terminator.node.successors() {
+                if !predecessors[successor.0 as usize].contains(&id) {
+                    predecessors[successor.0 as usize].push(id);
+                }
+            }
+        }
+        predecessors
+    }
+
+    fn fmt_operand(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::Var(var) => self.var(*var).name.clone(),
+            Operand::Const(Value::String(s)) => format!("{:?}", s),
+            Operand::Const(Value::Float(n)) => format!("{:?}", n),
+            Operand::Const(value) => value.to_string(),
+        }
+    }
+
+    fn fmt_operands(&self, operands: &[Operand]) -> String {
+        let operands: Vec<_> = operands.iter().map(|op| self.fmt_operand(op)).collect();
+        operands.join(", ")
+    }
+
+    pub(crate) fn fmt_instruction(&self, instruction: &Instruction) -> String {
+        let var = |var: &Var| &self.var(*var).name;
+        let operand = |operand| self.fmt_operand(operand);
+        match instruction {
+            Instruction::Copy { dest, value } => format!("{} = {}", var(dest), operand(value)),
+            Instruction::Unary {
+                dest,
+                operator,
+                operand: value,
+            } => format!("{} = {}{}", var(dest), operator.symbol(), operand(value)),
+            Instruction::Binary {
+                dest,
+                operator,
+                left,
+                right,
+            } => format!(
+                "{} = {} {} {}",
+                var(dest),
+                operand(left),
+                operator.symbol(),
+                operand(right)
+            ),
+            Instruction::Call {
+                dest,
+                function,
+                args,
+            } => format!(
+                "{} = call {}({})",
+                var(dest),
+                function,
+                self.fmt_operands(args)
+            ),
+            Instruction::Array { dest, elements } => {
+                format!("{} = [{}]", var(dest), self.fmt_operands(elements))
+            }
+            Instruction::Format { dest, parts } => {
+                format!("{} = format({})", var(dest), self.fmt_operands(parts))
+            }
+            Instruction::Index { dest, array, index } => {
+                format!("{} = {}[{}]", var(dest), operand(array), operand(index))
+            }
+            Instruction::Insert {
+                dest,
+                array,
+                index,
+                value,
+            } => format!(
+                "{} = insert({}, {}, {})",
+                var(dest),
+                operand(array),
+                operand(index),
+                operand(value)
+            ),
+            Instruction::Length { dest, value } => {
+                format!("{} = length({})", var(dest), operand(value))
+            }
+            Instruction::Append { dest, array, value } => format!(
+                "{} = append({}, {})",
+                var(dest),
+                operand(array),
+                operand(value)
+            ),
+            Instruction::Print { args } => format!("print({})", self.fmt_operands(args)),
+            Instruction::Phi { dest, sources } => {
+                let sources: Vec<_> = sources
+                    .iter()
+                    .map(|(block, value)| format!("{}: {}", block, operand(value)))
+                    .collect();
+                format!("{} = phi({})", var(dest), sources.join(", "))
+            }
+        }
+    }
+
+    pub(crate) fn fmt_terminator(&self, terminator: &Terminator) -> String {
+        match terminator {
+            Terminator::Jump(target) => format!("jump {}", target),
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } => format!("branch {}, {}, {}", self.fmt_operand(cond), then, otherwise),
+            Terminator::Return(value) => format!("return {}", self.fmt_operand(value)),
+        }
+    }
+}
+
+impl fmt::Display for IrFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let params: Vec<_> = self
+            .params
+            .iter()
+            .map(|&param| self.var(param).name.as_str())
+            .collect();
+        writeln!(f, "func {}({}):", self.name, params.join(", "))?;
+        for id in self.block_ids() {
+            let block = self.block(id);
+            writeln!(f, "{}:", id)?;
+            for instruction in &block.instructions {
+                writeln!(f, "    {}", self.fmt_instruction(&instruction.node))?;
+            }
+            writeln!(f, "    {}", self.fmt_terminator(&block.terminator.node))?;
+        }
+        Ok(())
+    }
+}
+
+/// A whole program in the intermediate representation.
+#[derive(Debug, Clone)]
+pub struct IrProgram {
+    pub functions: Vec<IrFunction>,
+}
+
+impl IrProgram {
+    /// Lowers every function of an analyzed program. The only error is a
+    /// variable used where it isn't declared, which analysis already rejects.
+    pub fn lower(program: &Program) -> Result<Self, Diagnostic> {
+        let functions = program
+            .functions
+            .iter()
+            .map(|function| Lowerer::new(function).lower(function))
+            .collect::<Result<_, _>>()?;
+        Ok(IrProgram { functions })
+    }
+}
+
+impl fmt::Display for IrProgram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, function) in self.functions.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", function)?;
+        }
+        Ok(())
+    }
+}
+
+// A block whose terminator hasn't been emitted yet.
+struct PartialBlock {
+    instructions: Vec<Spanned<Instruction>>,
+    terminator: Option<Spanned<Terminator>>,
+}
+
+// Where `continue` and `break` go in the innermost loop.
+struct LoopTargets {
+    next: BlockId,
+    exit: BlockId,
+}
+
+// Lowers one function. Statements are added to the current block; once it has
+// a terminator there is none until control flow picks the next one, and any
+// statement in between starts a block nothing jumps to.
+struct Lowerer {
+    function: IrFunction,
+    blocks: Vec<PartialBlock>,
+    current: Option<BlockId>,
+    scopes: Vec<HashMap<String, Var>>,
+    loops: Vec<LoopTargets>,
+}
+
+type Lowered<T> = Result<T, Diagnostic>;
+
+impl Lowerer {
+    fn new(function: &Function) -> Self {
+        let name = function_name(&function.name.node).unwrap_or_default();
+        let mut lowerer = Lowerer {
+            function: IrFunction::new(name, function.span),
+            blocks: Vec::new(),
+            current: None,
+            scopes: vec![HashMap::new()],
+            loops: Vec::new(),
+        };
+        let entry = lowerer.new_block();
+        lowerer.current = Some(entry);
+        lowerer
+    }
+
+    fn lower(mut self, function: &Function) -> Lowered<IrFunction> {
+        for param in &function.params {
+            let name = identifier_name(&param.node, param.span)?;
+            let var = self.declare(name, param.span);
+            self.function.params.push(var);
+        }
+        self.block(&function.body)?;
+        // Falling off the end returns `Void`
+        self.seal(
+            Terminator::Return(Operand::Const(Value::Void)),
+            function.span,
+        );
+
+        let span = function.span;
+        self.function.blocks = self
+            .blocks
+            .into_iter()
+            .map(|block| Block {
+                instructions: block.instructions,
+                terminator: block.terminator.unwrap_or_else(|| {
+                    Spanned::new(Terminator::Return(Operand::Const(Value::Void)), span)
+                }),
+            })
+            .collect();
+        Ok(self.function)
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(PartialBlock {
+            instructions: Vec::new(),
+            terminator: None,
+        });
+        BlockId(self.blocks.len() as u32 - 1)
+    }
+
+    // The block being added to, starting an unreachable one if there is none.
+    fn current(&mut self) -> BlockId {
+        match self.current {
+            Some(block) => block,
+            None => {
+                let block = self.new_block();
+                self.current = Some(block);
+                block
+            }
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction, span: Span) {
+        let block = self.current();
+        self.blocks[block.0 as usize]
+            .instructions
+            .push(Spanned::new(instruction, span));
+    }
+
+    // Ends the current block with a terminator the source asked for.
+    fn terminate(&mut self, terminator: Terminator, span: Span) {
+        let block = self.current();
+        self.blocks[block.0 as usize].terminator = Some(Spanned::new(terminator, span));
+        self.current = None;
+    }
+
+    // Ends the current block where control falls through to somewhere else,
+    // which doesn't happen if it already ended.
+    fn seal(&mut self, terminator: Terminator, span: Span) {
+        if self.current.is_some() {
+            self.terminate(terminator, span);
+        }
+    }
+
+    fn temporary(&mut self, span: Span) -> Var {
+        self.function.new_var(None, span)
+    }
+
+    // Declaring a name again in the same scope reuses its variable, as the
+    // interpreter overwrites it.
+    fn declare(&mut self, name: &str, span: Span) -> Var {
+        if let Some(&var) = self.scopes.last().and_then(|scope| scope.get(name)) {
+            return var;
+        }
+        let var = self.function.new_var(Some(name), span);
+        self.scopes
+            .last_mut()
+            .expect("a function always has a scope")
+            .insert(name.to_string(), var);
+        var
+    }
+
+    fn lookup(&self, token: &Token, span: Span) -> Lowered<Var> {
+        let name = identifier_name(token, span)?;
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+            .ok_or_else(|| undefined_variable(name, span))
+    }
+
+    fn block(&mut self, statements: &[Statement]) -> Lowered<()> {
+        self.scopes.push(HashMap::new());
+        let result = statements
+            .iter()
+            .try_for_each(|statement| self.statement(statement));
+        self.scopes.pop();
+        result
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Lowered<()> {
+        let span = statement.span;
+        match &statement.kind {
+            StatementKind::DeclareStatement { identifier, value } => {
+                let value = match value {
+                    Some(expr) => self.expression(expr)?,
+                    None => Operand::Const(Value::Void),
+                };
+                let dest = self.declare(identifier_name(identifier, span)?, span);
+                self.emit(Instruction::Copy { dest, value }, span);
+            }
+            StatementKind::AssignStatement { target, value } => {
+                self.assign(target, None, value, span)?;
+            }
+            StatementKind::CompoundAssign {
+                target,
+                operator,
+                value,
+            } => {
+                self.assign(target, BinaryOp::from_token(operator), value, span)?;
+            }
+            StatementKind::ArrayAppend { identifier, value } => {
+                self.append(&identifier.node, value, span)?;
+            }
+            StatementKind::IfStatement {
+                cond,
+                if_then,
+                else_then,
+            } => self.if_statement(cond, if_then, else_then.as_deref())?,
+            StatementKind::WhileStatement { cond, loop_stmt } => {
+                let header = self.new_block();
+                self.seal(Terminator::Jump(header), span);
+                self.current = Some(header);
+                let cond_value = self.expression(cond)?;
+                let body = self.new_block();
+                let exit = self.new_block();
+                self.terminate(
+                    Terminator::Branch {
+                        cond: cond_value,
+                        then: body,
+                        otherwise: exit,
+                    },
+                    cond.span,
+                );
+                self.current = Some(body);
+                self.loop_body(loop_stmt, header, exit, span)?;
+                self.current = Some(exit);
+            }
+            StatementKind::ForStatement {
+                init,
+                cond,
+                step,
+                body,
+            } => {
+                // Variables declared in the header live in a scope around the loop
+                self.scopes.push(HashMap::new());
+                if let Some(init) = init {
+                    self.statement(init)?;
+                }
+                let header = self.new_block();
+                self.seal(Terminator::Jump(header), span);
+                self.current = Some(header);
+                let cond = match cond {
+                    Some(cond) => Some((self.expression(cond)?, cond.span)),
+                    None => None,
+                };
+                let body_block = self.new_block();
+                let next = self.new_block();
+                let exit = self.new_block();
+                match cond {
+                    Some((cond, cond_span)) => self.terminate(
+                        Terminator::Branch {
+                            cond,
+                            then: body_block,
+                            otherwise: exit,
+                        },
+                        cond_span,
+                    ),
+                    None => self.terminate(Terminator::Jump(body_block), span),
+                }
+                self.current = Some(body_block);
+                self.loop_body(body, next, exit, span)?;
+                // `continue` still runs the step
+                self.current = Some(next);
+                if let Some(step) = step {
+                    self.statement(step)?;
+                }
+                self.seal(Terminator::Jump(header), span);
+                self.current = Some(exit);
+                self.scopes.pop();
+            }
+            StatementKind::ForInStatement {
+                variable,
+                iterable,
+                body,
+            } => self.for_in(variable, iterable, body, span)?,
+            StatementKind::ReturnStatement { value } => {
+                let value = self.expression(value)?;
+                self.terminate(Terminator::Return(value), span);
+            }
+            StatementKind::ExpressionStatement { expr } => {
+                self.expression(expr)?;
+            }
+            StatementKind::BreakStatement | StatementKind::ContinueStatement => {
+                let target = self.loops.last().map(|targets| match statement.kind {
+                    StatementKind::BreakStatement => targets.exit,
+                    _ => targets.next,
+                });
+                // Outside of a loop they end the function, as they do in the
+                // interpreter
+                let terminator = match target {
+                    Some(target) => Terminator::Jump(target),
+                    None => Terminator::Return(Operand::Const(Value::Void)),
+                };
+                self.terminate(terminator, span);
+            }
+            StatementKind::PrintStatement { args } => {
+                let args = self.expressions(args)?;
+                self.emit(Instruction::Print { args }, span);
+            }
+        }
+        Ok(())
+    }
+
+    fn loop_body(
+        &mut self,
+        body: &[Statement],
+        next: BlockId,
+        exit: BlockId,
+        span: Span,
+    ) -> Lowered<()> {
+        self.loops.push(LoopTargets { next, exit });
+        let result = self.block(body);
+        self.loops.pop();
+        result?;
+        self.seal(Terminator::Jump(next), span);
+        Ok(())
+    }
+
+    fn if_statement(
+        &mut self,
+        cond: &Expression,
+        if_then: &[Statement],
+        else_then: Option<&[Statement]>,
+    ) -> Lowered<()> {
+        let cond_value = self.expression(cond)?;
+        let then = self.new_block();
+        let Some(else_then) = else_then else {
+            let join = self.new_block();
+            self.terminate(
+                Terminator::Branch {
+                    cond: cond_value,
+                    then,
+                    otherwise: join,
+                },
+                cond.span,
+            );
+            self.current = Some(then);
+            self.block(if_then)?;
+            self.seal(Terminator::Jump(join), cond.span);
+            self.current = Some(join);
+            return Ok(());
+        };
+
+        let otherwise = self.new_block();
+        self.terminate(
+            Terminator::Branch {
+                cond: cond_value,
+                then,
+                otherwise,
+            },
+            cond.span,
+        );
+        self.current = Some(then);
+        self.block(if_then)?;
+        let then_end = self.current.take();
+        self.current = Some(otherwise);
+        self.block(else_then)?;
+        let else_end = self.current.take();
+
+        // Only join the branches back up if one of them doesn't return
+        let ends: Vec<_> = then_end.into_iter().chain(else_end).collect();
+        if !ends.is_empty() {
+            let join = self.new_block();
+            for end in ends {
+                self.blocks[end.0 as usize].terminator =
+                    Some(Spanned::new(Terminator::Jump(join), cond.span));
+            }
+            self.current = Some(join);
+        }
+        Ok(())
+    }
+
+    // The loop copies the array first, so appending to it inside the body
+    // doesn't change what is looped over.
+    fn for_in(
+        &mut self,
+        variable: &Spanned<Token>,
+        iterable: &Expression,
+        body: &[Statement],
+        span: Span,
+    ) -> Lowered<()> {
+        let array = self.expression(iterable)?;
+        let snapshot = self.temporary(iterable.span);
+        self.emit(
+            Instruction::Copy {
+                dest: snapshot,
+                value: array,
+            },
+            iterable.span,
+        );
+        let position = self.temporary(span);
+        self.emit(
+            Instruction::Copy {
+                dest: position,
+                value: Operand::Const(Value::Integer(0)),
+            },
+            span,
+        );
+
+        let header = self.new_block();
+        self.seal(Terminator::Jump(header), span);
+        self.current = Some(header);
+        let length = self.temporary(span);
+        self.emit(
+            Instruction::Length {
+                dest: length,
+                value: Operand::Var(snapshot),
+            },
+            span,
+        );
+        let more = self.temporary(span);
+        self.emit(
+            Instruction::Binary {
+                dest: more,
+                operator: BinaryOp::Less,
+                left: Operand::Var(position),
+                right: Operand::Var(length),
+            },
+            span,
+        );
+        let body_block = self.new_block();
+        let exit = self.new_block();
+        self.terminate(
+            Terminator::Branch {
+                cond: Operand::Var(more),
+                then: body_block,
+                otherwise: exit,
+            },
+            span,
+        );
+
+        self.current = Some(body_block);
+        self.scopes.push(HashMap::new());
+        let element = self.declare(
+            identifier_name(&variable.node, variable.span)?,
+            variable.span,
+        );
+        self.emit(
+            Instruction::Index {
+                dest: element,
+                array: Operand::Var(snapshot),
+                index: Operand::Var(position),
+            },
+            span,
+        );
+        self.emit(
+            Instruction::Binary {
+                dest: position,
+                operator: BinaryOp::Add,
+                left: Operand::Var(position),
+                right: Operand::Const(Value::Integer(1)),
+            },
+            span,
+        );
+        let result = self.loop_body(body, header, exit, span);
+        self.scopes.pop();
+        result?;
+        self.current = Some(exit);
+        Ok(())
+    }
+
+    // Lowers `target = value`, or `target operator= value`. Storing into an
+    // element rebuilds each array on the way to it, innermost first.
+    fn assign(
+        &mut self,
+        target: &Expression,
+        operator: Option<BinaryOp>,
+        value: &Expression,
+        span: Span,
+    ) -> Lowered<()> {
+        let Some((root, accesses)) = target.place() else {
+            return Err(type_error(
+                "Cannot assign to this expression".to_string(),
+                target.span,
+            ));
+        };
+        let mut indices = Vec::with_capacity(accesses.len());
+        for access in &accesses {
+            if let ExpressionKind::AccessIndex { index, .. } = &access.kind {
+                indices.push((self.expression(index)?, access.span));
+            }
+        }
+        let value = self.expression(value)?;
+        let root = self.lookup(root, span)?;
+
+        // The arrays along the way, outermost first
+        let mut arrays = vec![Operand::Var(root)];
+        for (index, access_span) in &indices[..indices.len().saturating_sub(1)] {
+            let dest = self.temporary(*access_span);
+            let array = arrays.last().expect("starts with the root").clone();
+            self.emit(
+                Instruction::Index {
+                    dest,
+                    array,
+                    index: index.clone(),
+                },
+                *access_span,
+            );
+            arrays.push(Operand::Var(dest));
+        }
+
+        let Some((index, access_span)) = indices.pop() else {
+            let instruction = match operator {
+                Some(operator) => Instruction::Binary {
+                    dest: root,
+                    operator,
+                    left: Operand::Var(root),
+                    right: value,
+                },
+                None => Instruction::Copy { dest: root, value },
+            };
+            self.emit(instruction, span);
+            return Ok(());
+        };
+        let array = arrays.pop().expect("one array per index");
+        let mut value = match operator {
+            Some(operator) => {
+                let old = self.temporary(access_span);
+                self.emit(
+                    Instruction::Index {
+                        dest: old,
+                        array: array.clone(),
+                        index: index.clone(),
+                    },
+                    access_span,
+                );
+                let new = self.temporary(span);
+                self.emit(
+                    Instruction::Binary {
+                        dest: new,
+                        operator,
+                        left: Operand::Var(old),
+                        right: value,
+                    },
+                    span,
+                );
+                Operand::Var(new)
+            }
+            None => value,
+        };
+
+        let mut levels = vec![(array, index, access_span)];
+        while let Some((index, access_span)) = indices.pop() {
+            let array = arrays.pop().expect("one array per index");
+            levels.push((array, index, access_span));
+        }
+        let outermost = levels.len() - 1;
+        for (level, (array, index, access_span)) in levels.into_iter().enumerate() {
+            let dest = if level == outermost {
+                root
+            } else {
+                self.temporary(access_span)
+            };
+            self.emit(
+                Instruction::Insert {
+                    dest,
+                    array,
+                    index,
+                    value,
+                },
+                span,
+            );
+            value = Operand::Var(dest);
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, identifier: &Token, value: &Expression, span: Span) -> Lowered<()> {
+        let value = self.expression(value)?;
+        let array = self.lookup(identifier, span)?;
+        self.emit(
+            Instruction::Append {
+                dest: array,
+                array: Operand::Var(array),
+                value,
+            },
+            span,
+        );
+        Ok(())
+    }
+
+    fn expressions(&mut self, exprs: &[Expression]) -> Lowered<Vec<Operand>> {
+        exprs.iter().map(|expr| self.expression(expr)).collect()
+    }
+
+    fn expression(&mut self, expr: &Expression) -> Lowered<Operand> {
+        let span = expr.span;
+        let dest = match &expr.kind {
+            ExpressionKind::Token(token) => {
+                let value = match token {
+                    Token::Integer(n) => Value::Integer(*n),
+                    Token::Float(n) => Value::Float(*n),
+                    Token::Boolean(b) => Value::Boolean(*b),
+                    Token::String(s) => Value::String(s.clone()),
+                    _ => return Ok(Operand::Var(self.lookup(token, span)?)),
+                };
+                return Ok(Operand::Const(value));
+            }
+            ExpressionKind::Grouping(inner) => return self.expression(inner),
+            ExpressionKind::Format { parts } => {
+                let mut operands = Vec::with_capacity(parts.len());
+                for part in parts {
+                    operands.push(match part {
+                        FormatPart::Text(text) => Operand::Const(Value::String(text.clone())),
+                        FormatPart::Expr(inner) => self.expression(inner)?,
+                    });
+                }
+                let dest = self.temporary(span);
+                self.emit(
+                    Instruction::Format {
+                        dest,
+                        parts: operands,
+                    },
+                    span,
+                );
+                dest
+            }
+            ExpressionKind::ArrayDec { arr_expr } => {
+                let elements = self.expressions(arr_expr)?;
+                let dest = self.temporary(span);
+                self.emit(Instruction::Array { dest, elements }, span);
+                dest
+            }
+            ExpressionKind::AccessIndex { array, index } => {
+                let array = self.expression(array)?;
+                let index = self.expression(index)?;
+                let dest = self.temporary(span);
+                self.emit(Instruction::Index { dest, array, index }, span);
+                dest
+            }
+            ExpressionKind::GetLength { ident } => {
+                let value = Operand::Var(self.lookup(ident, span)?);
+                let dest = self.temporary(span);
+                self.emit(Instruction::Length { dest, value }, span);
+                dest
+            }
+            ExpressionKind::ArrayAppend { array, value } => {
+                self.append(array, value, span)?;
+                return Ok(Operand::Const(Value::Void));
+            }
+            ExpressionKind::FunctionCall { identifier, args } => {
+                let args = self.expressions(args)?;
+                let function = identifier_name(identifier, span)?.to_string();
+                let dest = self.temporary(span);
+                self.emit(
+                    Instruction::Call {
+                        dest,
+                        function,
+                        args,
+                    },
+                    span,
+                );
+                dest
+            }
+            ExpressionKind::Unary { operator, operand } => {
+                let operand = self.expression(operand)?;
+                let dest = self.temporary(span);
+                let operator = UnaryOp::from_token(operator).expect("the parser's unary operators");
+                self.emit(
+                    Instruction::Unary {
+                        dest,
+                        operator,
+                        operand,
+                    },
+                    span,
+                );
+                dest
+            }
+            ExpressionKind::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let operator =
+                    BinaryOp::from_token(operator).expect("the parser's binary operators");
+                let left = self.expression(left)?;
+                if let BinaryOp::And | BinaryOp::Or = operator {
+                    return self.short_circuit(operator, left, right, span);
+                }
+                let right = self.expression(right)?;
+                let dest = self.temporary(span);
+                self.emit(
+                    Instruction::Binary {
+                        dest,
+                        operator,
+                        left,
+                        right,
+                    },
+                    span,
+                );
+                dest
+            }
+        };
+        Ok(Operand::Var(dest))
+    }
+
+    // `&&` and `||` only evaluate their right side when the left side doesn't
+    // already decide the result.
+    fn short_circuit(
+        &mut self,
+        operator: BinaryOp,
+        left: Operand,
+        right: &Expression,
+        span: Span,
+    ) -> Lowered<Operand> {
+        let dest = self.temporary(span);
+        self.emit(
+            Instruction::Copy {
+                dest,
+                value: left.clone(),
+            },
+            span,
+        );
+        let rest = self.new_block();
+        let join = self.new_block();
+        let (then, otherwise) = match operator {
+            BinaryOp::And => (rest, join),
+            _ => (join, rest),
+        };
+        self.terminate(
+            Terminator::Branch {
+                cond: left,
+                then,
+                otherwise,
+            },
+            span,
+        );
+        self.current = Some(rest);
+        let value = self.expression(right)?;
+        self.emit(Instruction::Copy { dest, value }, span);
+        self.seal(Terminator::Jump(join), span);
+        self.current = Some(join);
+        Ok(Operand::Var(dest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diagnostic::codes, get_program, run_lexer};
+
+    fn lower(source: &str) -> Result<IrProgram, Diagnostic> {
+        let (tokens, _) = run_lexer(source).unwrap();
+        let program = get_program(&tokens).unwrap();
+        IrProgram::lower(&program)
+    }
+
+    fn dump(source: &str) -> String {
+        lower(source).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_while_loop() {
+        let source = "func main() {
+            let i = 0;
+            while (i < 10) {
+                i += 1;
+            }
+            return i;
+        }";
+        assert_eq!(
+            dump(source),
+            "func main():
+b0:
+    i = 0
+    jump b1
+b1:
+    %0 = i < 10
+    branch %0, b2, b3
+b2:
+    i = i + 1
+    jump b1
+b3:
+    return i
+"
+        );
+    }
+
+    #[test]
+    fn test_if_without_a_join_when_every_branch_returns() {
+        let source = "func sign(x) {
+            if (x < 0) {
+                return -1;
+            } else {
+                return 1;
+            }
+        }
+        func main() { let y = 0; if (true) { y = 1; } print(y); }";
+        assert_eq!(
+            dump(source),
+            "func sign(x):
+b0:
+    %0 = x < 0
+    branch %0, b1, b2
+b1:
+    %1 = -1
+    return %1
+b2:
+    return 1
+
+func main():
+b0:
+    y = 0
+    branch true, b1, b2
+b1:
+    y = 1
+    jump b2
+b2:
+    print(y)
+    return void
+"
+        );
+    }
+
+    #[test]
+    fn test_for_loops() {
+        let source = "func main() {
+            let total = 0;
+            for (let i = 0; i < 3; i += 1) {
+                if (i == 1) { continue; }
+                total += i;
+            }
+            for value in [4, 5] {
+                if (value == 5) { break; }
+            }
+            return total;
+        }";
+        assert_eq!(
+            dump(source),
+            "func main():
+b0:
+    total = 0
+    i = 0
+    jump b1
+b1:
+    %0 = i < 3
+    branch %0, b2, b4
+b2:
+    %1 = i == 1
+    branch %1, b5, b6
+b3:
+    i = i + 1
+    jump b1
+b4:
+    %2 = [4, 5]
+    %3 = %2
+    %4 = 0
+    jump b7
+b5:
+    jump b3
+b6:
+    total = total + i
+    jump b3
+b7:
+    %5 = length(%3)
+    %6 = %4 < %5
+    branch %6, b8, b9
+b8:
+    value = %3[%4]
+    %4 = %4 + 1
+    %7 = value == 5
+    branch %7, b10, b11
+b9:
+    return total
+b10:
+    jump b9
+b11:
+    jump b7
+"
+        );
+    }
+
+    #[test]
+    fn test_nested_element_assignment() {
+        let source = "func main() {
+            let grid = [[1]];
+            grid[0][0] *= 2;
+            grid.append([3]);
+        }";
+        assert_eq!(
+            dump(source),
+            "func main():
+b0:
+    %0 = [1]
+    %1 = [%0]
+    grid = %1
+    %2 = grid[0]
+    %3 = %2[0]
+    %4 = %3 * 2
+    %5 = insert(%2, 0, %4)
+    grid = insert(grid, 0, %5)
+    %6 = [3]
+    grid = append(grid, %6)
+    return void
+"
+        );
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        let source = "func main() { return false || 1 < 2; }";
+        assert_eq!(
+            dump(source),
+            "func main():
+b0:
+    %0 = false
+    branch false, b2, b1
+b1:
+    %1 = 1 < 2
+    %0 = %1
+    jump b2
+b2:
+    return %0
+"
+        );
+    }
+
+    #[test]
+    fn test_shadowed_variables_get_their_own_names() {
+        let source = r#"func main() {
+            let x = 1;
+            if (true) {
+                let x = x + 1.5;
+                print("${x}!");
+            }
+            let x = 2;
+            return x;
+        }"#;
+        let program = lower(source).unwrap();
+        let main = &program.functions[0];
+        let names: Vec<_> = main.vars.iter().map(|var| var.name.as_str()).collect();
+        assert_eq!(names, vec!["x", "%0", "x.1", "%1"]);
+        assert_eq!(main.vars[2].variable.as_deref(), Some("x"));
+        assert_eq!(main.vars[1].variable, None);
+        assert!(program.to_string().contains("%1 = format(x.1, \"!\")"));
+        // Declaring `x` again in the same scope reuses it
+        assert!(program.to_string().contains("    x = 2\n"));
+    }
+
+    #[test]
+    fn test_statements_after_return_start_an_unreachable_block() {
+        let source = "func main() {
+            return 1;
+            print(2);
+            break;
+        }";
+        let program = lower(source).unwrap();
+        let main = &program.functions[0];
+        assert_eq!(main.blocks.len(), 2);
+        assert_eq!(main.predecessors(), vec![vec![], vec![]]);
+        assert_eq!(
+            main.block(BlockId(1)).terminator,
+            Terminator::Return(Operand::Const(Value::Void))
+        );
+    }
+
+    #[test]
+    fn test_predecessors() {
+        let source = "func main() {
+            let i = 0;
+            while (i < 3) {
+                if (i == 1) { i += 2; } else { i += 1; }
+            }
+            return i;
+        }";
+        let program = lower(source).unwrap();
+        let main = &program.functions[0];
+        let ids = |ids: &[u32]| ids.iter().map(|&id| BlockId(id)).collect::<Vec<_>>();
+        assert_eq!(
+            main.predecessors(),
+            vec![
+                ids(&[]),
+                ids(&[0, 6]),
+                ids(&[1]),
+                ids(&[1]),
+                ids(&[2]),
+                ids(&[2]),
+                ids(&[4, 5]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_undeclared_variable() {
+        let err = lower("func main() { return missing; }").unwrap_err();
+        assert_eq!(err.code, codes::UNDEFINED_VARIABLE);
+        assert_eq!(err.message, "Undefined variable 'missing'");
+    }
+}
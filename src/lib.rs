@@ -1,31 +1,114 @@
+use bytecode::Module;
+use diagnostic::{Diagnostic, Severity};
 use function::print_function;
+use interpreter::{Interpreter, Value};
+use ir::IrProgram;
 use program::Program;
-use token::Token;
+use semantic::SemanticAnalyzer;
+use span::{Span, Spanned};
+use token::{comment_len, doc_comment_text, TemplatePart, Token};
+use vm::Vm;
+pub mod bytecode;
+pub mod diagnostic;
 pub mod expression;
 mod function;
+pub mod interpreter;
+pub mod ir;
+pub mod optimize;
 pub mod program;
 pub mod semantic;
+pub mod span;
+pub mod ssa;
 pub mod statement;
 pub mod symbol;
 pub mod token;
+pub mod vm;
 
-pub fn run_lexer(input: &str) -> Result<(Vec<Token>, usize), String> {
-    let mut remaining = input.trim();
+pub fn run_lexer(input: &str) -> Result<(Vec<Spanned<Token>>, usize), Diagnostic> {
+    lex(input, Span::new(0, 0, 1, 1))
+}
+
+// Lexes `input` as if it began at the start of `origin`, which is how the
+// code inside an interpolated string gets spans pointing into the string.
+fn lex(input: &str, origin: Span) -> Result<(Vec<Spanned<Token>>, usize), Diagnostic> {
     let mut tokens = Vec::new();
     let mut main_index = 0;
     let mut curr_index = 0;
+    let mut offset = 0;
+    let mut line = origin.line;
+    let mut column = origin.column;
+    // Offsets are counted within `input`, spans within the whole source
+    let at = |start: usize, end: usize, line: usize, column: usize| {
+        Span::new(origin.start + start, origin.start + end, line, column)
+    };
+    // Doc comment lines seen since the last token, and the span covering them
+    let mut doc: Vec<&str> = Vec::new();
+    let mut doc_span = Span::default();
+
+    loop {
+        let rest = &input[offset..];
+        let remaining = rest.trim_start();
+        advance(
+            &rest[..rest.len() - remaining.len()],
+            &mut line,
+            &mut column,
+        );
+        offset = input.len() - remaining.len();
+        if remaining.is_empty() {
+            break;
+        }
+
+        // Comments are skipped like whitespace
+        match comment_len(remaining) {
+            Ok(Some(len)) => {
+                let comment = &remaining[..len];
+                if let Some(text) = doc_comment_text(comment) {
+                    if doc.is_empty() {
+                        doc_span = at(offset, offset + len, line, column);
+                    }
+                    doc_span.end = origin.start + offset + len;
+                    doc.push(text);
+                }
+                advance(comment, &mut line, &mut column);
+                offset += len;
+                continue;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                let span = at(offset, offset + err.len, line, column);
+                return Err(Diagnostic::error(err.code, err.message, span));
+            }
+        }
 
-    while !remaining.is_empty() {
         match Token::new(remaining) {
-            Ok((token, rest)) => {
+            Ok((mut token, rest)) => {
+                // Doc comments are only kept when they document a function
+                if token == Token::Func && !doc.is_empty() {
+                    let comment = Token::DocComment(doc.join("\n"));
+                    tokens.push(Spanned::new(comment, doc_span));
+                    curr_index += 1;
+                }
+                doc.clear();
                 // If the token is `Main`, record its index.
                 if token == Token::Main {
                     main_index = curr_index;
                 }
-                tokens.push(token);
-                remaining = rest.trim();
+                let end = input.len() - rest.len();
+                let span = at(offset, end, line, column);
+                if let Token::Template(parts) = &mut token {
+                    rebase_template(parts, span);
+                }
+                tokens.push(Spanned::new(token, span));
+                advance(&input[offset..end], &mut line, &mut column);
+                offset = end;
+            }
+            Err(err) => {
+                // The error may start partway into the token, even on a later line
+                advance(&remaining[..err.offset], &mut line, &mut column);
+                let start = offset + err.offset;
+                let span = at(start, start + err.len, line, column);
+                return Err(Diagnostic::error(err.code, err.message, span));
             }
-            Err(err) => return Err(err),
         }
         curr_index += 1;
     }
@@ -33,7 +116,34 @@ pub fn run_lexer(input: &str) -> Result<(Vec<Token>, usize), String> {
     Ok((tokens, main_index))
 }
 
-pub fn get_program(input: &Vec<Token>) -> Result<Program, String> {
+// Template code is lexed with spans relative to its string literal, as if
+// the literal started the source. Moves them to where the literal really is.
+fn rebase_template(parts: &mut [TemplatePart], literal: Span) {
+    for part in parts {
+        if let TemplatePart::Code(tokens) = part {
+            for token in tokens {
+                token.span = token.span.relative_to(literal);
+                if let Token::Template(nested) = &mut token.node {
+                    rebase_template(nested, literal);
+                }
+            }
+        }
+    }
+}
+
+// Moves a 1-based line/column position past `text`.
+fn advance(text: &str, line: &mut usize, column: &mut usize) {
+    for c in text.chars() {
+        if c == '\n' {
+            *line += 1;
+            *column = 1;
+        } else {
+            *column += 1;
+        }
+    }
+}
+
+pub fn get_program(input: &[Spanned<Token>]) -> Result<Program, Vec<Diagnostic>> {
     Program::new(input)
 }
 
@@ -42,3 +152,41 @@ pub fn print_ast(program: &Program) {
         print_function(function);
     }
 }
+
+pub fn analyze_program(program: Program) -> Result<(Program, Vec<Diagnostic>), Vec<Diagnostic>> {
+    let (program, mut diagnostics) = SemanticAnalyzer::new().analyze(program)?;
+    // The optimizer follows values and control flow, so it finds what
+    // analysis can't: a divisor that is always zero and variables that are
+    // never read
+    let mut ir = IrProgram::lower(&program).map_err(|err| vec![err])?;
+    diagnostics.extend(optimize_program(&mut ir));
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    if diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+    {
+        Err(diagnostics)
+    } else {
+        Ok((program, diagnostics))
+    }
+}
+
+pub fn run_program(program: &Program) -> Result<Value, Diagnostic> {
+    Interpreter::new(program).run()
+}
+
+pub fn lower_program(program: &Program) -> Result<IrProgram, Diagnostic> {
+    IrProgram::lower(program)
+}
+
+pub fn optimize_program(program: &mut IrProgram) -> Vec<Diagnostic> {
+    optimize::optimize(program)
+}
+
+pub fn compile_program(program: &Program) -> Module {
+    Module::compile(program)
+}
+
+pub fn run_module(module: &Module) -> Result<Value, Diagnostic> {
+    Vm::new(module).run()
+}
@@ -1,35 +1,213 @@
-use std::{env, fs};
+use std::{env, fs, process};
 
-use elden::{get_program, print_ast, run_lexer};
+use elden::{
+    analyze_program, compile_program, get_program,
+    interpreter::{with_call_stack, Value},
+    lower_program, optimize_program, print_ast, run_lexer, run_module, run_program, ssa,
+};
+
+// The exit code when the script can't be compiled or fails while running
+const FAILURE: i32 = 1;
+
+const USAGE: &str =
+    "Usage: elden [--emit=tokens|ast|ir|ssa|opt|bytecode] [--engine=ast|vm] [script]";
+const EMIT_KINDS: [&str; 6] = ["tokens", "ast", "ir", "ssa", "opt", "bytecode"];
+const ENGINES: [&str; 2] = ["ast", "vm"];
+
+// What the command line asks for
+#[derive(Debug, PartialEq)]
+struct Options {
+    emit: Option<String>,
+    engine: Option<String>,
+    program_file: String,
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let options = match parse_args(&args[1..]) {
+        Ok(options) => options,
+        Err(message) => usage_error(&message),
+    };
+    with_call_stack(|| {
+        run_file(
+            &options.program_file,
+            options.emit.as_deref(),
+            options.engine.as_deref(),
+        )
+    });
+}
 
-    if args.len() < 2 {
-        panic!("No file found. Usage: elden [script]");
+// Reads the flags and the one script to run, or says what's wrong with them.
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut emit = None;
+    let mut engine = None;
+    let mut program_file = None;
+    for arg in args {
+        if let Some(kind) = arg.strip_prefix("--emit=") {
+            if !EMIT_KINDS.contains(&kind) {
+                return Err(format!("Unknown --emit kind '{}'", kind));
+            }
+            emit = Some(kind.to_string());
+        } else if let Some(kind) = arg.strip_prefix("--engine=") {
+            if !ENGINES.contains(&kind) {
+                return Err(format!("Unknown --engine '{}'", kind));
+            }
+            engine = Some(kind.to_string());
+        } else if arg.starts_with('-') {
+            return Err(format!("Unknown option '{}'", arg));
+        } else if let Some(first) = &program_file {
+            return Err(format!(
+                "Only one script can be run, found '{}' and '{}'",
+                first, arg
+            ));
+        } else {
+            program_file = Some(arg.clone());
+        }
     }
 
-    let program_file = &args[1];
-    run_program(program_file);
+    let program_file = program_file.ok_or("No file found")?;
+    Ok(Options {
+        emit,
+        engine,
+        program_file,
+    })
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}. {}", message, USAGE);
+    process::exit(FAILURE);
 }
 
-fn run_program(program_file: &str) {
+fn run_file(program_file: &str, emit: Option<&str>, engine: Option<&str>) {
     let contents =
         fs::read_to_string(program_file).expect("Should have been able to read the file");
     let tokens = match run_lexer(&contents) {
         Ok(output) => output.0,
         Err(err) => {
-            println!("{}", err);
-            return;
+            eprint!("{}", err.render(&contents, program_file));
+            process::exit(FAILURE);
         }
     };
-    println!("Tokens: {:?}", tokens);
+    if emit == Some("tokens") {
+        let tokens: Vec<_> = tokens.iter().map(|token| &token.node).collect();
+        println!("Tokens: {:?}", tokens);
+        return;
+    }
     let program = match get_program(&tokens) {
         Ok(output) => output,
-        Err(err) => {
-            println!("{}", err);
+        Err(errors) => {
+            for err in errors {
+                eprint!("{}", err.render(&contents, program_file));
+            }
+            process::exit(FAILURE);
+        }
+    };
+    if emit == Some("ast") {
+        print_ast(&program);
+        return;
+    }
+    let program = match analyze_program(program) {
+        Ok((program, warnings)) => {
+            for warning in warnings {
+                eprint!("{}", warning.render(&contents, program_file));
+            }
+            program
+        }
+        Err(errors) => {
+            for err in errors {
+                eprint!("{}", err.render(&contents, program_file));
+            }
+            process::exit(FAILURE);
+        }
+    };
+    if let Some(kind @ ("ir" | "ssa" | "opt")) = emit {
+        match lower_program(&program) {
+            Ok(mut ir) => {
+                if kind == "ssa" {
+                    ir.functions.iter_mut().for_each(ssa::construct);
+                } else if kind == "opt" {
+                    // Anything it finds was reported by analysis
+                    optimize_program(&mut ir);
+                }
+                print!("{}", ir)
+            }
+            Err(err) => {
+                eprint!("{}", err.render(&contents, program_file));
+                process::exit(FAILURE);
+            }
+        }
+        return;
+    }
+    let result = if emit == Some("bytecode") || engine == Some("vm") {
+        let module = compile_program(&program);
+        if emit == Some("bytecode") {
+            print!("{}", module);
             return;
         }
+        run_module(&module)
+    } else {
+        run_program(&program)
     };
-    print_ast(&program);
+    match result {
+        // An integer returned from `main` becomes the exit code, anything else
+        // is printed. Only the low bits of it ever reach the OS.
+        Ok(Value::Integer(code)) => process::exit(code as i32),
+        Ok(Value::Void) => {}
+        Ok(value) => println!("{}", value),
+        Err(err) => {
+            eprint!("{}", err.render(&contents, program_file));
+            process::exit(FAILURE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        parse_args(&args)
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse(&["--engine=vm", "prog.el", "--emit=opt"]),
+            Ok(Options {
+                emit: Some("opt".to_string()),
+                engine: Some("vm".to_string()),
+                program_file: "prog.el".to_string(),
+            })
+        );
+        assert_eq!(parse(&[]), Err("No file found".to_string()));
+        assert_eq!(
+            parse(&["--emit=bogus", "prog.el"]),
+            Err("Unknown --emit kind 'bogus'".to_string())
+        );
+        assert_eq!(
+            parse(&["--engine=jit", "prog.el"]),
+            Err("Unknown --engine 'jit'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_unknown_options_are_rejected() {
+        assert_eq!(
+            parse(&["--foo", "prog.el"]),
+            Err("Unknown option '--foo'".to_string())
+        );
+        assert_eq!(
+            parse(&["prog.el", "--emit"]),
+            Err("Unknown option '--emit'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_extra_scripts_are_rejected() {
+        assert_eq!(
+            parse(&["a.el", "b.el"]),
+            Err("Only one script can be run, found 'a.el' and 'b.el'".to_string())
+        );
+    }
 }
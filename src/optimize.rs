@@ -0,0 +1,704 @@
+use std::collections::HashSet;
+
+use crate::{
+    bytecode::{same_constant, BinaryOp, UnaryOp},
+    diagnostic::{codes, Diagnostic},
+    interpreter::{binary_op, unary_op, Value},
+    ir::{BlockId, Instruction, IrFunction, IrProgram, Operand, Terminator, Var},
+    span::Span,
+    ssa::{self, vars, Dominators, Liveness},
+};
+
+/// Optimizes every function of a program and returns what the optimizer
+/// found wrong with it along the way.
+pub fn optimize(program: &mut IrProgram) -> Vec<Diagnostic> {
+    program
+        .functions
+        .iter_mut()
+        .flat_map(optimize_function)
+        .collect()
+}
+
+/// Runs the optimizations on a function, going through SSA form and back.
+/// The diagnostics come in source order.
+pub fn optimize_function(function: &mut IrFunction) -> Vec<Diagnostic> {
+    let mut diagnostics = unused_variables(function);
+    ssa::construct(function);
+    diagnostics.extend(fold_constants(function));
+    remove_unreachable_blocks(function);
+    ssa::destruct(function);
+    eliminate_dead_stores(function);
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    diagnostics
+}
+
+/// Warns about the source variables of a function that are assigned but
+/// never read. Parameters are left out, since callers have to pass them.
+pub fn unused_variables(function: &IrFunction) -> Vec<Diagnostic> {
+    let mut read = HashSet::new();
+    for block in &function.blocks {
+        for instruction in &block.instructions {
+            read.extend(vars(instruction.node.operands()));
+        }
+        read.extend(vars(block.terminator.node.operands()));
+    }
+    (0..function.vars.len() as u32)
+        .map(Var)
+        .filter(|var| !read.contains(var) && !function.params.contains(var))
+        .filter_map(|var| {
+            let info = function.var(var);
+            let name = info.variable.as_ref()?;
+            Some(Diagnostic::warning(
+                codes::UNUSED_VARIABLE,
+                format!("Unused variable '{}'", name),
+                info.span,
+            ))
+        })
+        .collect()
+}
+
+/// Drops the blocks control can't reach from the entry and renumbers the
+/// rest, keeping them in order.
+pub fn remove_unreachable_blocks(function: &mut IrFunction) {
+    let dominators = Dominators::new(function);
+    let mut renumbered = Vec::with_capacity(function.blocks.len());
+    let mut count = 0;
+    for block in function.block_ids() {
+        renumbered.push(dominators.is_reachable(block).then(|| {
+            count += 1;
+            BlockId(count - 1)
+        }));
+    }
+    let new_id = |block: BlockId| renumbered[block.0 as usize];
+
+    let blocks = std::mem::take(&mut function.blocks);
+    for (index, mut block) in blocks.into_iter().enumerate() {
+        if renumbered[index].is_none() {
+            continue;
+        }
+        for instruction in &mut block.instructions {
+            if let Instruction::Phi { sources, .. } = &mut instruction.node {
+                sources.retain_mut(|(from, _)| match new_id(*from) {
+                    Some(id) => {
+                        *from = id;
+                        true
+                    }
+                    None => false,
+                });
+            }
+        }
+        for target in block.terminator.node.successors_mut() {
+            *target = new_id(*target).expect("a reachable block only leads to reachable ones");
+        }
+        function.blocks.push(block);
+    }
+}
+
+/// Removes assignments whose value is never read, as long as working it out
+/// can't fail or do anything else. The function must be out of SSA form.
+pub fn eliminate_dead_stores(function: &mut IrFunction) {
+    // Removing one assignment can leave the ones it read from dead too
+    let mut removed = true;
+    while removed {
+        removed = false;
+        let liveness = Liveness::new(function);
+        for id in function.block_ids().collect::<Vec<_>>() {
+            let block = function.block_mut(id);
+            let mut live = liveness.live_out[id.0 as usize].clone();
+            live.extend(vars(block.terminator.node.operands()));
+            let mut kept = Vec::with_capacity(block.instructions.len());
+            for instruction in block.instructions.drain(..).rev() {
+                if let Some(dest) = instruction.node.dest() {
+                    if !live.remove(&dest) && is_pure(&instruction.node) {
+                        removed = true;
+                        continue;
+                    }
+                }
+                live.extend(vars(instruction.node.operands()));
+                kept.push(instruction);
+            }
+            kept.reverse();
+            block.instructions = kept;
+        }
+    }
+}
+
+// Whether an instruction only works out its result and can't fail to.
+// Analysis has checked the types, so what's left to fail is overflow,
+// division by zero, shifting too far and indexing out of bounds.
+fn is_pure(instruction: &Instruction) -> bool {
+    match instruction {
+        Instruction::Copy { .. }
+        | Instruction::Array { .. }
+        | Instruction::Format { .. }
+        | Instruction::Length { .. }
+        | Instruction::Append { .. }
+        | Instruction::Phi { .. } => true,
+        Instruction::Unary { operator, .. } => *operator != UnaryOp::Negate,
+        Instruction::Binary { operator, .. } => !matches!(
+            operator,
+            BinaryOp::Add
+                | BinaryOp::Sub
+                | BinaryOp::Mul
+                | BinaryOp::Div
+                | BinaryOp::Mod
+                | BinaryOp::ShiftLeft
+                | BinaryOp::ShiftRight
+        ),
+        Instruction::Call { .. }
+        | Instruction::Index { .. }
+        | Instruction::Insert { .. }
+        | Instruction::Print { .. } => false,
+    }
+}
+
+// What is known about a variable's value. It only ever moves down, from
+// `Unknown` through `Constant` to `Varying`, which is why the analysis ends.
+#[derive(Debug, Clone)]
+enum Lattice {
+    // Nothing assigns it on any path found so far
+    Unknown,
+    Constant(Value),
+    Varying,
+}
+
+impl Lattice {
+    fn meet(&self, other: &Lattice) -> Lattice {
+        match (self, other) {
+            (Lattice::Unknown, other) | (other, Lattice::Unknown) => other.clone(),
+            (Lattice::Constant(a), Lattice::Constant(b)) if same_constant(a, b) => self.clone(),
+            _ => Lattice::Varying,
+        }
+    }
+
+    fn same(&self, other: &Lattice) -> bool {
+        match (self, other) {
+            (Lattice::Unknown, Lattice::Unknown) | (Lattice::Varying, Lattice::Varying) => true,
+            (Lattice::Constant(a), Lattice::Constant(b)) => same_constant(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// Sparse conditional constant propagation on a function in SSA form. Every
+/// variable that holds the same value whenever it is assigned is replaced by
+/// that value, following only the branches that can be taken given the
+/// values known so far. A branch on a constant becomes a jump, so the blocks
+/// it skips can no longer be reached.
+///
+/// Operations that would fail at runtime are left alone. A division or
+/// modulo by a constant zero is reported as an error.
+pub fn fold_constants(function: &mut IrFunction) -> Vec<Diagnostic> {
+    let mut propagation = Propagation::new(function);
+    propagation.run(function);
+    let diagnostics = propagation.check(function);
+    propagation.rewrite(function);
+    diagnostics
+}
+
+struct Propagation {
+    values: Vec<Lattice>,
+    // The edges control can take, and the blocks they lead to
+    edges: HashSet<(BlockId, BlockId)>,
+    executable: Vec<bool>,
+    // The reachable blocks, in an order that sees most assignments before
+    // their reads
+    order: Vec<BlockId>,
+}
+
+impl Propagation {
+    fn new(function: &IrFunction) -> Self {
+        let mut values = vec![Lattice::Unknown; function.vars.len()];
+        for &param in &function.params {
+            values[param.0 as usize] = Lattice::Varying;
+        }
+        let mut executable = vec![false; function.blocks.len()];
+        executable[IrFunction::ENTRY.0 as usize] = true;
+        Propagation {
+            values,
+            edges: HashSet::new(),
+            executable,
+            order: Dominators::new(function).reverse_postorder().to_vec(),
+        }
+    }
+
+    fn value(&self, operand: &Operand) -> Lattice {
+        match operand {
+            Operand::Var(var) => self.values[var.0 as usize].clone(),
+            Operand::Const(value) => Lattice::Constant(value.clone()),
+        }
+    }
+
+    // Visits the executable blocks until nothing new is learned.
+    fn run(&mut self, function: &IrFunction) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &id in &self.order {
+                if !self.executable[id.0 as usize] {
+                    continue;
+                }
+                let block = function.block(id);
+                for instruction in &block.instructions {
+                    let Some(dest) = instruction.node.dest() else {
+                        continue;
+                    };
+                    let value = self.evaluate(id, &instruction.node, instruction.span);
+                    let slot = &mut self.values[dest.0 as usize];
+                    if !slot.same(&value) {
+                        *slot = value;
+                        changed = true;
+                    }
+                }
+                for target in self.targets(&block.terminator.node) {
+                    if self.edges.insert((id, target)) {
+                        self.executable[target.0 as usize] = true;
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+
+    fn evaluate(&self, block: BlockId, instruction: &Instruction, span: Span) -> Lattice {
+        match instruction {
+            Instruction::Copy { value, .. } => self.value(value),
+            Instruction::Unary {
+                operator, operand, ..
+            } => match self.value(operand) {
+                Lattice::Constant(value) => match unary_op(&operator.token(), value, span) {
+                    Ok(value) => Lattice::Constant(value),
+                    Err(_) => Lattice::Varying,
+                },
+                other => other,
+            },
+            Instruction::Binary {
+                operator,
+                left,
+                right,
+                ..
+            } => match (self.value(left), self.value(right)) {
+                (Lattice::Constant(left), Lattice::Constant(right)) => {
+                    match binary_op(&operator.token(), left, right, span) {
+                        Ok(value) => Lattice::Constant(value),
+                        Err(_) => Lattice::Varying,
+                    }
+                }
+                (Lattice::Varying, _) | (_, Lattice::Varying) => Lattice::Varying,
+                _ => Lattice::Unknown,
+            },
+            // Only the edges control has taken so far count
+            Instruction::Phi { sources, .. } => sources
+                .iter()
+                .filter(|(from, _)| self.edges.contains(&(*from, block)))
+                .fold(Lattice::Unknown, |known, (_, value)| {
+                    known.meet(&self.value(value))
+                }),
+            _ => Lattice::Varying,
+        }
+    }
+
+    // The successors control can go to given what is known about the
+    // condition.
+    fn targets(&self, terminator: &Terminator) -> Vec<BlockId> {
+        match terminator {
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } => match self.value(cond) {
+                Lattice::Unknown => Vec::new(),
+                Lattice::Constant(Value::Boolean(true)) => vec![*then],
+                Lattice::Constant(Value::Boolean(false)) => vec![*otherwise],
+                _ => vec![*then, *otherwise],
+            },
+            terminator => terminator.successors(),
+        }
+    }
+
+    // Finds the divisions that always divide by zero. The right side being
+    // zero is enough, since analysis made sure both sides are integers.
+    fn check(&self, function: &IrFunction) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        for &id in &self.order {
+            if !self.executable[id.0 as usize] {
+                continue;
+            }
+            for instruction in &function.block(id).instructions {
+                if let Instruction::Binary {
+                    operator: operator @ (BinaryOp::Div | BinaryOp::Mod),
+                    right,
+                    ..
+                } = &instruction.node
+                {
+                    if let Lattice::Constant(Value::Integer(0)) = self.value(right) {
+                        let what = match operator {
+                            BinaryOp::Div => "Division",
+                            _ => "Modulo",
+                        };
+                        diagnostics.push(
+                            Diagnostic::error(
+                                codes::CONSTANT_DIVISION_BY_ZERO,
+                                format!("{} by zero", what),
+                                instruction.span,
+                            )
+                            .with_note("the right-hand side is always 0"),
+                        );
+                    }
+                }
+            }
+        }
+        diagnostics
+    }
+
+    // Replaces constant variables by their values and drops what control
+    // can no longer reach.
+    fn rewrite(&self, function: &mut IrFunction) {
+        let constant = |var: Var| match &self.values[var.0 as usize] {
+            Lattice::Constant(value) => Some(value.clone()),
+            _ => None,
+        };
+        for id in function.block_ids().collect::<Vec<_>>() {
+            let block = function.block_mut(id);
+            block.instructions.retain(|instruction| {
+                instruction
+                    .node
+                    .dest()
+                    .is_none_or(|dest| constant(dest).is_none())
+            });
+            for instruction in &mut block.instructions {
+                for operand in instruction.node.operands_mut() {
+                    if let Some(value) = operand_constant(operand, constant) {
+                        *operand = Operand::Const(value);
+                    }
+                }
+                if let Instruction::Phi { dest, sources } = &instruction.node {
+                    let sources: Vec<_> = sources
+                        .iter()
+                        .filter(|(from, _)| self.edges.contains(&(*from, id)))
+                        .cloned()
+                        .collect();
+                    instruction.node = match <[_; 1]>::try_from(sources) {
+                        Ok([(_, value)]) => Instruction::Copy { dest: *dest, value },
+                        Err(sources) => Instruction::Phi {
+                            dest: *dest,
+                            sources,
+                        },
+                    };
+                }
+            }
+            for operand in block.terminator.node.operands_mut() {
+                if let Some(value) = operand_constant(operand, constant) {
+                    *operand = Operand::Const(value);
+                }
+            }
+            if !self.executable[id.0 as usize] {
+                continue;
+            }
+            if let Terminator::Branch {
+                then, otherwise, ..
+            } = block.terminator.node
+            {
+                match (
+                    self.edges.contains(&(id, then)),
+                    self.edges.contains(&(id, otherwise)),
+                ) {
+                    (true, false) => block.terminator.node = Terminator::Jump(then),
+                    (false, true) => block.terminator.node = Terminator::Jump(otherwise),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+fn operand_constant(operand: &Operand, constant: impl Fn(Var) -> Option<Value>) -> Option<Value> {
+    match operand {
+        Operand::Var(var) => constant(*var),
+        Operand::Const(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analyze_program, get_program, run_lexer};
+
+    fn optimized(source: &str) -> (IrProgram, Vec<Diagnostic>) {
+        let (tokens, _) = run_lexer(source).unwrap();
+        let program = get_program(&tokens).unwrap();
+        let mut program = IrProgram::lower(&program).unwrap();
+        let diagnostics = optimize(&mut program);
+        (program, diagnostics)
+    }
+
+    fn dump(source: &str) -> String {
+        let (program, diagnostics) = optimized(source);
+        assert_eq!(diagnostics, Vec::new());
+        program.to_string()
+    }
+
+    #[test]
+    fn test_folds_expressions() {
+        let source = "func main() { return 1 * 2 - 4 % 6; }";
+        assert_eq!(dump(source), "func main():\nb0:\n    return -2\n");
+        let source = "func main() { return !(1 < 2) || -2.5 * 2.0 == -5.0 && ~0 == -1; }";
+        assert!(dump(source).contains("return true"));
+    }
+
+    #[test]
+    fn test_folds_expression_example() {
+        // The same steps as `--emit=opt`
+        let (tokens, _) = run_lexer(include_str!("../examples/expression.el")).unwrap();
+        let (program, diagnostics) = analyze_program(get_program(&tokens).unwrap()).unwrap();
+        assert_eq!(diagnostics, Vec::new());
+        let mut program = IrProgram::lower(&program).unwrap();
+        assert_eq!(optimize(&mut program), Vec::new());
+        assert!(program
+            .to_string()
+            .ends_with("    print(%8)\n    return -2\n"));
+    }
+
+    #[test]
+    fn test_propagates_let_values() {
+        let source = "func main(x) {
+            let a = 6;
+            let b = a * 7;
+            let c = b + x;
+            print(b, c);
+            return b;
+        }";
+        assert_eq!(
+            dump(source),
+            "func main(x):
+b0:
+    %1 = 42 + x
+    c = %1
+    print(42, c)
+    return 42
+"
+        );
+    }
+
+    #[test]
+    fn test_constant_branches_become_jumps() {
+        let source = "func main() {
+            let y = 0;
+            if (true) {
+                y = 1;
+            } else {
+                y = 2;
+            }
+            while (false) {
+                y += 1;
+            }
+            return y;
+        }";
+        let (program, _) = optimized(source);
+        let function = &program.functions[0];
+        for block in &function.blocks {
+            assert!(!matches!(block.terminator.node, Terminator::Branch { .. }));
+        }
+        assert!(function
+            .blocks
+            .iter()
+            .any(|block| block.terminator.node
+                == Terminator::Return(Operand::Const(Value::Integer(1)))));
+    }
+
+    #[test]
+    fn test_values_meeting_from_branches() {
+        // Both branches agree, so `y` is still known after them
+        let source = "func main(x) {
+            let y = 0;
+            if (x) { y = 3; } else { y = 1 + 2; }
+            return y;
+        }";
+        assert!(dump(source).contains("return 3"));
+        // They don't here
+        let source = "func main(x) {
+            let y = 0;
+            if (x) { y = 3; }
+            return y;
+        }";
+        assert!(!dump(source).contains("return 3"));
+    }
+
+    #[test]
+    fn test_loops_are_not_folded_past_their_first_iteration() {
+        let source = "func main() {
+            let i = 0;
+            while (i < 10) {
+                i += 1;
+            }
+            return i;
+        }";
+        let output = dump(source);
+        assert!(output.contains("branch"), "{}", output);
+        assert!(output.contains("return i"), "{}", output);
+    }
+
+    #[test]
+    fn test_division_by_zero_is_reported_not_folded() {
+        let source = "func main(x) {
+            let zero = 3 - 3;
+            print(x % zero);
+            return 1 / zero;
+        }";
+        let (program, diagnostics) = optimized(source);
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (codes::CONSTANT_DIVISION_BY_ZERO, "Modulo by zero"),
+                (codes::CONSTANT_DIVISION_BY_ZERO, "Division by zero"),
+            ]
+        );
+        assert!(program.to_string().contains("= 1 / 0"));
+    }
+
+    #[test]
+    fn test_division_by_zero_in_untaken_branch_is_fine() {
+        let source = "func main() {
+            if (false) { return 1 / 0; }
+            return 0;
+        }";
+        assert_eq!(optimized(source).1, Vec::new());
+    }
+
+    #[test]
+    fn test_failing_operations_are_left_alone() {
+        let source = "func main() { return 9223372036854775807 + 1; }";
+        assert!(dump(source).contains("9223372036854775807 + 1"));
+    }
+
+    #[test]
+    fn test_code_dead_from_propagated_values_is_removed_silently() {
+        // `x > y` is always false, and `x < y` always true
+        let (program, diagnostics) = optimized(include_str!("../examples/if.el"));
+        assert_eq!(diagnostics, Vec::new());
+        assert_eq!(
+            program.to_string(),
+            "func main():
+b0:
+    jump b1
+b1:
+    jump b2
+b2:
+    return -2
+"
+        );
+
+        let source = "func main() {
+            let debug = false;
+            if (debug) { print(1); }
+            return 0;
+        }";
+        assert!(!dump(source).contains("print"));
+    }
+
+    #[test]
+    fn test_unused_variable_warnings() {
+        let source = "func main(unused_param) {
+            let a = 1;
+            let b = 2;
+            let c = [];
+            c.append(b);
+            for x in [1, 2] {
+                print(a);
+            }
+            return 0;
+        }";
+        let (_, diagnostics) = optimized(source);
+        let messages: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.message.as_str()))
+            .collect();
+        assert_eq!(messages, [(codes::UNUSED_VARIABLE, "Unused variable 'x'")]);
+        // The warning points at the loop variable, not the whole loop
+        let span = diagnostics[0].span;
+        assert_eq!((span.line, span.column), (6, 17));
+        assert_eq!(&source[span.start..span.end], "x");
+    }
+
+    #[test]
+    fn test_dead_stores_are_removed() {
+        let source = "func main(x) {
+            let a = x == 1;
+            let b = [x, x];
+            let c = x + 1;
+            let d = f(x);
+            b = [a];
+            return 0;
+        }
+        func f(x) { return x; }";
+        let (program, _) = optimized(source);
+        assert_eq!(
+            program.functions[0].to_string(),
+            "func main(x):
+b0:
+    %2 = x + 1
+    %3 = call f(x)
+    return 0
+"
+        );
+    }
+
+    #[test]
+    fn test_stores_read_in_a_loop_are_kept() {
+        let source = "func main(n) {
+            let total = 0;
+            let i = 0;
+            while (i < n) {
+                total += i;
+                i += 1;
+            }
+            return total;
+        }";
+        // The constant starting values move to where the loop is entered
+        assert_eq!(
+            dump(source),
+            "func main(n):
+b0:
+    total.1 = 0
+    i.1 = 0
+    jump b1
+b1:
+    %0 = i.1 < n
+    branch %0, b2, b3
+b2:
+    total.2 = total.1 + i.1
+    i.2 = i.1 + 1
+    total.1 = total.2
+    i.1 = i.2
+    jump b1
+b3:
+    return total.1
+"
+        );
+    }
+
+    #[test]
+    fn test_unreachable_blocks_are_removed() {
+        let source = "func main() {
+            let y = 0;
+            if (false) { y = 1; } else { y = 2; }
+            return y;
+            print(y);
+        }";
+        let (program, _) = optimized(source);
+        let function = &program.functions[0];
+        let dominators = Dominators::new(function);
+        assert!(function
+            .block_ids()
+            .all(|block| dominators.is_reachable(block)));
+        assert_eq!(function.blocks.len(), 3);
+        assert_eq!(
+            function.blocks[2].terminator.node,
+            Terminator::Return(Operand::Const(Value::Integer(2)))
+        );
+    }
+}
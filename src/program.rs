@@ -1,4 +1,9 @@
-use crate::{function::Function, token::Token};
+use crate::{
+    diagnostic::{codes, Diagnostic},
+    function::Function,
+    span::Spanned,
+    token::Token,
+};
 
 #[derive(Debug)]
 pub struct Program {
@@ -6,20 +11,64 @@ pub struct Program {
 }
 
 impl Program {
-    pub fn new(tokens: &Vec<Token>) -> Result<Self, String> {
+    /// Parses a whole program, reporting every syntax error found.
+    pub fn new(tokens: &[Spanned<Token>]) -> Result<Self, Vec<Diagnostic>> {
+        let (program, errors) = Program::parse(tokens);
+        if errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parses as much of the program as possible. Functions with a malformed
+    /// header are skipped up to the next `func`; errors inside function bodies
+    /// are skipped statement by statement. Returns the partial program with
+    /// every error found along the way.
+    pub fn parse(tokens: &[Spanned<Token>]) -> (Self, Vec<Diagnostic>) {
         let mut functions = Vec::new();
+        let mut errors = Vec::new();
         let mut index = 0;
+        // A doc comment is only ever lexed right before a `func`
+        let mut doc = None;
 
         while index < tokens.len() {
-            match Function::new(tokens, index) {
-                Ok((func, consumed)) => {
+            if let Token::DocComment(text) = &tokens[index].node {
+                doc = Some(text.clone());
+                index += 1;
+                continue;
+            }
+            if tokens[index] != Token::Func {
+                errors.push(Diagnostic::error(
+                    codes::EXPECTED_FUNCTION,
+                    format!("Expected a function, found '{}'", tokens[index].node),
+                    tokens[index].span,
+                ));
+                index = next_function(tokens, index + 1);
+                continue;
+            }
+
+            match Function::parse(tokens, index, &mut errors) {
+                Ok((mut func, consumed)) => {
+                    func.doc = doc.take();
                     functions.push(func);
                     index += consumed;
                 }
-                Err(err) => return Err(format!("Error parsing function - {}", err)),
+                Err(err) => {
+                    errors.push(err);
+                    doc = None;
+                    index = next_function(tokens, index + 1);
+                }
             }
         }
 
-        Ok(Program { functions })
+        (Program { functions }, errors)
     }
 }
+
+// Index of the next `func` token, or the doc comment before it, at or after `start`.
+fn next_function(tokens: &[Spanned<Token>], start: usize) -> usize {
+    (start..tokens.len())
+        .find(|&i| matches!(tokens[i].node, Token::Func | Token::DocComment(_)))
+        .unwrap_or(tokens.len())
+}
@@ -1,15 +1,39 @@
+use std::fmt;
+
 use crate::{
+    diagnostic::{codes, with_article, Diagnostic, Severity},
+    expression::{Expression, ExpressionKind, FormatPart},
+    function::{function_name, Function},
     program::Program,
+    span::Span,
+    statement::{Statement, StatementKind},
     symbol::{DataType, Symbol, SymbolTable, SymbolType},
     token::Token,
 };
 
+// Inference runs over the whole program until no function symbol changes.
+// Each pass can only replace unknown parts of a signature, but a recursive
+// function can keep wrapping its argument in arrays, so the number of passes
+// is capped.
+const MAX_PASSES: usize = 32;
+
 pub struct SemanticAnalyzer {
     symbol_table: SymbolTable,
     current_function: Option<String>,
-    errors: Vec<String>,
+    // Warnings are collected here too; only errors fail the analysis
+    errors: Vec<Diagnostic>,
+    // Whether the current pass learned anything new about a function
+    changed: bool,
+    // Whether the innermost loop has a `break` that leaves it
+    broke: bool,
+    // Whether the statements being analyzed can never run, and were already
+    // warned about
+    unreachable: bool,
+    // How many loops enclose the statement being analyzed
+    loop_depth: usize,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum InferredType {
     Integer,
     Float,
@@ -20,72 +44,1476 @@ pub enum InferredType {
     Void,
 }
 
+impl InferredType {
+    /// The concrete type, or `None` if any part of it is still unknown.
+    pub fn to_data_type(&self) -> Option<DataType> {
+        match self {
+            InferredType::Integer => Some(DataType::Integer),
+            InferredType::Float => Some(DataType::Float),
+            InferredType::Boolean => Some(DataType::Boolean),
+            InferredType::String => Some(DataType::String),
+            InferredType::Array(element) => {
+                Some(DataType::Array(Box::new(element.to_data_type()?)))
+            }
+            InferredType::Unknown => None,
+            InferredType::Void => Some(DataType::Void),
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, InferredType::Integer | InferredType::Float)
+    }
+
+    // Whether a value of this type could be the given concrete kind once
+    // inference is done.
+    fn could_be(&self, other: &InferredType) -> bool {
+        unify(self, other).is_some()
+    }
+}
+
+impl fmt::Display for InferredType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InferredType::Integer => write!(f, "integer"),
+            InferredType::Float => write!(f, "float"),
+            InferredType::Boolean => write!(f, "boolean"),
+            InferredType::String => write!(f, "string"),
+            InferredType::Array(element) => write!(f, "[{}]", element),
+            InferredType::Unknown => write!(f, "unknown"),
+            InferredType::Void => write!(f, "void"),
+        }
+    }
+}
+
+/// Combines two views of the same type, filling in whatever either side
+/// leaves unknown. Returns `None` when they disagree.
+pub fn unify(a: &InferredType, b: &InferredType) -> Option<InferredType> {
+    match (a, b) {
+        (InferredType::Unknown, other) | (other, InferredType::Unknown) => Some(other.clone()),
+        (InferredType::Array(a), InferredType::Array(b)) => {
+            Some(InferredType::Array(Box::new(unify(a, b)?)))
+        }
+        (a, b) if a == b => Some(a.clone()),
+        _ => None,
+    }
+}
+
+// Unifies `slot` with `ty` in place. Returns whether `slot` learned anything;
+// a disagreement leaves it untouched.
+fn refine(slot: &mut InferredType, ty: &InferredType) -> bool {
+    match unify(slot, ty) {
+        Some(unified) if unified != *slot => {
+            *slot = unified;
+            true
+        }
+        _ => false,
+    }
+}
+
+// The value of a condition that is a `true` or `false` literal.
+fn literal_condition(cond: &Expression) -> Option<bool> {
+    match cond.kind {
+        ExpressionKind::Token(Token::Boolean(b)) => Some(b),
+        _ => None,
+    }
+}
+
+impl Default for SemanticAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SemanticAnalyzer {
     pub fn new() -> Self {
         SemanticAnalyzer {
             symbol_table: SymbolTable::new(),
             current_function: None,
             errors: Vec::new(),
+            changed: false,
+            broke: false,
+            unreachable: false,
+            loop_depth: 0,
         }
     }
 
-    pub fn analyze(&mut self, program: &Program) -> Result<(), Vec<String>> {
+    /// Checks names and types across the program and returns it with every
+    /// expression annotated with its type, along with any warnings. If there
+    /// are errors, they are returned together with the warnings.
+    pub fn analyze(
+        &mut self,
+        mut program: Program,
+    ) -> Result<(Program, Vec<Diagnostic>), Vec<Diagnostic>> {
         // First pass: register all functions in the symbol table
         for function in &program.functions {
-            let func_name = match &function.name {
-                Token::Identifier(name) => name.clone(),
-                _ => {
-                    self.errors
-                        .push("Function name must be an identifier".to_string());
+            let func_name = match function_name(&function.name.node) {
+                Some(name) => name.to_string(),
+                None => {
+                    self.errors.push(Diagnostic::error(
+                        codes::INVALID_NAME,
+                        "Function name must be an identifier",
+                        function.name.span,
+                    ));
                     continue;
                 }
             };
 
-            let symbol = Symbol::new(func_name.clone(), SymbolType::Function, DataType::Void);
+            // Parameter and return types start out unknown and are filled in
+            // from calls and return statements
+            self.declare(Symbol::function(
+                func_name,
+                vec![InferredType::Unknown; function.params.len()],
+                InferredType::Unknown,
+                function.name.span,
+            ));
+        }
 
-            if let Err(err) = self.symbol_table.declare(func_name.clone(), symbol) {
-                self.errors.push(err);
+        // Then walk every body until the function types settle. Only the errors
+        // from the last pass are kept, since earlier ones saw less.
+        let registration_errors = std::mem::take(&mut self.errors);
+        for _ in 0..MAX_PASSES {
+            self.errors = registration_errors.clone();
+            self.changed = false;
+            for function in &mut program.functions {
+                self.analyze_function(function);
+            }
+            if !self.changed {
+                break;
             }
         }
 
-        for function in &program.functions {
-            let func_name = match &function.name {
-                Token::Identifier(name) => name.clone(),
-                _ => continue,
-            };
+        if self
+            .errors
+            .iter()
+            .any(|err| err.severity == Severity::Error)
+        {
+            Err(self.errors.clone())
+        } else {
+            Ok((program, self.errors.clone()))
+        }
+    }
 
-            self.current_function = Some(func_name);
-            self.symbol_table.enter_scope();
+    fn analyze_function(&mut self, function: &mut Function) {
+        let func_name = match function_name(&function.name.node) {
+            Some(name) => name.to_string(),
+            None => return,
+        };
+        let param_types = match self.symbol_table.lookup_global(&func_name) {
+            Some(symbol) => symbol.params.clone(),
+            None => return,
+        };
 
-            // Register parameters
-            for param in &function.params {
-                match param {
-                    Token::Identifier(name) => {
-                        let symbol =
-                            Symbol::new(name.clone(), SymbolType::Variable, DataType::Integer);
+        self.current_function = Some(func_name.clone());
+        self.symbol_table.enter_scope();
 
-                        if let Err(err) = self.symbol_table.declare(name.clone(), symbol) {
-                            self.errors.push(err);
+        // Register parameters
+        for (param, ty) in function.params.iter().zip(param_types) {
+            match &param.node {
+                Token::Identifier(name) => self.declare(Symbol::new(
+                    name.clone(),
+                    SymbolType::Variable,
+                    ty,
+                    param.span,
+                )),
+                _ => self.errors.push(Diagnostic::error(
+                    codes::INVALID_NAME,
+                    "Function parameter must be an identifier",
+                    param.span,
+                )),
+            }
+        }
+
+        // Falling off the end returns nothing
+        if !self.analyze_statements(&mut function.body) {
+            self.refine_return(&InferredType::Void, function.span);
+        }
+
+        self.symbol_table.exit_scope();
+        self.current_function = None;
+    }
+
+    // Analyzes a statement and returns whether control never gets past it,
+    // because every path through it returns, breaks or continues.
+    fn analyze_statement(&mut self, statement: &mut Statement) -> bool {
+        let span = statement.span;
+        match &mut statement.kind {
+            StatementKind::DeclareStatement { identifier, value } => {
+                // The initializer is resolved first, so `let x = x;` is an error
+                let ty = match value {
+                    Some(value) => {
+                        value.resolve_symbols(&self.symbol_table, &mut self.errors);
+                        self.infer(value)
+                    }
+                    None => InferredType::Unknown,
+                };
+                if let Token::Identifier(name) = identifier {
+                    self.declare(Symbol::new(name.clone(), SymbolType::Variable, ty, span));
+                }
+            }
+            StatementKind::AssignStatement { target, value } => {
+                if let ExpressionKind::Token(identifier) = &target.kind {
+                    self.check_assignable(identifier, target.span);
+                    value.resolve_symbols(&self.symbol_table, &mut self.errors);
+                    let ty = self.infer(value);
+                    self.assign_type(identifier, &ty, span);
+                } else {
+                    // An element of an array: its type is whatever reading it gives
+                    target.resolve_symbols(&self.symbol_table, &mut self.errors);
+                    value.resolve_symbols(&self.symbol_table, &mut self.errors);
+                    let expected = self.infer(target);
+                    let ty = self.infer(value);
+                    if unify(&expected, &ty).is_none() {
+                        self.type_error(
+                            format!(
+                                "Cannot assign {} to an element of type {}",
+                                with_article(&ty),
+                                expected
+                            ),
+                            span,
+                        );
+                    }
+                }
+            }
+            StatementKind::CompoundAssign {
+                target,
+                operator,
+                value,
+            } => {
+                // `x op= v` is typed exactly like `x = x op v`
+                if let ExpressionKind::Token(identifier) = &target.kind {
+                    self.check_assignable(identifier, target.span);
+                } else {
+                    target.resolve_symbols(&self.symbol_table, &mut self.errors);
+                }
+                value.resolve_symbols(&self.symbol_table, &mut self.errors);
+                let current = self.infer(target);
+                let ty = self.infer(value);
+                let ty = self.binary_type(operator, &current, &ty, span);
+                if let ExpressionKind::Token(identifier) = &target.kind {
+                    self.assign_type(identifier, &ty, span);
+                }
+            }
+            StatementKind::ArrayAppend { identifier, value } => {
+                self.check_assignable(&identifier.node, identifier.span);
+                value.resolve_symbols(&self.symbol_table, &mut self.errors);
+                let ty = self.infer(value);
+                self.append_type(&identifier.node, &ty, span);
+            }
+            StatementKind::IfStatement {
+                cond,
+                if_then,
+                else_then,
+            } => {
+                cond.resolve_symbols(&self.symbol_table, &mut self.errors);
+                self.check_condition(cond);
+                // A literal condition leaves the other branch unreachable
+                let literal = literal_condition(cond);
+                let then_diverges = self.analyze_branch(if_then, literal != Some(false));
+                let else_diverges = match else_then {
+                    Some(else_then) => self.analyze_branch(else_then, literal != Some(true)),
+                    None => false,
+                };
+                return match literal {
+                    Some(true) => then_diverges,
+                    Some(false) => else_diverges,
+                    None => then_diverges && else_diverges,
+                };
+            }
+            StatementKind::WhileStatement { cond, loop_stmt } => {
+                // Only `while (true)` without a `break` never ends
+                let literal = literal_condition(cond);
+                let no_break = self.analyze_loop(|this| {
+                    cond.resolve_symbols(&this.symbol_table, &mut this.errors);
+                    this.check_condition(cond);
+                    this.analyze_loop_body(loop_stmt, literal != Some(false))
+                });
+                return no_break && literal == Some(true);
+            }
+            StatementKind::ForStatement {
+                init,
+                cond,
+                step,
+                body,
+            } => {
+                // The header gets a scope of its own, so the loop variable
+                // is gone once the loop ends
+                self.symbol_table.enter_scope();
+                if let Some(init) = init {
+                    self.analyze_statement(init);
+                }
+                // Leaving out the condition is the same as `true`
+                let literal = cond.as_ref().map_or(Some(true), literal_condition);
+                let no_break = self.analyze_loop(|this| {
+                    if let Some(cond) = cond {
+                        cond.resolve_symbols(&this.symbol_table, &mut this.errors);
+                        this.check_condition(cond);
+                    }
+                    if let Some(step) = step {
+                        this.analyze_statement(step);
+                    }
+                    this.analyze_loop_body(body, literal != Some(false))
+                });
+                self.symbol_table.exit_scope();
+                return no_break && literal == Some(true);
+            }
+            StatementKind::ForInStatement {
+                variable,
+                iterable,
+                body,
+            } => {
+                iterable.resolve_symbols(&self.symbol_table, &mut self.errors);
+                let element = match self.infer(iterable) {
+                    InferredType::Array(element) => *element,
+                    InferredType::Unknown => InferredType::Unknown,
+                    other => {
+                        self.type_error(
+                            format!("Cannot iterate over {}", with_article(other)),
+                            iterable.span,
+                        );
+                        InferredType::Unknown
+                    }
+                };
+                self.symbol_table.enter_scope();
+                if let Token::Identifier(name) = &variable.node {
+                    self.declare(Symbol::new(
+                        name.clone(),
+                        SymbolType::Variable,
+                        element,
+                        variable.span,
+                    ));
+                }
+                self.analyze_loop(|this| this.analyze_loop_body(body, true));
+                self.symbol_table.exit_scope();
+            }
+            StatementKind::BreakStatement | StatementKind::ContinueStatement => {
+                if self.loop_depth == 0 {
+                    let keyword = match statement.kind {
+                        StatementKind::BreakStatement => "break",
+                        _ => "continue",
+                    };
+                    self.errors.push(Diagnostic::error(
+                        codes::OUTSIDE_LOOP,
+                        format!("'{}' outside of a loop", keyword),
+                        span,
+                    ));
+                }
+                if let StatementKind::BreakStatement = statement.kind {
+                    self.broke = true;
+                }
+                // Outside of a loop it is already an error
+                return self.loop_depth > 0;
+            }
+            StatementKind::PrintStatement { args } => {
+                for arg in args {
+                    arg.resolve_symbols(&self.symbol_table, &mut self.errors);
+                    if self.infer(arg) == InferredType::Void {
+                        self.type_error("Cannot print a void value".to_string(), arg.span);
+                    }
+                }
+            }
+            StatementKind::ReturnStatement { value } => {
+                value.resolve_symbols(&self.symbol_table, &mut self.errors);
+                let ty = self.infer(value);
+                self.refine_return(&ty, value.span);
+                return true;
+            }
+            StatementKind::ExpressionStatement { expr } => {
+                expr.resolve_symbols(&self.symbol_table, &mut self.errors);
+                self.infer(expr);
+                if !expr.has_side_effects() {
+                    self.errors.push(Diagnostic::warning(
+                        codes::UNUSED_RESULT,
+                        "Expression has no effect and its result is unused",
+                        expr.span,
+                    ));
+                }
+            }
+        }
+        false
+    }
+
+    // Infers the type of `expr`, records it on the expression and returns it.
+    // Names that don't resolve are left unknown; `resolve_symbols` reports them.
+    fn infer(&mut self, expr: &mut Expression) -> InferredType {
+        let span = expr.span;
+        let ty = match &mut expr.kind {
+            ExpressionKind::Token(token) => self.token_type(token),
+            ExpressionKind::Grouping(inner) => self.infer(inner),
+            ExpressionKind::Format { parts } => {
+                // Anything with a value can be turned into text
+                for part in parts.iter_mut() {
+                    if let FormatPart::Expr(inner) = part {
+                        let ty = self.infer(inner);
+                        if ty == InferredType::Void {
+                            self.type_error(
+                                format!("Cannot interpolate {}", with_article(ty)),
+                                inner.span,
+                            );
+                        }
+                    }
+                }
+                InferredType::String
+            }
+            ExpressionKind::ArrayDec { arr_expr } => {
+                let mut element = InferredType::Unknown;
+                for item in arr_expr.iter_mut() {
+                    let ty = self.infer(item);
+                    match unify(&element, &ty) {
+                        Some(unified) => element = unified,
+                        None => self.type_error(
+                            format!(
+                                "Array elements must all have the same type, found {} and {}",
+                                element, ty
+                            ),
+                            item.span,
+                        ),
+                    }
+                }
+                InferredType::Array(Box::new(element))
+            }
+            ExpressionKind::AccessIndex { array, index } => {
+                let array_ty = self.infer(array);
+                let index_ty = self.infer(index);
+                if !index_ty.could_be(&InferredType::Integer) {
+                    self.type_error(
+                        format!("Array index must be an integer, found {}", index_ty),
+                        index.span,
+                    );
+                }
+                match array_ty {
+                    InferredType::Array(element) => *element,
+                    InferredType::Unknown => InferredType::Unknown,
+                    other => {
+                        self.type_error(format!("Cannot index into {}", with_article(other)), span);
+                        InferredType::Unknown
+                    }
+                }
+            }
+            ExpressionKind::GetLength { ident } => {
+                match self.token_type(ident) {
+                    InferredType::Array(_) | InferredType::String | InferredType::Unknown => {}
+                    other => self.type_error(
+                        format!("Cannot take the length of {}", with_article(other)),
+                        span,
+                    ),
+                }
+                InferredType::Integer
+            }
+            ExpressionKind::ArrayAppend { array, value } => {
+                let ty = self.infer(value);
+                self.append_type(array, &ty, span);
+                InferredType::Void
+            }
+            ExpressionKind::FunctionCall { identifier, args } => {
+                let args: Vec<(InferredType, Span)> = args
+                    .iter_mut()
+                    .map(|arg| (self.infer(arg), arg.span))
+                    .collect();
+                self.call_type(identifier, &args, span)
+            }
+            ExpressionKind::Unary { operator, operand } => {
+                let ty = self.infer(operand);
+                match operator {
+                    Token::Not => {
+                        if !ty.could_be(&InferredType::Boolean) {
+                            self.operator_error(operator, &ty, span);
+                        }
+                        InferredType::Boolean
+                    }
+                    Token::BitNot => {
+                        if !ty.could_be(&InferredType::Integer) {
+                            self.operator_error(operator, &ty, span);
+                        }
+                        InferredType::Integer
+                    }
+                    Token::Sub => {
+                        if ty.is_numeric() || ty == InferredType::Unknown {
+                            ty
+                        } else {
+                            self.operator_error(operator, &ty, span);
+                            InferredType::Unknown
                         }
                     }
-                    _ => self
-                        .errors
-                        .push("Function parameter must be an identifier".to_string()),
+                    _ => ty,
                 }
             }
+            ExpressionKind::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let left = self.infer(left);
+                let right = self.infer(right);
+                self.binary_type(operator, &left, &right, span)
+            }
+        };
+        expr.ty = ty.to_data_type();
+        ty
+    }
+
+    fn binary_type(
+        &mut self,
+        operator: &Token,
+        left: &InferredType,
+        right: &InferredType,
+        span: Span,
+    ) -> InferredType {
+        if let Token::And | Token::Or = operator {
+            for side in [left, right] {
+                if !side.could_be(&InferredType::Boolean) {
+                    self.operator_error(operator, side, span);
+                }
+            }
+            return InferredType::Boolean;
+        }
 
-            for statement in &function.body {
-                todo!()
+        // Every other operator needs both sides to have the same type
+        let ty = match unify(left, right) {
+            Some(ty) => ty,
+            None => {
+                self.type_error(
+                    format!(
+                        "Cannot apply {} to {} and {}",
+                        operator,
+                        with_article(left),
+                        with_article(right)
+                    ),
+                    span,
+                );
+                return InferredType::Unknown;
             }
+        };
 
-            self.symbol_table.exit_scope();
-            self.current_function = None;
+        match operator {
+            Token::BitAnd | Token::BitOr | Token::BitXor | Token::ShiftLeft | Token::ShiftRight => {
+                if !ty.could_be(&InferredType::Integer) {
+                    self.operator_error(operator, &ty, span);
+                }
+                InferredType::Integer
+            }
+            Token::Add | Token::Sub | Token::Mul | Token::Div | Token::Mod => {
+                if ty.is_numeric() || ty == InferredType::Unknown {
+                    ty
+                } else {
+                    self.operator_error(operator, &ty, span);
+                    InferredType::Unknown
+                }
+            }
+            Token::Greater | Token::GreaterEqual | Token::Less | Token::LessEqual => {
+                if !(ty.is_numeric() || ty == InferredType::String || ty == InferredType::Unknown) {
+                    self.operator_error(operator, &ty, span);
+                }
+                InferredType::Boolean
+            }
+            Token::EqualEqual | Token::NotEqual => InferredType::Boolean,
+            _ => InferredType::Unknown,
         }
+    }
 
-        if self.errors.is_empty() {
-            Ok(())
+    // The return type of a call. Checks the arguments against the callee's
+    // parameters, and uses them to learn the parameter types still unknown.
+    fn call_type(
+        &mut self,
+        identifier: &Token,
+        args: &[(InferredType, Span)],
+        span: Span,
+    ) -> InferredType {
+        let function = match identifier {
+            Token::Identifier(name) => self
+                .symbol_table
+                .lookup_global_mut(name)
+                .filter(|symbol| symbol.symbol_type == SymbolType::Function),
+            _ => None,
+        };
+        let function = match function {
+            Some(function) => function,
+            None => return InferredType::Unknown,
+        };
+
+        let mut errors = Vec::new();
+        if function.params.len() != args.len() {
+            errors.push(Diagnostic::error(
+                codes::ARGUMENT_COUNT,
+                format!(
+                    "Function '{}' expects {} argument(s) but got {}",
+                    function.name,
+                    function.params.len(),
+                    args.len()
+                ),
+                span,
+            ));
         } else {
-            Err(self.errors.clone())
+            for (i, (param, (arg, arg_span))) in function.params.iter_mut().zip(args).enumerate() {
+                if unify(param, arg).is_none() {
+                    errors.push(Diagnostic::error(
+                        codes::TYPE_MISMATCH,
+                        format!(
+                            "Argument {} of '{}' must be {}, found {}",
+                            i + 1,
+                            function.name,
+                            with_article(&param),
+                            arg
+                        ),
+                        *arg_span,
+                    ));
+                } else {
+                    self.changed |= refine(param, arg);
+                }
+            }
+        }
+
+        let return_type = function.data_type.clone();
+        let defined_at = function.span;
+        self.errors.extend(
+            errors
+                .into_iter()
+                .map(|error| error.with_label(defined_at, "function defined here")),
+        );
+        return_type
+    }
+
+    fn token_type(&self, token: &Token) -> InferredType {
+        match token {
+            Token::Integer(_) => InferredType::Integer,
+            Token::Float(_) => InferredType::Float,
+            Token::Boolean(_) => InferredType::Boolean,
+            Token::String(_) => InferredType::String,
+            Token::Identifier(name) => match self.symbol_table.lookup(name) {
+                Some(symbol) if symbol.symbol_type == SymbolType::Variable => {
+                    symbol.data_type.clone()
+                }
+                _ => InferredType::Unknown,
+            },
+            _ => InferredType::Unknown,
+        }
+    }
+
+    fn check_condition(&mut self, cond: &mut Expression) {
+        let ty = self.infer(cond);
+        if !ty.could_be(&InferredType::Boolean) {
+            self.type_error(
+                format!("Condition must be a boolean, found {}", ty),
+                cond.span,
+            );
+        }
+    }
+
+    fn assign_type(&mut self, identifier: &Token, ty: &InferredType, span: Span) {
+        let symbol = match self.variable_mut(identifier) {
+            Some(symbol) => symbol,
+            None => return,
+        };
+        match unify(&symbol.data_type, ty) {
+            Some(unified) => symbol.data_type = unified,
+            None => {
+                let message = format!(
+                    "Cannot assign {} to '{}', which is {}",
+                    with_article(ty),
+                    symbol.name,
+                    with_article(&symbol.data_type)
+                );
+                self.type_error(message, span);
+            }
+        }
+    }
+
+    fn append_type(&mut self, array: &Token, ty: &InferredType, span: Span) {
+        let symbol = match self.variable_mut(array) {
+            Some(symbol) => symbol,
+            None => return,
+        };
+        let message = match &mut symbol.data_type {
+            InferredType::Array(element) => match unify(element, ty) {
+                Some(unified) => {
+                    **element = unified;
+                    return;
+                }
+                None => format!(
+                    "Cannot append {} to an array of {}",
+                    with_article(ty),
+                    element
+                ),
+            },
+            // Appending is what tells us an untyped variable is an array
+            InferredType::Unknown => {
+                symbol.data_type = InferredType::Array(Box::new(ty.clone()));
+                return;
+            }
+            other => format!("Cannot append to {}", with_article(other)),
+        };
+        self.type_error(message, span);
+    }
+
+    fn refine_return(&mut self, ty: &InferredType, span: Span) {
+        let function = match &self.current_function {
+            Some(name) => self.symbol_table.lookup_global_mut(name),
+            None => None,
+        };
+        let function = match function {
+            Some(function) => function,
+            None => return,
+        };
+        if unify(&function.data_type, ty).is_none() {
+            let message = format!(
+                "Mismatched return type: expected {}, found {}",
+                function.data_type, ty
+            );
+            self.type_error(message, span);
+        } else {
+            self.changed |= refine(&mut function.data_type, ty);
+        }
+    }
+
+    fn variable_mut(&mut self, identifier: &Token) -> Option<&mut Symbol> {
+        match identifier {
+            Token::Identifier(name) => self
+                .symbol_table
+                .lookup_mut(name)
+                .filter(|symbol| symbol.symbol_type == SymbolType::Variable),
+            _ => None,
+        }
+    }
+
+    fn operator_error(&mut self, operator: &Token, ty: &InferredType, span: Span) {
+        let message = format!("Cannot apply {} to {}", operator, with_article(ty));
+        self.type_error(message, span);
+    }
+
+    fn type_error(&mut self, message: String, span: Span) {
+        self.errors
+            .push(Diagnostic::error(codes::TYPE_MISMATCH, message, span));
+    }
+
+    // Every block gets its own scope, so names declared inside it are gone
+    // once it ends.
+    // Returns whether control never gets past the statements. The first
+    // statement after one control never gets past is unreachable.
+    fn analyze_statements(&mut self, statements: &mut [Statement]) -> bool {
+        let outer = self.unreachable;
+        let mut diverges = false;
+        for statement in statements {
+            if diverges {
+                self.warn_unreachable(statement.span);
+            }
+            diverges |= self.analyze_statement(statement);
         }
+        self.unreachable = outer;
+        diverges
+    }
+
+    fn analyze_block(&mut self, statements: &mut [Statement]) -> bool {
+        self.symbol_table.enter_scope();
+        let diverges = self.analyze_statements(statements);
+        self.symbol_table.exit_scope();
+        diverges
+    }
+
+    // Analyzes the body of an `if` or `else`, which can't run if `reachable`
+    // is false.
+    fn analyze_branch(&mut self, statements: &mut [Statement], reachable: bool) -> bool {
+        let outer = self.unreachable;
+        if let (false, Some(first)) = (reachable, statements.first()) {
+            self.warn_unreachable(first.span);
+        }
+        let diverges = self.analyze_block(statements);
+        self.unreachable = outer;
+        diverges
+    }
+
+    // Runs `analyze` over a loop until the types of the variables around it
+    // settle, since a type learned late in the body applies to the statements
+    // before it on the next iteration. Only the last run's diagnostics are
+    // kept. Like the passes over the program, the number of runs is capped.
+    fn analyze_loop(&mut self, mut analyze: impl FnMut(&mut Self) -> bool) -> bool {
+        let errors = self.errors.len();
+        let mut types = self.symbol_table.types();
+        for _ in 1..MAX_PASSES {
+            let result = analyze(self);
+            let settled = self.symbol_table.types();
+            if settled == types {
+                return result;
+            }
+            types = settled;
+            self.errors.truncate(errors);
+        }
+        analyze(self)
+    }
+
+    // Returns whether nothing in the body breaks out of the loop.
+    fn analyze_loop_body(&mut self, statements: &mut [Statement], reachable: bool) -> bool {
+        let outer = std::mem::replace(&mut self.broke, false);
+        self.loop_depth += 1;
+        self.analyze_branch(statements, reachable);
+        self.loop_depth -= 1;
+        !std::mem::replace(&mut self.broke, outer)
+    }
+
+    // Warns about code that can never run, once for each stretch of it.
+    fn warn_unreachable(&mut self, span: Span) {
+        if !self.unreachable {
+            self.unreachable = true;
+            self.errors.push(Diagnostic::warning(
+                codes::UNREACHABLE_CODE,
+                "Unreachable code",
+                span,
+            ));
+        }
+    }
+
+    // Reports a write to a name that isn't a declared variable.
+    fn check_assignable(&mut self, identifier: &Token, span: Span) {
+        if let Token::Identifier(name) = identifier {
+            let is_variable = matches!(
+                self.symbol_table.lookup(name),
+                Some(symbol) if symbol.symbol_type == SymbolType::Variable
+            );
+            if !is_variable {
+                self.errors.push(Diagnostic::error(
+                    codes::UNDECLARED_VARIABLE,
+                    format!("Cannot assign to undeclared variable '{}'", name),
+                    span,
+                ));
+            }
+        }
+    }
+
+    fn declare(&mut self, symbol: Symbol) {
+        let name = symbol.name.clone();
+        let span = symbol.span;
+        if let Err(err) = self.symbol_table.declare(name.clone(), symbol) {
+            let mut error = Diagnostic::error(codes::REDECLARATION, err, span);
+            if let Some(previous) = self.symbol_table.lookup(&name) {
+                error = error.with_label(previous.span, "first declared here");
+            }
+            self.errors.push(error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analyze_program, get_program, run_lexer};
+
+    fn analyze(source: &str) -> Result<Program, Vec<Diagnostic>> {
+        let (tokens, _) = run_lexer(source).unwrap();
+        let program = get_program(&tokens).unwrap();
+        analyze_program(program).map(|(program, _)| program)
+    }
+
+    fn warnings(source: &str) -> Vec<Diagnostic> {
+        let (tokens, _) = run_lexer(source).unwrap();
+        let program = get_program(&tokens).unwrap();
+        analyze_program(program).unwrap().1
+    }
+
+    fn error_codes(source: &str) -> Vec<&'static str> {
+        analyze(source)
+            .unwrap_err()
+            .iter()
+            .map(|err| err.code)
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_program() {
+        let source = "\
+func add(a, b) {
+    let sum = a + b;
+    return sum;
+}
+func main() {
+    let arr = [1, 2];
+    let i = 0;
+    while (i < arr.length) {
+        let doubled = arr[i] * 2;
+        arr.append(doubled);
+        i = i + 1;
+    }
+    if (i > 1) {
+        let x = 1;
+    } else {
+        let x = 2;
+    }
+    return add(i, 1);
+}";
+        analyze(source).unwrap();
+    }
+
+    #[test]
+    fn test_undeclared_variable() {
+        let source = "func main() { let x = y + 1; return x; }";
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, codes::UNDECLARED_VARIABLE);
+        assert_eq!(errors[0].message, "Use of undeclared variable 'y'");
+    }
+
+    #[test]
+    fn test_assignment_to_undeclared() {
+        let source = "func main() { sum = 1; return 0; }";
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(errors[0].code, codes::UNDECLARED_VARIABLE);
+        assert_eq!(
+            errors[0].message,
+            "Cannot assign to undeclared variable 'sum'"
+        );
+
+        // The error points at the name, not the whole statement
+        for source in [
+            "func main() { sum = 1; return 0; }",
+            "func main() { sum += 1; return 0; }",
+            "func main() { sum.append(1); return 0; }",
+        ] {
+            let errors = analyze(source).unwrap_err();
+            let span = errors[0].span;
+            assert_eq!(errors[0].code, codes::UNDECLARED_VARIABLE);
+            assert_eq!(&source[span.start..span.end], "sum");
+        }
+    }
+
+    #[test]
+    fn test_unknown_function() {
+        let source = "func main() { let x = 1; return check(x, x); }";
+        assert_eq!(error_codes(source), vec![codes::UNKNOWN_FUNCTION]);
+    }
+
+    #[test]
+    fn test_redeclaration_in_same_scope() {
+        let source = "func main() { let x = 1; let x = 2; return x; }";
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, codes::REDECLARATION);
+        assert_eq!(errors[0].labels[0].message, "first declared here");
+    }
+
+    #[test]
+    fn test_block_scopes() {
+        // Shadowing in an inner block is fine, but the name is gone afterwards
+        let source = "\
+func main() {
+    let x = 1;
+    if (x > 0) {
+        let x = 2;
+        let y = x;
+    }
+    return y;
+}";
+        assert_eq!(error_codes(source), vec![codes::UNDECLARED_VARIABLE]);
+    }
+
+    #[test]
+    fn test_for_loop_scopes() {
+        let source = "\
+func main() {
+    let total = 0;
+    for (let i = 0; i < 3; i = i + 1) {
+        total = total + i;
+    }
+    for x in [1, 2] {
+        total = total + x;
+    }
+    return i + x;
+}";
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors
+            .iter()
+            .all(|err| err.code == codes::UNDECLARED_VARIABLE && err.span.line == 9));
+    }
+
+    #[test]
+    fn test_for_in_types() {
+        let source = "func main() { for s in [\"a\"] { let n = s + 1; } return 0; }";
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "Cannot apply + to a string and an integer"
+        );
+
+        let source = "func main() { for n in 5 { } return 0; }";
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(errors[0].message, "Cannot iterate over an integer");
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        let source = "\
+func main() {
+    while (true) {
+        if (true) {
+            break;
+        }
+    }
+    continue;
+    return 0;
+}";
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, codes::OUTSIDE_LOOP);
+        assert_eq!(errors[0].message, "'continue' outside of a loop");
+        assert_eq!(errors[0].span.line, 7);
+    }
+
+    #[test]
+    fn test_local_does_not_hide_function() {
+        let source = "func f() { return 1; } func main() { let f = 2; return f(); }";
+        analyze(source).unwrap();
+    }
+
+    #[test]
+    fn test_examples() {
+        let array = include_str!("../examples/array.el");
+        let errors = analyze(array).unwrap_err();
+        assert!(errors
+            .iter()
+            .all(|err| err.code == codes::UNDECLARED_VARIABLE && err.message.contains("'sum'")));
+
+        let check = include_str!("../examples/check.el");
+        assert_eq!(error_codes(check), vec![codes::UNKNOWN_FUNCTION]);
+    }
+
+    // ----- Type Inference Tests -----
+
+    fn return_type(program: &Program, function: usize) -> Option<DataType> {
+        match &program.functions[function].body.last().unwrap().kind {
+            StatementKind::ReturnStatement { value } => value.ty.clone(),
+            _ => panic!("expected a return statement"),
+        }
+    }
+
+    #[test]
+    fn test_binary_type_mismatch() {
+        let errors = analyze("func main() { return 1 + (1 < 2); }").unwrap_err();
+        assert_eq!(errors[0].code, codes::TYPE_MISMATCH);
+        assert_eq!(
+            errors[0].message,
+            "Cannot apply + to an integer and a boolean"
+        );
+    }
+
+    #[test]
+    fn test_negation_needs_number() {
+        let errors = analyze("func main() { let t = true; return -t; }").unwrap_err();
+        assert_eq!(errors[0].message, "Cannot apply - to a boolean");
+        analyze("func main() { let f = 1.5; let g = -f * 2.0; return 0; }").unwrap();
+    }
+
+    #[test]
+    fn test_print_checks_arguments() {
+        let source = "func main() { let arr = [1]; print(arr, missing, arr.append(2)); return 0; }";
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].code, codes::UNDECLARED_VARIABLE);
+        assert_eq!(errors[1].message, "Cannot print a void value");
+    }
+
+    #[test]
+    fn test_condition_must_be_boolean() {
+        let source = "func main() { let x = 1; while (x) { x = x - 1; } return x; }";
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "Condition must be a boolean, found integer"
+        );
+    }
+
+    #[test]
+    fn test_append_wrong_element_type() {
+        let source = "func main() { let arr = [1, 2]; arr.append(\"three\"); return 0; }";
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "Cannot append a string to an array of integer"
+        );
+    }
+
+    #[test]
+    fn test_compound_assignment_types() {
+        let source =
+            "func main() { let s = \"a\"; s += \"b\"; let arr = [1]; arr[0] *= 2.5; return 0; }";
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].message, "Cannot apply + to a string");
+        assert_eq!(
+            errors[1].message,
+            "Cannot apply * to an integer and a float"
+        );
+
+        let source = "func main() { total += 1; return 0; }";
+        assert_eq!(error_codes(source), vec![codes::UNDECLARED_VARIABLE]);
+    }
+
+    #[test]
+    fn test_indexed_assignment_types() {
+        let source =
+            "func main() { let grid = [[1]]; grid[0][0] = \"one\"; grid[0] = 2; return 0; }";
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].message,
+            "Cannot assign a string to an element of type integer"
+        );
+        assert_eq!(
+            errors[1].message,
+            "Cannot assign an integer to an element of type [integer]"
+        );
+
+        let source = "func main() { let arr = [1]; arr[missing] = 2; return 0; }";
+        assert_eq!(error_codes(source), vec![codes::UNDECLARED_VARIABLE]);
+    }
+
+    #[test]
+    fn test_assignment_keeps_type() {
+        let source = "func main() { let x = 1; x = \"one\"; return 0; }";
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "Cannot assign a string to 'x', which is an integer"
+        );
+    }
+
+    #[test]
+    fn test_loop_bodies_checked_with_types_learned_later() {
+        for source in [
+            "func main(c) { let arr = []; while (c) { print(arr[0] + 1); arr.append(1.5); } return 0; }",
+            "func main() { let arr = []; for (let i = 0; i < 2; i += 1) { print(arr[0] + 1); arr.append(1.5); } return 0; }",
+            "func main() { let arr = []; for x in [1] { print(arr[0] + x); arr.append(1.5); } return 0; }",
+        ] {
+            // Reported once, not once per time the body is checked
+            let errors = analyze(source).unwrap_err();
+            assert_eq!(errors.len(), 1);
+            assert_eq!(
+                errors[0].message,
+                "Cannot apply + to a float and an integer"
+            );
+        }
+
+        let source = "func main(c) {
+            let arr = [];
+            let sum = 0.0;
+            while (c) {
+                sum += arr[0];
+                arr.append(1.5);
+            }
+            return sum;
+        }";
+        assert!(analyze(source).is_ok());
+    }
+
+    #[test]
+    fn test_mismatched_return_types() {
+        let source = "func main() { if (1 < 2) { return 1; } return 1 < 2; }";
+        assert_eq!(error_codes(source), vec![codes::TYPE_MISMATCH]);
+    }
+
+    #[test]
+    fn test_falling_off_the_end_returns_void() {
+        // The `if` can be skipped, so `f` may return nothing
+        let source = "func f(c) { if (c) { return 1; } } func main() { return f(false) + 1; }";
+        assert_eq!(error_codes(source), vec![codes::TYPE_MISMATCH]);
+
+        // Every path returns, or the loop never ends
+        let sources = [
+            "func f(c) { if (c) { return 1; } else { return 2; } } func main() { return f(true); }",
+            "func f() { while (true) { return 1; } } func main() { return f(); }",
+            "func f() { for (;;) { if (true) { return 1; } } } func main() { return f(); }",
+        ];
+        for source in sources {
+            let program = analyze(source).unwrap();
+            assert_eq!(
+                return_type(&program, 1),
+                Some(DataType::Integer),
+                "{}",
+                source
+            );
+        }
+
+        // A `break` lets control reach the end
+        let source = "func f(n) { while (true) { if (n > 1) { break; } return 1; } }
+        func main() { return f(2); }";
+        assert_eq!(error_codes(source), vec![codes::TYPE_MISMATCH]);
+    }
+
+    #[test]
+    fn test_types_recorded_on_ast() {
+        let source = "func main() { let arr = [\"a\"]; return arr[0] > \"b\"; }";
+        let program = analyze(source).unwrap();
+        assert_eq!(return_type(&program, 0), Some(DataType::Boolean));
+
+        match &program.functions[0].body[0].kind {
+            StatementKind::DeclareStatement { value, .. } => assert_eq!(
+                value.as_ref().unwrap().ty,
+                Some(DataType::Array(Box::new(DataType::String)))
+            ),
+            _ => panic!("expected a declaration"),
+        }
+    }
+
+    #[test]
+    fn test_infers_types_from_calls() {
+        // `echo` is defined before it is called, and `fib` only learns its
+        // return type from the base case, so both need a second pass.
+        let source = "\
+func echo(s) {
+    return s;
+}
+func fib(n) {
+    if (n < 2) {
+        return n;
+    }
+    let a = n - 1;
+    let b = n - 2;
+    return fib(a) + fib(b);
+}
+func main() {
+    let x = echo(\"hi\");
+    return fib(10);
+}";
+        let program = analyze(source).unwrap();
+        assert_eq!(return_type(&program, 0), Some(DataType::String));
+        assert_eq!(return_type(&program, 1), Some(DataType::Integer));
+        assert_eq!(return_type(&program, 2), Some(DataType::Integer));
+    }
+
+    #[test]
+    fn test_inferred_parameter_checked_in_body() {
+        let source = "func negate(b) { return !b; } func main() { return negate(1); }";
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(errors[0].message, "Cannot apply ! to an integer");
+    }
+
+    #[test]
+    fn test_unify() {
+        let unknown_array = InferredType::Array(Box::new(InferredType::Unknown));
+        let int_array = InferredType::Array(Box::new(InferredType::Integer));
+        assert_eq!(unify(&unknown_array, &int_array), Some(int_array.clone()));
+        assert_eq!(
+            unify(&InferredType::Unknown, &InferredType::Float),
+            Some(InferredType::Float)
+        );
+        assert_eq!(unify(&int_array, &InferredType::Integer), None);
+    }
+
+    // ----- Function Call Tests -----
+
+    #[test]
+    fn test_function_symbol_records_signature() {
+        let source = "\
+func add(a, b) {
+    return a + b;
+}
+func main() {
+    let x = 1;
+    return add(x, 2);
+}";
+        let (tokens, _) = run_lexer(source).unwrap();
+        let mut analyzer = SemanticAnalyzer::new();
+        analyzer.analyze(get_program(&tokens).unwrap()).unwrap();
+
+        let add = analyzer.symbol_table.lookup_global("add").unwrap();
+        assert_eq!(
+            add.params,
+            vec![InferredType::Integer, InferredType::Integer]
+        );
+        assert_eq!(add.data_type, InferredType::Integer);
+    }
+
+    #[test]
+    fn test_wrong_argument_count() {
+        let source = "func add(a, b) { return a + b; } func main() { return add(1); }";
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, codes::ARGUMENT_COUNT);
+        assert_eq!(
+            errors[0].message,
+            "Function 'add' expects 2 argument(s) but got 1"
+        );
+        assert_eq!(errors[0].labels[0].message, "function defined here");
+    }
+
+    #[test]
+    fn test_wrong_argument_type() {
+        // The first call decides that `n` is an integer
+        let source = "\
+func twice(n) {
+    return n * 2;
+}
+func main() {
+    let a = twice(1);
+    let b = twice(\"two\");
+    return a;
+}";
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "Argument 1 of 'twice' must be an integer, found string"
+        );
+        assert_eq!(errors[0].span.line, 6);
+    }
+
+    #[test]
+    fn test_argument_type_checked_against_body() {
+        // Nothing calls `flag` with a boolean, so the integer argument flows
+        // into the body and fails there
+        let source =
+            "func flag(b) { if (b) { return 1; } return 0; } func main() { return flag(3); }";
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "Condition must be a boolean, found integer"
+        );
+    }
+
+    #[test]
+    fn test_expression_statement_warnings() {
+        let source = "\
+func log_value(v) {
+    print(v);
+    return 0;
+}
+func main() {
+    let x = 1;
+    log_value(x);
+    x + 1;
+    [log_value(2)];
+    return 0;
+}";
+        let warnings = warnings(source);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].severity, Severity::Warning);
+        assert_eq!(warnings[0].code, codes::UNUSED_RESULT);
+        assert_eq!(
+            warnings[0].message,
+            "Expression has no effect and its result is unused"
+        );
+        assert_eq!((warnings[0].span.line, warnings[0].span.column), (8, 5));
+
+        // Names in an expression statement still have to resolve
+        let source = "func main() { missing(1); return 0; }";
+        assert_eq!(error_codes(source), vec![codes::UNKNOWN_FUNCTION]);
+    }
+
+    #[test]
+    fn test_format_types() {
+        let source =
+            r#"func main() { let n = 1; let s = "n is ${n + 1.5} and ${missing}"; return s; }"#;
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].code, codes::UNDECLARED_VARIABLE);
+        assert_eq!(
+            errors[1].message,
+            "Cannot apply + to an integer and a float"
+        );
+
+        let source = r#"func main() { let arr = [1]; print("${arr.append(2)}"); return 0; }"#;
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(errors[0].message, "Cannot interpolate a void");
+
+        let program = analyze(r#"func main() { let n = 1; return "${n}"; }"#).unwrap();
+        assert_eq!(return_type(&program, 0), Some(DataType::String));
+    }
+
+    #[test]
+    fn test_bitwise_types() {
+        let source =
+            "func main() { let flags = 0b0110; return [flags & 2 | 1 << 3, ~flags ^ 1 >> 1]; }";
+        assert!(analyze(source).is_ok());
+
+        let source =
+            "func main() { let a = 1.5 & 2.5; let b = ~true; let c = 1 << 2.0; return 0; }";
+        let errors = analyze(source).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|err| err.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Cannot apply & to a float",
+                "Cannot apply ~ to a boolean",
+                "Cannot apply << to an integer and a float",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_constant_division_by_zero() {
+        let source = "func main() { let n = 2; n -= 2; return 10 % n; }";
+        assert_eq!(error_codes(source), vec![codes::CONSTANT_DIVISION_BY_ZERO]);
+        // Floats divide by zero just fine
+        assert!(analyze("func main() { print(1.0 / 0.0); return 0; }").is_ok());
+    }
+
+    #[test]
+    fn test_dead_code_warnings() {
+        let source = "func main() {
+    let unused = 1;
+    return 0;
+    print(2);
+}";
+        let found: Vec<_> = warnings(source)
+            .iter()
+            .map(|warning| (warning.code, warning.span.line))
+            .collect();
+        assert_eq!(
+            found,
+            vec![(codes::UNUSED_VARIABLE, 2), (codes::UNREACHABLE_CODE, 4)]
+        );
+    }
+
+    fn warning_lines(source: &str) -> Vec<(&'static str, usize)> {
+        warnings(source)
+            .iter()
+            .map(|warning| (warning.code, warning.span.line))
+            .collect()
+    }
+
+    #[test]
+    fn test_unreachable_code_warnings() {
+        let source = "func main(x, c) {
+    if (false) {
+        print(1);
+        if (c) { return 2; }
+        print(3);
+    }
+    while (false) { x += 1; }
+    if (true) { print(x); } else { print(4); print(5); }
+    if (c) { return 1; } else { return 2; }
+    print(6);
+    return 7;
+}";
+        assert_eq!(
+            warning_lines(source),
+            vec![
+                (codes::UNREACHABLE_CODE, 3),
+                (codes::UNREACHABLE_CODE, 7),
+                (codes::UNREACHABLE_CODE, 8),
+                (codes::UNREACHABLE_CODE, 10),
+            ]
+        );
+
+        let source = "func main(x) {
+    while (true) {
+        if (x > 10) { break; continue; }
+        x += 1;
+    }
+    for (;;) { return x; }
+    return 0;
+}";
+        assert_eq!(
+            warning_lines(source),
+            vec![(codes::UNREACHABLE_CODE, 3), (codes::UNREACHABLE_CODE, 7)]
+        );
+    }
+
+    #[test]
+    fn test_no_unreachable_warnings_from_propagated_values() {
+        assert_eq!(warning_lines(include_str!("../examples/if.el")), vec![]);
+        let source = "func main() {
+    let debug = false;
+    if (debug) { print(1); }
+    if (true) { print(2); } else {}
+    return 0;
+}";
+        assert_eq!(warning_lines(source), vec![]);
     }
 }
@@ -0,0 +1,226 @@
+/// A range of source text. `start` and `end` are byte offsets into the
+/// source, `line` and `column` are the 1-based position of `start`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Span {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    /// Takes a span measured from the start of `origin`, as though the source
+    /// began there, and returns where it is in the whole source.
+    pub fn relative_to(self, origin: Span) -> Span {
+        let column = if self.line == 1 {
+            origin.column + self.column - 1
+        } else {
+            self.column
+        };
+        Span {
+            start: origin.start + self.start,
+            end: origin.start + self.end,
+            line: origin.line + self.line - 1,
+            column,
+        }
+    }
+
+    /// Returns the span running from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end.max(self.end),
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+/// A value paired with the source range it came from.
+#[derive(Debug, Clone)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Spanned { node, span }
+    }
+}
+
+// Spans are bookkeeping, so two spanned values are equal when their nodes are.
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+
+impl<T: PartialEq> PartialEq<T> for Spanned<T> {
+    fn eq(&self, other: &T) -> bool {
+        self.node == *other
+    }
+}
+
+/// Returns the span covering every token in a non-empty slice.
+pub fn span_of<T>(items: &[Spanned<T>]) -> Span {
+    match (items.first(), items.last()) {
+        (Some(first), Some(last)) => first.span.to(last.span),
+        _ => Span::default(),
+    }
+}
+
+/// Returns the span of the item at `index`. Past the end of the slice this is
+/// an empty span just after the last item, which is where a missing token would go.
+pub fn span_at<T>(items: &[Spanned<T>], index: usize) -> Span {
+    match items.get(index) {
+        Some(item) => item.span,
+        None => match items.last() {
+            Some(last) => Span {
+                start: last.span.end,
+                end: last.span.end,
+                line: last.span.line,
+                column: last.span.column + (last.span.end - last.span.start),
+            },
+            None => Span::default(),
+        },
+    }
+}
+
+/// Wraps plain values with empty spans, for tests that build token streams by hand.
+#[cfg(test)]
+pub fn spanned<T>(nodes: Vec<T>) -> Vec<Spanned<T>> {
+    nodes
+        .into_iter()
+        .map(|node| Spanned::new(node, Span::default()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_lexer;
+    use crate::token::{TemplatePart, Token};
+
+    #[test]
+    fn test_span_to() {
+        let a = Span::new(4, 7, 1, 5);
+        let b = Span::new(10, 12, 2, 3);
+        assert_eq!(a.to(b), Span::new(4, 12, 1, 5));
+    }
+
+    #[test]
+    fn test_lexer_spans() {
+        let (tokens, _) = run_lexer("let x =\n  42;").unwrap();
+        let spans: Vec<Span> = tokens.iter().map(|t| t.span).collect();
+        assert_eq!(
+            spans,
+            vec![
+                Span::new(0, 3, 1, 1),
+                Span::new(4, 5, 1, 5),
+                Span::new(6, 7, 1, 7),
+                Span::new(10, 12, 2, 3),
+                Span::new(12, 13, 2, 5),
+            ]
+        );
+        assert_eq!(tokens[3], Token::Integer(42));
+    }
+
+    #[test]
+    fn test_lexer_error_span() {
+        let err = run_lexer("let x =\n  @;").unwrap_err();
+        assert_eq!(err.code, crate::diagnostic::codes::UNKNOWN_TOKEN);
+        assert_eq!(err.span, Span::new(10, 11, 2, 3));
+    }
+
+    #[test]
+    fn test_lexer_skips_comments() {
+        let source = "let /* a /* nested */ comment */ x = 1; // trailing\n// own line\nx";
+        let (tokens, _) = run_lexer(source).unwrap();
+        let nodes: Vec<&Token> = tokens.iter().map(|t| &t.node).collect();
+        assert_eq!(
+            nodes,
+            vec![
+                &Token::Let,
+                &Token::Identifier("x".to_string()),
+                &Token::Equal,
+                &Token::Integer(1),
+                &Token::SemiColon,
+                &Token::Identifier("x".to_string()),
+            ]
+        );
+        assert_eq!(tokens[1].span, Span::new(33, 34, 1, 34));
+        assert_eq!(tokens[5].span, Span::new(64, 65, 3, 1));
+    }
+
+    #[test]
+    fn test_lexer_doc_comments() {
+        let source = "/// Doc\n/// more\nfunc main() {\n    /// ignored\n    return 0;\n}";
+        let (tokens, main_index) = run_lexer(source).unwrap();
+        assert_eq!(tokens[0].node, Token::DocComment("Doc\nmore".to_string()));
+        assert_eq!(tokens[0].span, Span::new(0, 16, 1, 1));
+        assert_eq!(tokens[1].node, Token::Func);
+        assert_eq!(tokens[main_index].node, Token::Main);
+        assert!(!tokens[3..]
+            .iter()
+            .any(|t| matches!(t.node, Token::DocComment(_))));
+    }
+
+    #[test]
+    fn test_lexer_unterminated_comment() {
+        let err = run_lexer("let x;\n/* open /* nested */").unwrap_err();
+        assert_eq!(err.code, crate::diagnostic::codes::UNTERMINATED_COMMENT);
+        assert_eq!(err.span, Span::new(7, 27, 2, 1));
+    }
+
+    #[test]
+    fn test_lexer_escape_error_span() {
+        let err = run_lexer("let s = \"first\nsecond \\x\";").unwrap_err();
+        assert_eq!(err.code, crate::diagnostic::codes::INVALID_ESCAPE);
+        assert_eq!(err.span, Span::new(22, 24, 2, 8));
+    }
+
+    #[test]
+    fn test_template_code_spans() {
+        let (tokens, _) = run_lexer("let s =\n  \"one\n${a} ${\"${b}\"}\";").unwrap();
+        let parts = match &tokens[3].node {
+            Token::Template(parts) => parts,
+            other => panic!("Expected a template, got {:?}", other),
+        };
+        let code = |part: &TemplatePart| match part {
+            TemplatePart::Code(tokens) => tokens.clone(),
+            other => panic!("Expected code, got {:?}", other),
+        };
+        let a = code(&parts[1]);
+        assert_eq!(a[0].span, Span::new(17, 18, 3, 3));
+        let nested = code(&parts[3]);
+        let b = match &nested[0].node {
+            Token::Template(parts) => code(&parts[0]),
+            other => panic!("Expected a template, got {:?}", other),
+        };
+        assert_eq!(nested[0].span, Span::new(22, 28, 3, 8));
+        assert_eq!(b[0].span, Span::new(25, 26, 3, 11));
+    }
+
+    #[test]
+    fn test_span_relative_to() {
+        let origin = Span::new(10, 20, 3, 5);
+        assert_eq!(
+            Span::new(2, 4, 1, 3).relative_to(origin),
+            Span::new(12, 14, 3, 7)
+        );
+        assert_eq!(
+            Span::new(8, 9, 2, 1).relative_to(origin),
+            Span::new(18, 19, 4, 1)
+        );
+    }
+}
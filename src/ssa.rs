@@ -0,0 +1,834 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    ir::{Block, BlockId, Instruction, IrFunction, Operand, Terminator, Var},
+    span::{Span, Spanned},
+};
+
+/// The dominator tree of a function's control-flow graph. Block `a`
+/// dominates block `b` if every path from the entry to `b` goes through `a`.
+/// Blocks that can't be reached from the entry have no dominators.
+#[derive(Debug)]
+pub struct Dominators {
+    // The immediate dominator of each reachable block; the entry is its own
+    idom: Vec<Option<BlockId>>,
+    // Reachable blocks in reverse postorder
+    order: Vec<BlockId>,
+}
+
+impl Dominators {
+    /// Computes dominators with the iterative algorithm of Cooper, Harvey and
+    /// Kennedy.
+    pub fn new(function: &IrFunction) -> Self {
+        let order = reverse_postorder(function);
+        let mut position = vec![usize::MAX; function.blocks.len()];
+        for (index, block) in order.iter().enumerate() {
+            position[block.0 as usize] = index;
+        }
+        let predecessors = function.predecessors();
+
+        let mut idom = vec![None; function.blocks.len()];
+        idom[IrFunction::ENTRY.0 as usize] = Some(IrFunction::ENTRY);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &order[1..] {
+                let mut new_idom = None;
+                for &predecessor in &predecessors[block.0 as usize] {
+                    if idom[predecessor.0 as usize].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => predecessor,
+                        Some(other) => intersect(&idom, &position, predecessor, other),
+                    });
+                }
+                if new_idom.is_some() && idom[block.0 as usize] != new_idom {
+                    idom[block.0 as usize] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        Dominators { idom, order }
+    }
+
+    /// The closest block that dominates `block`, or `None` for the entry and
+    /// for unreachable blocks.
+    pub fn immediate_dominator(&self, block: BlockId) -> Option<BlockId> {
+        self.idom[block.0 as usize].filter(|&idom| idom != block)
+    }
+
+    pub fn is_reachable(&self, block: BlockId) -> bool {
+        self.idom[block.0 as usize].is_some()
+    }
+
+    /// Whether `a` dominates `b`. Every reachable block dominates itself.
+    pub fn dominates(&self, a: BlockId, b: BlockId) -> bool {
+        if !self.is_reachable(b) {
+            return false;
+        }
+        let mut block = b;
+        loop {
+            if block == a {
+                return true;
+            }
+            match self.immediate_dominator(block) {
+                Some(idom) => block = idom,
+                None => return false,
+            }
+        }
+    }
+
+    /// The reachable blocks in reverse postorder, so every block comes before
+    /// the blocks it dominates.
+    pub fn reverse_postorder(&self) -> &[BlockId] {
+        &self.order
+    }
+
+    /// The blocks each block immediately dominates.
+    pub fn children(&self) -> Vec<Vec<BlockId>> {
+        let mut children = vec![Vec::new(); self.idom.len()];
+        for &block in &self.order {
+            if let Some(idom) = self.immediate_dominator(block) {
+                children[idom.0 as usize].push(block);
+            }
+        }
+        children
+    }
+
+    /// The dominance frontier of each block: the blocks it doesn't strictly
+    /// dominate but does dominate a predecessor of. These are where the
+    /// values of variables assigned in the block meet others.
+    pub fn frontiers(&self, function: &IrFunction) -> Vec<Vec<BlockId>> {
+        let mut frontiers = vec![Vec::new(); function.blocks.len()];
+        for (index, predecessors) in function.predecessors().into_iter().enumerate() {
+            let block = BlockId(index as u32);
+            let predecessors: Vec<_> = predecessors
+                .into_iter()
+                .filter(|&predecessor| self.is_reachable(predecessor))
+                .collect();
+            if predecessors.len() < 2 {
+                continue;
+            }
+            let idom = self.immediate_dominator(block);
+            for mut runner in predecessors {
+                while Some(runner) != idom {
+                    let frontier: &mut Vec<BlockId> = &mut frontiers[runner.0 as usize];
+                    if !frontier.contains(&block) {
+                        frontier.push(block);
+                    }
+                    match self.immediate_dominator(runner) {
+                        Some(next) => runner = next,
+                        None => break,
+                    }
+                }
+            }
+        }
+        frontiers
+    }
+}
+
+// Walks up from `a` and `b` to the closest block dominating both.
+fn intersect(idom: &[Option<BlockId>], position: &[usize], a: BlockId, b: BlockId) -> BlockId {
+    let (mut a, mut b) = (a, b);
+    let idom = |block: BlockId| idom[block.0 as usize].expect("only processed blocks");
+    while a != b {
+        while position[a.0 as usize] > position[b.0 as usize] {
+            a = idom(a);
+        }
+        while position[b.0 as usize] > position[a.0 as usize] {
+            b = idom(b);
+        }
+    }
+    a
+}
+
+fn reverse_postorder(function: &IrFunction) -> Vec<BlockId> {
+    let mut visited = vec![false; function.blocks.len()];
+    let mut postorder = Vec::with_capacity(function.blocks.len());
+    // Each entry is a block and how many of its successors were visited.
+    // They are visited last first, so the first one comes first in the end.
+    let mut stack = vec![(IrFunction::ENTRY, 0)];
+    visited[IrFunction::ENTRY.0 as usize] = true;
+    while let Some((block, next)) = stack.pop() {
+        let successors = function.block(block).terminator.node.successors();
+        match successors.iter().rev().nth(next) {
+            Some(&successor) => {
+                stack.push((block, next + 1));
+                if !visited[successor.0 as usize] {
+                    visited[successor.0 as usize] = true;
+                    stack.push((successor, 0));
+                }
+            }
+            None => postorder.push(block),
+        }
+    }
+    postorder.reverse();
+    postorder
+}
+
+/// The variables that may still be read on entry to and exit from each block.
+/// A phi reads its sources at the end of their predecessors, not in its own
+/// block.
+#[derive(Debug)]
+pub struct Liveness {
+    pub live_in: Vec<HashSet<Var>>,
+    pub live_out: Vec<HashSet<Var>>,
+}
+
+impl Liveness {
+    pub fn new(function: &IrFunction) -> Self {
+        let count = function.blocks.len();
+        // What each block reads before assigning, and what it assigns
+        let mut reads = vec![HashSet::new(); count];
+        let mut assigns = vec![HashSet::new(); count];
+        for block in function.block_ids() {
+            let index = block.0 as usize;
+            let block = function.block(block);
+            for instruction in &block.instructions {
+                if !matches!(instruction.node, Instruction::Phi { .. }) {
+                    for var in vars(instruction.node.operands()) {
+                        if !assigns[index].contains(&var) {
+                            reads[index].insert(var);
+                        }
+                    }
+                }
+                if let Some(dest) = instruction.node.dest() {
+                    assigns[index].insert(dest);
+                }
+            }
+            for var in vars(block.terminator.node.operands()) {
+                if !assigns[index].contains(&var) {
+                    reads[index].insert(var);
+                }
+            }
+        }
+
+        let mut live_in: Vec<HashSet<Var>> = vec![HashSet::new(); count];
+        let mut live_out: Vec<HashSet<Var>> = vec![HashSet::new(); count];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in function.block_ids().rev() {
+                let index = block.0 as usize;
+                let mut out = HashSet::new();
+                for successor in function.block(block).terminator.node.successors() {
+                    out.extend(live_in[successor.0 as usize].iter().copied());
+                    for instruction in &function.block(successor).instructions {
+                        if let Instruction::Phi { sources, .. } = &instruction.node {
+                            for (from, value) in sources {
+                                if let (true, Operand::Var(var)) = (*from == block, value) {
+                                    out.insert(*var);
+                                }
+                            }
+                        }
+                    }
+                }
+                let mut inside = reads[index].clone();
+                inside.extend(out.difference(&assigns[index]).copied());
+                if out != live_out[index] || inside != live_in[index] {
+                    live_out[index] = out;
+                    live_in[index] = inside;
+                    changed = true;
+                }
+            }
+        }
+        Liveness { live_in, live_out }
+    }
+}
+
+pub(crate) fn vars(operands: Vec<&Operand>) -> impl Iterator<Item = Var> + '_ {
+    operands.into_iter().filter_map(|operand| match operand {
+        Operand::Var(var) => Some(*var),
+        Operand::Const(_) => None,
+    })
+}
+
+/// Converts a function to SSA form, where every variable is assigned exactly
+/// once. Wherever different assignments of a variable meet, a phi picks
+/// between them, as long as the variable is read again afterwards. Blocks
+/// that can't be reached are left as they are.
+pub fn construct(function: &mut IrFunction) {
+    let dominators = Dominators::new(function);
+    let frontiers = dominators.frontiers(function);
+    let reachable_predecessors: Vec<Vec<BlockId>> = function
+        .predecessors()
+        .into_iter()
+        .map(|predecessors| {
+            predecessors
+                .into_iter()
+                .filter(|&block| dominators.is_reachable(block))
+                .collect()
+        })
+        .collect();
+
+    // Find where each variable is assigned
+    let mut assigned_in: HashMap<Var, Vec<BlockId>> = HashMap::new();
+    for &param in &function.params {
+        assigned_in
+            .entry(param)
+            .or_default()
+            .push(IrFunction::ENTRY);
+    }
+    for &block in dominators.reverse_postorder() {
+        for instruction in &function.block(block).instructions {
+            if let Some(dest) = instruction.node.dest() {
+                let blocks = assigned_in.entry(dest).or_default();
+                if !blocks.contains(&block) {
+                    blocks.push(block);
+                }
+            }
+        }
+    }
+    let liveness = Liveness::new(function);
+
+    // Place phis on the iterated dominance frontier of each variable's
+    // assignments, where the variable is still live. `origins` remembers
+    // which variable each phi is for.
+    let mut origins: Vec<Vec<Var>> = vec![Vec::new(); function.blocks.len()];
+    let mut vars: Vec<_> = assigned_in.keys().copied().collect();
+    vars.sort();
+    for var in vars {
+        let mut worklist = assigned_in[&var].clone();
+        let mut has_phi = HashSet::new();
+        while let Some(block) = worklist.pop() {
+            for &frontier in &frontiers[block.0 as usize] {
+                if !liveness.live_in[frontier.0 as usize].contains(&var)
+                    || !has_phi.insert(frontier)
+                {
+                    continue;
+                }
+                let sources = reachable_predecessors[frontier.0 as usize]
+                    .iter()
+                    .map(|&predecessor| (predecessor, Operand::Var(var)))
+                    .collect();
+                let span = function.var(var).span;
+                let phis = &mut origins[frontier.0 as usize];
+                function.block_mut(frontier).instructions.insert(
+                    phis.len(),
+                    Spanned::new(Instruction::Phi { dest: var, sources }, span),
+                );
+                phis.push(var);
+                if !assigned_in[&var].contains(&frontier) {
+                    worklist.push(frontier);
+                }
+            }
+        }
+    }
+
+    let mut renamer = Renamer {
+        stacks: HashMap::new(),
+        first_assigned: HashSet::new(),
+        children: dominators.children(),
+        origins,
+    };
+    for &param in &function.params {
+        renamer.first_assigned.insert(param);
+        renamer.stacks.entry(param).or_default().push(param);
+    }
+    renamer.rename(function, IrFunction::ENTRY);
+}
+
+// Gives each assignment its own variable, walking the dominator tree so the
+// assignment reaching each read is the latest one on the way down to it.
+struct Renamer {
+    // The current version of each original variable, latest last
+    stacks: HashMap<Var, Vec<Var>>,
+    // The first assignment of a variable keeps it, the rest get new versions
+    first_assigned: HashSet<Var>,
+    children: Vec<Vec<BlockId>>,
+    origins: Vec<Vec<Var>>,
+}
+
+impl Renamer {
+    fn current(&self, var: Var) -> Var {
+        self.stacks
+            .get(&var)
+            .and_then(|stack| stack.last())
+            .copied()
+            // Nothing assigns it on the way here, so leave the read alone
+            .unwrap_or(var)
+    }
+
+    fn replace(&self, operand: &mut Operand) {
+        if let Operand::Var(var) = operand {
+            *var = self.current(*var);
+        }
+    }
+
+    fn assign(&mut self, function: &mut IrFunction, var: Var) -> Var {
+        let version = if self.first_assigned.insert(var) {
+            var
+        } else {
+            function.version_of(var)
+        };
+        self.stacks.entry(var).or_default().push(version);
+        version
+    }
+
+    fn rename(&mut self, function: &mut IrFunction, block: BlockId) {
+        let mut assigned = Vec::new();
+        let phis = self.origins[block.0 as usize].len();
+        let mut instructions = std::mem::take(&mut function.block_mut(block).instructions);
+        for (index, instruction) in instructions.iter_mut().enumerate() {
+            // A phi's sources are filled in from its predecessors
+            if index >= phis {
+                for operand in instruction.node.operands_mut() {
+                    self.replace(operand);
+                }
+            }
+            if let Some(dest) = instruction.node.dest_mut() {
+                let original = if index < phis {
+                    self.origins[block.0 as usize][index]
+                } else {
+                    *dest
+                };
+                *dest = self.assign(function, original);
+                assigned.push(original);
+            }
+        }
+        function.block_mut(block).instructions = instructions;
+        for operand in function.block_mut(block).terminator.node.operands_mut() {
+            self.replace(operand);
+        }
+
+        let mut successors = function.block(block).terminator.node.successors();
+        successors.dedup();
+        for successor in successors {
+            let origins = &self.origins[successor.0 as usize];
+            for (index, &origin) in origins.iter().enumerate() {
+                let current = self.current(origin);
+                let phi = &mut function.block_mut(successor).instructions[index].node;
+                if let Instruction::Phi { sources, .. } = phi {
+                    for (from, value) in sources.iter_mut() {
+                        if *from == block {
+                            *value = Operand::Var(current);
+                        }
+                    }
+                }
+            }
+        }
+
+        for child in self.children[block.0 as usize].clone() {
+            self.rename(function, child);
+        }
+        for var in assigned {
+            self.stacks.get_mut(&var).expect("pushed above").pop();
+        }
+    }
+}
+
+/// Takes a function out of SSA form by replacing each phi with copies at the
+/// end of its predecessors. An edge from a block that branches to a block with
+/// several predecessors gets a block of its own first, so the copies only run
+/// on that edge.
+pub fn destruct(function: &mut IrFunction) {
+    let predecessors = function.predecessors();
+    for block in function.block_ids().collect::<Vec<_>>() {
+        let phis: Vec<_> = function
+            .block(block)
+            .instructions
+            .iter()
+            .take_while(|instruction| matches!(instruction.node, Instruction::Phi { .. }))
+            .cloned()
+            .collect();
+        if phis.is_empty() {
+            continue;
+        }
+        function.block_mut(block).instructions.drain(..phis.len());
+
+        let mut copies: HashMap<BlockId, Vec<(Var, Operand, Span)>> = HashMap::new();
+        let mut order = Vec::new();
+        for phi in &phis {
+            let Instruction::Phi { dest, sources } = &phi.node else {
+                unreachable!("only phis were taken");
+            };
+            for (from, value) in sources {
+                if !order.contains(from) {
+                    order.push(*from);
+                }
+                copies
+                    .entry(*from)
+                    .or_default()
+                    .push((*dest, value.clone(), phi.span));
+            }
+        }
+
+        let critical = predecessors[block.0 as usize].len() > 1;
+        for from in order {
+            let mut at = from;
+            if critical && function.block(from).terminator.node.successors().len() > 1 {
+                at = split_edge(function, from, block);
+            }
+            let copies = copies.remove(&from).unwrap_or_default();
+            emit_parallel_copies(function, at, copies);
+        }
+    }
+}
+
+// Puts a new block on the edge from `from` to `to` and returns it.
+fn split_edge(function: &mut IrFunction, from: BlockId, to: BlockId) -> BlockId {
+    let span = function.block(from).terminator.span;
+    function.blocks.push(Block {
+        instructions: Vec::new(),
+        terminator: Spanned::new(Terminator::Jump(to), span),
+    });
+    let middle = BlockId(function.blocks.len() as u32 - 1);
+    if let Terminator::Branch {
+        then, otherwise, ..
+    } = &mut function.block_mut(from).terminator.node
+    {
+        for target in [then, otherwise] {
+            if *target == to {
+                *target = middle;
+            }
+        }
+    }
+    middle
+}
+
+// The copies out of phis all happen at once, so if one overwrites a variable
+// another still reads, every value goes through a temporary first.
+fn emit_parallel_copies(
+    function: &mut IrFunction,
+    block: BlockId,
+    copies: Vec<(Var, Operand, Span)>,
+) {
+    let dests: HashSet<Var> = copies.iter().map(|(dest, ..)| *dest).collect();
+    let overlapping = copies.iter().any(
+        |(dest, value, _)| matches!(value, Operand::Var(var) if dests.contains(var) && var != dest),
+    );
+
+    let mut instructions = Vec::new();
+    let mut finals = Vec::new();
+    for (dest, value, span) in copies {
+        if value == Operand::Var(dest) {
+            continue;
+        }
+        if overlapping {
+            let temporary = function.new_var(None, span);
+            instructions.push(Spanned::new(
+                Instruction::Copy {
+                    dest: temporary,
+                    value,
+                },
+                span,
+            ));
+            finals.push(Spanned::new(
+                Instruction::Copy {
+                    dest,
+                    value: Operand::Var(temporary),
+                },
+                span,
+            ));
+        } else {
+            instructions.push(Spanned::new(Instruction::Copy { dest, value }, span));
+        }
+    }
+    instructions.extend(finals);
+    function.block_mut(block).instructions.extend(instructions);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_program, ir::IrProgram, run_lexer};
+
+    fn lower(source: &str) -> IrFunction {
+        let (tokens, _) = run_lexer(source).unwrap();
+        let program = get_program(&tokens).unwrap();
+        let mut program = IrProgram::lower(&program).unwrap();
+        program.functions.pop().unwrap()
+    }
+
+    fn ssa(source: &str) -> IrFunction {
+        let mut function = lower(source);
+        construct(&mut function);
+        function
+    }
+
+    // The phis at the start of `block`, printed.
+    fn phis(function: &IrFunction, block: u32) -> Vec<String> {
+        function
+            .block(BlockId(block))
+            .instructions
+            .iter()
+            .filter(|instruction| matches!(instruction.node, Instruction::Phi { .. }))
+            .map(|instruction| function.fmt_instruction(&instruction.node))
+            .collect()
+    }
+
+    fn assert_assigned_once(function: &IrFunction) {
+        let mut assigned = HashSet::new();
+        for &param in &function.params {
+            assigned.insert(param);
+        }
+        for block in &function.blocks {
+            for instruction in &block.instructions {
+                if let Some(dest) = instruction.node.dest() {
+                    assert!(
+                        assigned.insert(dest),
+                        "{} is assigned twice",
+                        function.var(dest).name
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_dominators_of_if() {
+        let function = lower(include_str!("../examples/if.el"));
+        let dominators = Dominators::new(&function);
+        let idoms: Vec<_> = function
+            .block_ids()
+            .map(|block| dominators.immediate_dominator(block).map(|idom| idom.0))
+            .collect();
+        assert_eq!(idoms, vec![None, Some(0), Some(0), Some(2), Some(2)]);
+        assert!(dominators.dominates(BlockId(0), BlockId(4)));
+        assert!(dominators.dominates(BlockId(2), BlockId(2)));
+        assert!(!dominators.dominates(BlockId(1), BlockId(2)));
+        assert_eq!(
+            dominators.children(),
+            vec![
+                vec![BlockId(1), BlockId(2)],
+                vec![],
+                vec![BlockId(3), BlockId(4)],
+                vec![],
+                vec![]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dominance_frontiers_of_a_loop() {
+        let function = lower(
+            "func main() {
+                let i = 0;
+                while (i < 10) {
+                    if (i == 5) { i += 2; } else { i += 1; }
+                }
+                return i;
+            }",
+        );
+        let dominators = Dominators::new(&function);
+        let frontiers = dominators.frontiers(&function);
+        let ids = |ids: &[u32]| ids.iter().map(|&id| BlockId(id)).collect::<Vec<_>>();
+        // b1 is the loop header, b2 the body, b4 and b5 the branches of the
+        // `if` and b6 where they join
+        assert_eq!(
+            frontiers,
+            vec![
+                ids(&[]),
+                ids(&[1]),
+                ids(&[1]),
+                ids(&[]),
+                ids(&[6]),
+                ids(&[6]),
+                ids(&[1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unreachable_blocks_have_no_dominators() {
+        let function = lower("func main() { return 1; print(2); }");
+        let dominators = Dominators::new(&function);
+        assert!(!dominators.is_reachable(BlockId(1)));
+        assert_eq!(dominators.immediate_dominator(BlockId(1)), None);
+        assert_eq!(dominators.reverse_postorder(), &[BlockId(0)]);
+    }
+
+    #[test]
+    fn test_phis_in_the_while_example() {
+        let function = ssa(include_str!("../examples/while.el"));
+        assert_eq!(
+            phis(&function, 1),
+            vec![
+                "i.1 = phi(b0: i, b2: i.2)",
+                "sum.1 = phi(b0: sum, b2: sum.2)"
+            ]
+        );
+        for block in [0, 2, 3] {
+            assert!(phis(&function, block).is_empty());
+        }
+        assert_eq!(
+            function.fmt_terminator(&function.block(BlockId(3)).terminator.node),
+            "return sum.1"
+        );
+        assert_assigned_once(&function);
+    }
+
+    #[test]
+    fn test_phis_in_the_print_example() {
+        let function = ssa(include_str!("../examples/print.el"));
+        assert_eq!(
+            phis(&function, 1),
+            vec![
+                "squares.1 = phi(b0: squares, b2: squares.2)",
+                "i.1 = phi(b0: i, b2: i.2)"
+            ]
+        );
+        assert_assigned_once(&function);
+    }
+
+    #[test]
+    fn test_no_phis_in_the_if_example() {
+        // Every branch returns, so no values meet
+        let function = ssa(include_str!("../examples/if.el"));
+        for block in function.block_ids() {
+            assert!(phis(&function, block.0).is_empty());
+        }
+        assert_assigned_once(&function);
+    }
+
+    #[test]
+    fn test_phis_where_if_branches_meet() {
+        let function = ssa("func main() {
+                let x = 0;
+                let y = 1;
+                if (y > 0) { x = 1; } else { x = 2; y = 3; }
+                let z = 4;
+                if (z > 3) { x += 1; }
+                return x + y;
+            }");
+        assert_eq!(
+            phis(&function, 3),
+            vec!["x.3 = phi(b1: x.1, b2: x.2)", "y.2 = phi(b1: y, b2: y.1)"]
+        );
+        assert_eq!(phis(&function, 5), vec!["x.5 = phi(b3: x.3, b4: x.4)"]);
+        assert_assigned_once(&function);
+    }
+
+    #[test]
+    fn test_no_phis_for_variables_that_are_dead_at_the_join() {
+        let function = ssa("func main() {
+                let i = 0;
+                while (i < 3) {
+                    let double = i * 2;
+                    if (double > 2) { print(double); }
+                    i += 1;
+                }
+                let unused = 0;
+                if (i > 1) { unused = 1; }
+                return i;
+            }");
+        let all: Vec<_> = function
+            .block_ids()
+            .flat_map(|block| phis(&function, block.0))
+            .collect();
+        assert_eq!(all, vec!["i.1 = phi(b0: i, b5: i.2)"]);
+    }
+
+    #[test]
+    fn test_liveness() {
+        let function = lower(include_str!("../examples/while.el"));
+        let liveness = Liveness::new(&function);
+        let names = |vars: &HashSet<Var>| {
+            let mut names: Vec<_> = vars
+                .iter()
+                .map(|&var| function.var(var).name.clone())
+                .collect();
+            names.sort();
+            names
+        };
+        assert!(liveness.live_in[0].is_empty());
+        assert_eq!(names(&liveness.live_out[0]), vec!["i", "sum"]);
+        assert_eq!(names(&liveness.live_in[1]), vec!["i", "sum"]);
+        assert_eq!(names(&liveness.live_in[3]), vec!["sum"]);
+        assert!(liveness.live_out[3].is_empty());
+    }
+
+    #[test]
+    fn test_destruct_the_while_example() {
+        let mut function = ssa(include_str!("../examples/while.el"));
+        destruct(&mut function);
+        assert_eq!(
+            function.to_string(),
+            "func main():
+b0:
+    i = 0
+    sum = 0
+    i.1 = i
+    sum.1 = sum
+    jump b1
+b1:
+    %0 = i.1 < 10
+    branch %0, b2, b3
+b2:
+    sum.2 = sum.1 + i.1
+    i.2 = i.1 + 1
+    i.1 = i.2
+    sum.1 = sum.2
+    jump b1
+b3:
+    return sum.1
+"
+        );
+    }
+
+    #[test]
+    fn test_destruct_splits_critical_edges() {
+        let mut function = ssa("func main() {
+            let x = 0;
+            if (x < 1) { x = 1; }
+            return x;
+        }");
+        assert_eq!(phis(&function, 2), vec!["x.2 = phi(b0: x, b1: x.1)"]);
+        destruct(&mut function);
+        assert_eq!(
+            function.to_string(),
+            "func main():
+b0:
+    x = 0
+    %0 = x < 1
+    branch %0, b1, b3
+b1:
+    x.1 = 1
+    x.2 = x.1
+    jump b2
+b2:
+    return x.2
+b3:
+    x.2 = x
+    jump b2
+"
+        );
+    }
+
+    #[test]
+    fn test_destruct_keeps_parallel_copies_apart() {
+        let mut function = ssa("func main() {
+            let a = 1;
+            let b = 2;
+            while (a < b) {
+                let t = a;
+                a = b;
+                b = t;
+            }
+            return a - b;
+        }");
+        // Make the phis swap each other's values around the loop
+        let header = function.block_mut(BlockId(1));
+        let dests: Vec<_> = header.instructions[..2]
+            .iter()
+            .map(|instruction| instruction.node.dest().unwrap())
+            .collect();
+        for (index, instruction) in header.instructions[..2].iter_mut().enumerate() {
+            if let Instruction::Phi { sources, .. } = &mut instruction.node {
+                sources[1].1 = Operand::Var(dests[1 - index]);
+            }
+        }
+        destruct(&mut function);
+        let latch: Vec<_> = function
+            .block(BlockId(2))
+            .instructions
+            .iter()
+            .map(|instruction| function.fmt_instruction(&instruction.node))
+            .collect();
+        assert_eq!(
+            latch[latch.len() - 4..],
+            ["%2 = b.1", "%3 = a.1", "a.1 = %2", "b.1 = %3"]
+        );
+    }
+}
@@ -1,17 +1,42 @@
-use crate::{expression::Expression, token::Token};
+use crate::{
+    diagnostic::{codes, Diagnostic},
+    expression::{parse_expression_list, Expression},
+    span::{span_at, span_of, Span, Spanned},
+    token::{token_at, Token},
+};
+
+#[derive(Debug)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+// As with expressions, spans are ignored when comparing statements.
+impl PartialEq for Statement {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
 
 #[derive(Debug, PartialEq)]
-pub enum Statement {
+pub enum StatementKind {
     DeclareStatement {
         identifier: Token,
         value: Option<Expression>,
     },
     ArrayAppend {
-        identifier: Token,
+        identifier: Spanned<Token>,
         value: Expression,
     },
+    // `target = value`, where the target is a variable or an index into one
     AssignStatement {
-        identifier: Token,
+        target: Expression,
+        value: Expression,
+    },
+    // `target op= value`, where `operator` is the binary operator applied
+    CompoundAssign {
+        target: Expression,
+        operator: Token,
         value: Expression,
     },
     IfStatement {
@@ -23,15 +48,37 @@ pub enum Statement {
         cond: Expression,
         loop_stmt: Vec<Statement>,
     },
+    // for (init; cond; step) { body }, where each part may be left out
+    ForStatement {
+        init: Option<Box<Statement>>,
+        cond: Option<Expression>,
+        step: Option<Box<Statement>>,
+        body: Vec<Statement>,
+    },
+    // for variable in iterable { body }
+    ForInStatement {
+        variable: Spanned<Token>,
+        iterable: Expression,
+        body: Vec<Statement>,
+    },
     ReturnStatement {
         value: Expression,
     },
+    // An expression evaluated only for its side effects, like a call
+    ExpressionStatement {
+        expr: Expression,
+    },
+    PrintStatement {
+        args: Vec<Expression>,
+    },
+    BreakStatement,
+    ContinueStatement,
 }
 
 pub fn get_statement_slice(
-    tokens: &[Token],
+    tokens: &[Spanned<Token>],
     curr_index: usize,
-) -> Result<(&[Token], usize), String> {
+) -> Result<(&[Spanned<Token>], usize), Diagnostic> {
     // Collect tokens until a semicolon is found
     let mut index = curr_index;
     while index < tokens.len() && tokens[index] != Token::SemiColon {
@@ -39,282 +86,697 @@ pub fn get_statement_slice(
     }
     // If we've reached the end without finding a semicolon, it's a syntax error
     if index >= tokens.len() {
-        return Err("Syntax error, expected semicolon at end of statement in function body".into());
+        return Err(Diagnostic::error(
+            codes::EXPECTED_TOKEN,
+            "Syntax error, expected semicolon at end of statement in function body",
+            span_at(tokens, index),
+        ));
     }
     // Slice containing tokens for the current statement
     let statement_tokens = &tokens[curr_index..=index];
     Ok((statement_tokens, index + 1))
 }
 
-fn parse_block(tokens: &[Token]) -> Result<(Vec<Statement>, usize), String> {
-    // Assume the block starts with '{'
-    if tokens.is_empty() || tokens[0] != Token::LeftBrace {
-        return Err("Expected '{' to start block".into());
+// Parses `tokens[start..end]` as a single expression. An empty range is
+// reported at the token that closes it, since that's where the expression is missing.
+fn parse_expression(
+    tokens: &[Spanned<Token>],
+    start: usize,
+    end: usize,
+) -> Result<Expression, Diagnostic> {
+    if start >= end {
+        return Err(Diagnostic::error(
+            codes::EXPECTED_EXPRESSION,
+            "Expected an expression",
+            span_at(tokens, start),
+        ));
+    }
+    let (expr, _) = Expression::new(&tokens[start..end])?;
+    Ok(expr)
+}
+
+// The binary operator a compound assignment token like `+=` applies.
+fn compound_operator(token: &Token) -> Option<Token> {
+    match token {
+        Token::AddEqual => Some(Token::Add),
+        Token::SubEqual => Some(Token::Sub),
+        Token::MulEqual => Some(Token::Mul),
+        Token::DivEqual => Some(Token::Div),
+        Token::ModEqual => Some(Token::Mod),
+        _ => None,
+    }
+}
+
+/// Returns how many tokens to skip after a statement that failed to parse, so
+/// that parsing can resume at the next statement. Stops after the next `;`
+/// outside of parentheses, after a nested `{ ... }` block (and any `else`
+/// blocks chained to it), or just before a `}` closing the enclosing block or
+/// a `func` (or its doc comment) starting the next function.
+pub fn synchronize(tokens: &[Spanned<Token>]) -> usize {
+    let mut depth = 0;
+    // `;` inside parentheses belongs to a `for` header, not the end of a statement
+    let mut paren_depth = 0;
+    let mut index = 0;
+    while index < tokens.len() {
+        match tokens[index].node {
+            Token::LeftParen => paren_depth += 1,
+            Token::RightParen => paren_depth = usize::max(paren_depth, 1) - 1,
+            Token::SemiColon if depth == 0 && paren_depth == 0 => return index + 1,
+            Token::LeftBrace => depth += 1,
+            Token::RightBrace if depth == 0 => return index.max(1),
+            Token::RightBrace => {
+                depth -= 1;
+                if depth == 0 && token_at(tokens, index + 1) != Some(&Token::Else) {
+                    return index + 1;
+                }
+            }
+            Token::Func | Token::DocComment(_) => return index.max(1),
+            _ => {}
+        }
+        index += 1;
     }
+    tokens.len()
+}
+
+/// Parses statements until a closing `}`, the next function or the end of input, and
+/// returns them with the number of tokens consumed. A statement that fails to
+/// parse is reported in `errors` and skipped, so one mistake doesn't hide the rest.
+pub fn parse_statements(
+    tokens: &[Spanned<Token>],
+    errors: &mut Vec<Diagnostic>,
+) -> (Vec<Statement>, usize) {
     let mut statements = Vec::new();
-    let mut curr_index = 1; // Skip '{'
-    while curr_index < tokens.len() && tokens[curr_index] != Token::RightBrace {
-        let (stmt, consumed) = Statement::new(&tokens[curr_index..])?;
-        statements.push(stmt);
-        curr_index += consumed;
+    let mut curr_index = 0;
+    while curr_index < tokens.len()
+        && !matches!(
+            tokens[curr_index].node,
+            Token::RightBrace | Token::Func | Token::DocComment(_)
+        )
+    {
+        match Statement::parse(&tokens[curr_index..], errors) {
+            Ok((stmt, consumed)) => {
+                statements.push(stmt);
+                curr_index += consumed;
+            }
+            Err(err) => {
+                errors.push(err);
+                curr_index += synchronize(&tokens[curr_index..]);
+            }
+        }
+    }
+    (statements, curr_index)
+}
+
+// Index of the `)` matching the `(` at `open`.
+fn find_closing_paren(tokens: &[Spanned<Token>], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        match token.node {
+            Token::LeftParen => depth += 1,
+            Token::RightParen => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Parses `for (init; cond; step) { ... }`.
+fn parse_for(
+    tokens: &[Spanned<Token>],
+    errors: &mut Vec<Diagnostic>,
+) -> Result<(StatementKind, usize), Diagnostic> {
+    let close = find_closing_paren(tokens, 1).ok_or_else(|| {
+        Diagnostic::error(
+            codes::EXPECTED_TOKEN,
+            "Syntax error, expected closing parenthesis for for statement",
+            span_at(tokens, tokens.len()),
+        )
+    })?;
+    let header = &tokens[..close];
+    let mut index = 2;
+
+    // The initializer ends with its own semicolon
+    let init = if token_at(header, index) == Some(&Token::SemiColon) {
+        index += 1;
+        None
+    } else {
+        let (init, consumed) = Statement::parse(&header[index..], errors)?;
+        if !matches!(
+            init.kind,
+            StatementKind::DeclareStatement { .. }
+                | StatementKind::AssignStatement { .. }
+                | StatementKind::CompoundAssign { .. }
+        ) {
+            return Err(Diagnostic::error(
+                codes::UNEXPECTED_TOKEN,
+                "Expected a declaration or assignment to start the for loop",
+                init.span,
+            ));
+        }
+        index += consumed;
+        Some(Box::new(init))
+    };
+
+    let semicolon = (index..close)
+        .find(|&i| header[i] == Token::SemiColon)
+        .ok_or_else(|| {
+            Diagnostic::error(
+                codes::EXPECTED_TOKEN,
+                "Syntax error, expected ';' after for loop condition",
+                span_at(tokens, close),
+            )
+        })?;
+    let cond = if semicolon == index {
+        None
+    } else {
+        Some(parse_expression(header, index, semicolon)?)
+    };
+    index = semicolon + 1;
+
+    // The step has no semicolon of its own, so parse it with one borrowed
+    // from the closing parenthesis
+    let step = if index == close {
+        None
+    } else {
+        let mut step_tokens = header[index..].to_vec();
+        step_tokens.push(Spanned::new(Token::SemiColon, tokens[close].span));
+        let (step, consumed) = Statement::parse(&step_tokens, errors)?;
+        if consumed != step_tokens.len()
+            || !matches!(
+                step.kind,
+                StatementKind::AssignStatement { .. }
+                    | StatementKind::CompoundAssign { .. }
+                    | StatementKind::ArrayAppend { .. }
+            )
+        {
+            return Err(Diagnostic::error(
+                codes::UNEXPECTED_TOKEN,
+                "Expected an assignment as the for loop step",
+                span_of(&header[index..]),
+            ));
+        }
+        Some(Box::new(step))
+    };
+
+    if token_at(tokens, close + 1) != Some(&Token::LeftBrace) {
+        return Err(Diagnostic::error(
+            codes::EXPECTED_TOKEN,
+            "Syntax error, expected '{' after for loop header",
+            span_at(tokens, close + 1),
+        ));
+    }
+    let (body, body_consumed) = parse_block(&tokens[close + 1..], errors)?;
+    Ok((
+        StatementKind::ForStatement {
+            init,
+            cond,
+            step,
+            body,
+        },
+        close + 1 + body_consumed,
+    ))
+}
+
+// Parses `for variable in iterable { ... }`.
+fn parse_for_in(
+    tokens: &[Spanned<Token>],
+    errors: &mut Vec<Diagnostic>,
+) -> Result<(StatementKind, usize), Diagnostic> {
+    let variable = match token_at(tokens, 1) {
+        Some(Token::Identifier(_)) => tokens[1].clone(),
+        _ => {
+            return Err(Diagnostic::error(
+                codes::EXPECTED_IDENTIFIER,
+                "Expected '(' or a loop variable after for",
+                span_at(tokens, 1),
+            ))
+        }
+    };
+    if token_at(tokens, 2) != Some(&Token::In) {
+        return Err(Diagnostic::error(
+            codes::EXPECTED_TOKEN,
+            "Syntax error, expected 'in' after the loop variable",
+            span_at(tokens, 2),
+        ));
     }
+
+    // Expressions never contain braces, so the body starts at the first one.
+    // Reaching a `;` first means the body is missing.
+    let open = (3..tokens.len())
+        .find(|&i| tokens[i] == Token::LeftBrace || tokens[i] == Token::SemiColon)
+        .filter(|&i| tokens[i] == Token::LeftBrace)
+        .ok_or_else(|| {
+            Diagnostic::error(
+                codes::EXPECTED_TOKEN,
+                "Syntax error, expected '{' after for loop iterable",
+                span_at(tokens, 3),
+            )
+        })?;
+    let iterable = parse_expression(tokens, 3, open)?;
+
+    let (body, body_consumed) = parse_block(&tokens[open..], errors)?;
+    Ok((
+        StatementKind::ForInStatement {
+            variable,
+            iterable,
+            body,
+        },
+        open + body_consumed,
+    ))
+}
+
+// <expression>, SemiColon
+fn parse_expression_statement(
+    tokens: &[Spanned<Token>],
+) -> Result<(StatementKind, usize), Diagnostic> {
+    let (token_slice, consumed) = get_statement_slice(tokens, 0)?;
+    let expr = parse_expression(token_slice, 0, token_slice.len() - 1)?;
+    Ok((StatementKind::ExpressionStatement { expr }, consumed))
+}
+
+fn parse_block(
+    tokens: &[Spanned<Token>],
+    errors: &mut Vec<Diagnostic>,
+) -> Result<(Vec<Statement>, usize), Diagnostic> {
+    // Assume the block starts with '{'
+    if tokens.is_empty() || tokens[0] != Token::LeftBrace {
+        return Err(Diagnostic::error(
+            codes::EXPECTED_TOKEN,
+            "Expected '{' to start block",
+            span_at(tokens, 0),
+        ));
+    }
+    let (statements, consumed) = parse_statements(&tokens[1..], errors);
+    let mut curr_index = 1 + consumed; // Skip '{'
     if curr_index >= tokens.len() || tokens[curr_index] != Token::RightBrace {
-        return Err("Expected '}' at end of block".into());
+        return Err(Diagnostic::error(
+            codes::EXPECTED_TOKEN,
+            "Expected '}' at end of block",
+            span_at(tokens, curr_index),
+        ));
     }
     curr_index += 1; // Consume '}'
     Ok((statements, curr_index))
 }
 
 impl Statement {
-    pub fn new(tokens: &[Token]) -> Result<(Self, usize), String> {
-        if tokens.is_empty() {
-            return Err("No tokens provided".into());
+    /// Parses one statement, failing on the first syntax error anywhere inside it.
+    pub fn new(tokens: &[Spanned<Token>]) -> Result<(Self, usize), Diagnostic> {
+        let mut errors = Vec::new();
+        let result = Statement::parse(tokens, &mut errors);
+        match errors.into_iter().next() {
+            Some(err) => Err(err),
+            None => result,
         }
+    }
 
-        match tokens[0] {
-            Token::Let => {
-                // We assume the statement is of the form:
-                // let Identifier, Equal, <expression>, SemiColon
-                let (token_slice, consumed) = match get_statement_slice(tokens, 0) {
-                    Ok(output) => output,
-                    Err(e) => return Err(e),
-                };
+    /// Parses one statement. Errors in statements nested inside its blocks are
+    /// recovered from and pushed onto `errors`; an error in the statement itself
+    /// is returned.
+    pub fn parse(
+        tokens: &[Spanned<Token>],
+        errors: &mut Vec<Diagnostic>,
+    ) -> Result<(Self, usize), Diagnostic> {
+        let (kind, consumed) = parse_statement_kind(tokens, errors)?;
+        let span = span_of(&tokens[..consumed]);
+        Ok((Statement { kind, span }, consumed))
+    }
+}
 
-                if token_slice.len() >= 4 {
-                    let identifier = match &token_slice[1] {
-                        Token::Identifier(_) => token_slice[1].clone(),
-                        _ => {
-                            return Err("Assignment statement must start with an identifier".into())
-                        }
-                    };
+fn parse_statement_kind(
+    tokens: &[Spanned<Token>],
+    errors: &mut Vec<Diagnostic>,
+) -> Result<(StatementKind, usize), Diagnostic> {
+    if tokens.is_empty() {
+        return Err(Diagnostic::error(
+            codes::UNEXPECTED_EOF,
+            "No tokens provided",
+            Span::default(),
+        ));
+    }
 
-                    if token_slice[2] != Token::Equal {
-                        return Err(
-                            "Expected '=' after the identifier in assignment statement".into()
-                        );
-                    }
+    match tokens[0].node {
+        Token::Let => {
+            // We assume the statement is of the form:
+            // let Identifier, Equal, <expression>, SemiColon
+            let (token_slice, consumed) = get_statement_slice(tokens, 0)?;
 
-                    let expr = Expression::new(&token_slice[3..token_slice.len() - 1])?;
+            if token_slice.len() >= 4 {
+                let identifier = match &token_slice[1].node {
+                    Token::Identifier(_) => token_slice[1].node.clone(),
+                    _ => {
+                        return Err(Diagnostic::error(
+                            codes::EXPECTED_IDENTIFIER,
+                            "Assignment statement must start with an identifier",
+                            token_slice[1].span,
+                        ))
+                    }
+                };
 
-                    return Ok((
-                        Statement::DeclareStatement {
-                            identifier,
-                            value: Some(expr.0),
-                        },
-                        consumed,
+                if token_slice[2] != Token::Equal {
+                    return Err(Diagnostic::error(
+                        codes::EXPECTED_TOKEN,
+                        "Expected '=' after the identifier in assignment statement",
+                        token_slice[2].span,
                     ));
-                } else {
-                    return Err("Syntax error, expected an assignment statement ".into());
                 }
+
+                let expr = parse_expression(token_slice, 3, token_slice.len() - 1)?;
+
+                Ok((
+                    StatementKind::DeclareStatement {
+                        identifier,
+                        value: Some(expr),
+                    },
+                    consumed,
+                ))
+            } else {
+                Err(Diagnostic::error(
+                    codes::UNEXPECTED_TOKEN,
+                    "Syntax error, expected an assignment statement ",
+                    span_of(token_slice),
+                ))
             }
-            Token::Identifier(_) => {
-                // We assume the statement is of the form:
-                // Identifier, Equal, <expression>, SemiColon
-                let (token_slice, consumed) = get_statement_slice(tokens, 0)?;
-                let identifier = token_slice[0].clone();
-                if token_slice.len() >= 3
-                    && token_slice[1] == Token::Dot
-                    && token_slice[2] == Token::Append
-                {
-                    //there should be an expression (expr);
-
-                    let (expr, _) = Expression::new(&token_slice[4..token_slice.len() - 2])?;
-
-                    return Ok((
-                        Statement::ArrayAppend {
-                            identifier,
-                            value: expr,
-                        },
-                        consumed,
-                    ));
-                }
-                if token_slice.len() >= 3 {
-                    let identifier = token_slice[0].clone();
-                    if token_slice[1] != Token::Equal {
-                        return Err(
-                            "Expected '=' after the identifier in assignment statement".into()
-                        );
-                    }
-                    let expr = Expression::new(&token_slice[2..token_slice.len() - 1])?;
-                    return Ok((
-                        Statement::AssignStatement {
-                            identifier,
-                            value: expr.0,
-                        },
-                        consumed,
+        }
+        Token::Identifier(_) => {
+            // We assume the statement is of the form:
+            // Identifier, Equal, <expression>, SemiColon
+            let (token_slice, consumed) = get_statement_slice(tokens, 0)?;
+            let identifier = token_slice[0].clone();
+            if token_slice.len() >= 3
+                && token_slice[1] == Token::Dot
+                && token_slice[2] == Token::Append
+            {
+                //there should be an expression (expr);
+
+                let expr = parse_expression(token_slice, 4, token_slice.len() - 2)?;
+
+                return Ok((
+                    StatementKind::ArrayAppend {
+                        identifier,
+                        value: expr,
+                    },
+                    consumed,
+                ));
+            }
+            if token_slice.len() >= 3 {
+                let equal = match token_slice.iter().position(|token| {
+                    *token == Token::Equal || compound_operator(&token.node).is_some()
+                }) {
+                    Some(equal) => equal,
+                    None => return parse_expression_statement(tokens),
+                };
+                let target = parse_expression(token_slice, 0, equal)?;
+                if target.place().is_none() {
+                    return Err(Diagnostic::error(
+                        codes::UNEXPECTED_TOKEN,
+                        "Invalid assignment target, expected a variable or an array element",
+                        target.span,
                     ));
-                } else {
-                    return Err("Syntax error, expected an assignment statement".into());
                 }
-            }
-            Token::Return => {
-                //now, since the first token is a return, we get a slice until the next semi colon
-                let (token_slice, consumed) = match get_statement_slice(&tokens, 0) {
-                    Ok(output) => output,
-                    Err(e) => return Err(e),
+                let value = parse_expression(token_slice, equal + 1, token_slice.len() - 1)?;
+                let kind = match compound_operator(&token_slice[equal].node) {
+                    Some(operator) => StatementKind::CompoundAssign {
+                        target,
+                        operator,
+                        value,
+                    },
+                    None => StatementKind::AssignStatement { target, value },
                 };
+                Ok((kind, consumed))
+            } else {
+                parse_expression_statement(tokens)
+            }
+        }
+        Token::Integer(_)
+        | Token::Float(_)
+        | Token::Boolean(_)
+        | Token::String(_)
+        | Token::Template(_)
+        | Token::LeftParen
+        | Token::LeftSquare
+        | Token::Sub
+        | Token::Not
+        | Token::BitNot => parse_expression_statement(tokens),
+        Token::Return => {
+            //now, since the first token is a return, we get a slice until the next semi colon
+            let (token_slice, consumed) = get_statement_slice(tokens, 0)?;
 
-                let expr = Expression::new(&token_slice[1..token_slice.len() - 1])?;
-                return Ok((Statement::ReturnStatement { value: expr.0 }, consumed));
+            let expr = parse_expression(token_slice, 1, token_slice.len() - 1)?;
+            Ok((StatementKind::ReturnStatement { value: expr }, consumed))
+        }
+        Token::Break | Token::Continue => {
+            let (token_slice, consumed) = get_statement_slice(tokens, 0)?;
+            if token_slice.len() != 2 {
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_TOKEN,
+                    format!("Expected ';' after '{}'", tokens[0].node),
+                    token_slice[1].span,
+                ));
             }
-            Token::If => {
-                // If, LeftParen, <condition tokens>, RightParen, LeftBrace, <if body tokens>, RightBrace,
-                // optionally Else, LeftBrace, <else body tokens>, RightBrace
-                if tokens.len() > 2 && tokens.get(1) == Some(&Token::LeftParen) {
-                    // Find the matching right parenthesis for the condition
-                    let mut paren_depth = 0;
-                    let mut right_paren_index = None;
-                    for i in 1..tokens.len() {
-                        match tokens[i] {
-                            Token::LeftParen => {
-                                paren_depth += 1;
-                            }
-                            Token::RightParen => {
-                                paren_depth -= 1;
-                                if paren_depth == 0 {
-                                    right_paren_index = Some(i);
-                                    break;
-                                }
+            let kind = if tokens[0] == Token::Break {
+                StatementKind::BreakStatement
+            } else {
+                StatementKind::ContinueStatement
+            };
+            Ok((kind, consumed))
+        }
+        Token::For => {
+            if token_at(tokens, 1) == Some(&Token::LeftParen) {
+                parse_for(tokens, errors)
+            } else {
+                parse_for_in(tokens, errors)
+            }
+        }
+        Token::Print => {
+            // Print, LeftParen, <expressions separated by commas>, RightParen, SemiColon
+            let (token_slice, consumed) = get_statement_slice(tokens, 0)?;
+            if token_at(token_slice, 1) != Some(&Token::LeftParen) {
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_TOKEN,
+                    "Expected '(' after print",
+                    span_at(token_slice, 1),
+                ));
+            }
+            let (args, args_end) = parse_expression_list(
+                token_slice,
+                2,
+                Token::RightParen,
+                "Expected ')' after print arguments",
+            )?;
+            if args_end != token_slice.len() - 1 {
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_TOKEN,
+                    "Expected ';' after print statement",
+                    span_at(token_slice, args_end),
+                ));
+            }
+            Ok((StatementKind::PrintStatement { args }, consumed))
+        }
+        Token::If => {
+            // If, LeftParen, <condition tokens>, RightParen, LeftBrace, <if body tokens>, RightBrace,
+            // optionally Else, LeftBrace, <else body tokens>, RightBrace
+            if tokens.len() > 2 && token_at(tokens, 1) == Some(&Token::LeftParen) {
+                // Find the matching right parenthesis for the condition
+                let mut paren_depth = 0;
+                let mut right_paren_index = None;
+                for (i, token) in tokens.iter().enumerate().skip(1) {
+                    match token.node {
+                        Token::LeftParen => {
+                            paren_depth += 1;
+                        }
+                        Token::RightParen => {
+                            paren_depth -= 1;
+                            if paren_depth == 0 {
+                                right_paren_index = Some(i);
+                                break;
                             }
-                            _ => {}
                         }
+                        _ => {}
                     }
-                    let right_paren_index = match right_paren_index {
-                        Some(i) => i,
-                        None => {
-                            return Err(
-                                "Syntax error, expected closing parentheses for if statement"
-                                    .into(),
-                            )
-                        }
-                    };
+                }
+                let right_paren_index = match right_paren_index {
+                    Some(i) => i,
+                    None => {
+                        return Err(Diagnostic::error(
+                            codes::EXPECTED_TOKEN,
+                            "Syntax error, expected closing parentheses for if statement",
+                            span_at(tokens, tokens.len()),
+                        ))
+                    }
+                };
 
-                    // Parse the condition from tokens[2..right_paren_index]
-                    let (cond_expr, _cond_consumed) =
-                        Expression::new(&tokens[2..right_paren_index])?;
+                // Parse the condition from tokens[2..right_paren_index]
+                let cond_expr = parse_expression(tokens, 2, right_paren_index)?;
 
-                    // After the condition, expect a left brace for the if-block
-                    if tokens.get(right_paren_index + 1) != Some(&Token::LeftBrace) {
-                        return Err("Syntax error, expected '{' after if condition".into());
-                    }
-                    // use parse_block to parse statements enclosed in {}
-                    let (if_body, body_consumed) = parse_block(&tokens[right_paren_index + 1..])?;
-                    let mut curr_index = right_paren_index + 1 + body_consumed;
-
-                    // Check for an optional 'else' block
-                    let mut else_body = None;
-                    if tokens.get(curr_index) == Some(&Token::Else) {
-                        curr_index += 1; // consume 'else'
-                        if tokens.get(curr_index) == Some(&Token::If) {
-                            // This is an 'else if' so parse it as a nested if-statement
-                            let (nested_if, consumed_nested) =
-                                Statement::new(&tokens[curr_index..])?;
-                            curr_index += consumed_nested;
-                            else_body = Some(vec![nested_if]);
-                        } else if tokens.get(curr_index) == Some(&Token::LeftBrace) {
-                            // else block: parse the block
-                            let (else_stmts, else_consumed) = parse_block(&tokens[curr_index..])?;
-                            else_body = Some(else_stmts);
-                            curr_index += else_consumed;
-                        } else {
-                            return Err("Syntax error, expected '{' or 'if' after else".into());
-                        }
-                    }
+                // After the condition, expect a left brace for the if-block
+                if token_at(tokens, right_paren_index + 1) != Some(&Token::LeftBrace) {
+                    return Err(Diagnostic::error(
+                        codes::EXPECTED_TOKEN,
+                        "Syntax error, expected '{' after if condition",
+                        span_at(tokens, right_paren_index + 1),
+                    ));
+                }
+                // use parse_block to parse statements enclosed in {}
+                let (if_body, body_consumed) =
+                    parse_block(&tokens[right_paren_index + 1..], errors)?;
+                let mut curr_index = right_paren_index + 1 + body_consumed;
 
-                    Ok((
-                        Statement::IfStatement {
-                            cond: cond_expr,
-                            if_then: if_body,
-                            else_then: else_body,
-                        },
-                        curr_index,
-                    ))
-                } else {
-                    Err("Syntax error, expected opening parentheses for if statement".into())
+                // Check for an optional 'else' block
+                let mut else_body = None;
+                if token_at(tokens, curr_index) == Some(&Token::Else) {
+                    curr_index += 1; // consume 'else'
+                    if token_at(tokens, curr_index) == Some(&Token::If) {
+                        // This is an 'else if' so parse it as a nested if-statement
+                        let (nested_if, consumed_nested) =
+                            Statement::parse(&tokens[curr_index..], errors)?;
+                        curr_index += consumed_nested;
+                        else_body = Some(vec![nested_if]);
+                    } else if token_at(tokens, curr_index) == Some(&Token::LeftBrace) {
+                        // else block: parse the block
+                        let (else_stmts, else_consumed) =
+                            parse_block(&tokens[curr_index..], errors)?;
+                        else_body = Some(else_stmts);
+                        curr_index += else_consumed;
+                    } else {
+                        return Err(Diagnostic::error(
+                            codes::EXPECTED_TOKEN,
+                            "Syntax error, expected '{' or 'if' after else",
+                            span_at(tokens, curr_index),
+                        ));
+                    }
                 }
+
+                Ok((
+                    StatementKind::IfStatement {
+                        cond: cond_expr,
+                        if_then: if_body,
+                        else_then: else_body,
+                    },
+                    curr_index,
+                ))
+            } else {
+                Err(Diagnostic::error(
+                    codes::EXPECTED_TOKEN,
+                    "Syntax error, expected opening parentheses for if statement",
+                    span_at(tokens, 1),
+                ))
             }
+        }
 
-            Token::While => {
-                // Parse while statement:
-                // while, LeftParen, <condition tokens>, RightParen, LeftBrace, <loop body>, RightBrace
-                if tokens.len() > 2 && tokens.get(1) == Some(&Token::LeftParen) {
-                    let mut paren_depth = 0;
-                    let mut right_paren_index = None;
-                    for i in 1..tokens.len() {
-                        match tokens[i] {
-                            Token::LeftParen => paren_depth += 1,
-                            Token::RightParen => {
-                                paren_depth -= 1;
-                                if paren_depth == 0 {
-                                    right_paren_index = Some(i);
-                                    break;
-                                }
+        Token::While => {
+            // Parse while statement:
+            // while, LeftParen, <condition tokens>, RightParen, LeftBrace, <loop body>, RightBrace
+            if tokens.len() > 2 && token_at(tokens, 1) == Some(&Token::LeftParen) {
+                let mut paren_depth = 0;
+                let mut right_paren_index = None;
+                for (i, token) in tokens.iter().enumerate().skip(1) {
+                    match token.node {
+                        Token::LeftParen => paren_depth += 1,
+                        Token::RightParen => {
+                            paren_depth -= 1;
+                            if paren_depth == 0 {
+                                right_paren_index = Some(i);
+                                break;
                             }
-                            _ => {}
                         }
+                        _ => {}
                     }
-                    let right_paren_index =
-                        match right_paren_index {
-                            Some(i) => i,
-                            None => return Err(
-                                "Syntax error, expected closing parenthesis for while statement"
-                                    .into(),
-                            ),
-                        };
-
-                    // Parse condition from tokens[2..right_paren_index]
-                    let (cond_expr, _cond_consumed) =
-                        Expression::new(&tokens[2..right_paren_index])?;
-
-                    // Expect a left brace after the condition for the loop block.
-                    if tokens.get(right_paren_index + 1) != Some(&Token::LeftBrace) {
-                        return Err("Syntax error, expected '{' after while condition".into());
+                }
+                let right_paren_index = match right_paren_index {
+                    Some(i) => i,
+                    None => {
+                        return Err(Diagnostic::error(
+                            codes::EXPECTED_TOKEN,
+                            "Syntax error, expected closing parenthesis for while statement",
+                            span_at(tokens, tokens.len()),
+                        ))
                     }
+                };
 
-                    let (loop_stmts, body_consumed) =
-                        parse_block(&tokens[right_paren_index + 1..])?;
-                    let curr_index = right_paren_index + 1 + body_consumed;
-                    Ok((
-                        Statement::WhileStatement {
-                            cond: cond_expr,
-                            loop_stmt: loop_stmts,
-                        },
-                        curr_index,
-                    ))
-                } else {
-                    Err("Syntax error, expected opening parenthesis for while statement".into())
+                // Parse condition from tokens[2..right_paren_index]
+                let cond_expr = parse_expression(tokens, 2, right_paren_index)?;
+
+                // Expect a left brace after the condition for the loop block.
+                if token_at(tokens, right_paren_index + 1) != Some(&Token::LeftBrace) {
+                    return Err(Diagnostic::error(
+                        codes::EXPECTED_TOKEN,
+                        "Syntax error, expected '{' after while condition",
+                        span_at(tokens, right_paren_index + 1),
+                    ));
                 }
-            }
-            _ => {
-                println!("{:?}", tokens[0]);
-                return Err("Expected a statement  ".into());
+
+                let (loop_stmts, body_consumed) =
+                    parse_block(&tokens[right_paren_index + 1..], errors)?;
+                let curr_index = right_paren_index + 1 + body_consumed;
+                Ok((
+                    StatementKind::WhileStatement {
+                        cond: cond_expr,
+                        loop_stmt: loop_stmts,
+                    },
+                    curr_index,
+                ))
+            } else {
+                Err(Diagnostic::error(
+                    codes::EXPECTED_TOKEN,
+                    "Syntax error, expected opening parenthesis for while statement",
+                    span_at(tokens, 1),
+                ))
             }
         }
+        _ => Err(Diagnostic::error(
+            codes::UNEXPECTED_TOKEN,
+            format!("Expected a statement, found '{}'", tokens[0].node),
+            tokens[0].span,
+        )),
     }
 }
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::expression::Expression;
+    use crate::expression::{Expression, ExpressionKind};
+    use crate::program::Program;
+    use crate::run_lexer;
+    use crate::span::spanned;
     use crate::token::Token;
 
+    fn expr(kind: ExpressionKind) -> Expression {
+        Expression {
+            kind,
+            span: Span::default(),
+            ty: None,
+        }
+    }
+
+    fn stmt(kind: StatementKind) -> Statement {
+        Statement {
+            kind,
+            span: Span::default(),
+        }
+    }
+
     // ----- Assignment Statement Tests -----
 
     #[test]
     fn test_assignment_statement_success() {
         // let x = 42
 
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::Let,
             Token::Identifier("x".to_string()),
             Token::Equal,
             Token::Integer(42),
             Token::SemiColon,
-        ];
-        let expected = Statement::DeclareStatement {
+        ]);
+        let expected = stmt(StatementKind::DeclareStatement {
             identifier: Token::Identifier("x".to_string()),
-            value: Some(Expression::Token(Token::Integer(42))),
-        };
+            value: Some(expr(ExpressionKind::Token(Token::Integer(42)))),
+        });
 
         let result = Statement::new(&tokens);
         assert_eq!(result, Ok((expected, 5)));
@@ -323,17 +785,17 @@ mod tests {
     #[test]
     fn test_assignment_missing_equal() {
         //  let x + 42  (error: expected '=' after identifier)
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::Let,
             Token::Identifier("x".to_string()),
             Token::Add, // wrong token instead of '='
             Token::Integer(42),
             Token::SemiColon,
-        ];
+        ]);
         let result = Statement::new(&tokens);
         assert!(result.is_err());
         assert_eq!(
-            result.unwrap_err(),
+            result.unwrap_err().message,
             "Expected '=' after the identifier in assignment statement".to_string()
         );
     }
@@ -341,17 +803,17 @@ mod tests {
     #[test]
     fn test_assignment_non_identifier() {
         //  let 42 = 42  (error: identifier expected after let)
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::Let,
             Token::Integer(42), // not an identifier
             Token::Equal,
             Token::Integer(42),
             Token::SemiColon,
-        ];
+        ]);
         let result = Statement::new(&tokens);
         assert!(result.is_err());
         assert_eq!(
-            result.unwrap_err(),
+            result.unwrap_err().message,
             "Assignment statement must start with an identifier".to_string()
         );
     }
@@ -359,11 +821,11 @@ mod tests {
     #[test]
     fn test_assignment_not_enough_tokens() {
         // Tokens: let x  (error: not enough tokens for an assignment)
-        let tokens = vec![Token::Let, Token::Identifier("x".to_string())];
+        let tokens = spanned(vec![Token::Let, Token::Identifier("x".to_string())]);
         let result = Statement::new(&tokens);
         assert!(result.is_err());
         assert_eq!(
-            result.unwrap_err(),
+            result.unwrap_err().message,
             "Syntax error, expected semicolon at end of statement in function body".to_string()
         );
     }
@@ -372,10 +834,10 @@ mod tests {
     #[test]
     fn test_return_statement_success() {
         // Tokens: return 42
-        let tokens = vec![Token::Return, Token::Integer(42), Token::SemiColon];
-        let expected = Statement::ReturnStatement {
-            value: Expression::Token(Token::Integer(42)),
-        };
+        let tokens = spanned(vec![Token::Return, Token::Integer(42), Token::SemiColon]);
+        let expected = stmt(StatementKind::ReturnStatement {
+            value: expr(ExpressionKind::Token(Token::Integer(42))),
+        });
         // Expression::new for [Token::Number(42)] returns consumed count of 1.
         let result = Statement::new(&tokens);
         assert_eq!(result, Ok((expected, 3)));
@@ -384,32 +846,84 @@ mod tests {
     #[test]
     fn test_return_statement_no_expression() {
         // Tokens: return  (error: missing expression after return)
-        let tokens = vec![Token::Return, Token::SemiColon];
+        let tokens = spanned(vec![Token::Return, Token::SemiColon]);
         let result = Statement::new(&tokens);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Expected an expression".to_string());
+        assert_eq!(
+            result.unwrap_err().message,
+            "Expected an expression".to_string()
+        );
     }
 
     // ----- General Statement Error Tests -----
 
     #[test]
     fn test_statement_no_tokens() {
-        let tokens: Vec<Token> = vec![];
+        let tokens: Vec<Spanned<Token>> = vec![];
         let result = Statement::new(&tokens);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "No tokens provided".to_string());
+        assert_eq!(
+            result.unwrap_err().message,
+            "No tokens provided".to_string()
+        );
     }
 
     #[test]
     fn test_statement_invalid_start() {
-        // Tokens: x (an identifier, not starting with let or return)
-        let tokens = vec![Token::Identifier("x".to_string()), Token::SemiColon];
+        // Tokens: else; (nothing can start with else)
+        let tokens = spanned(vec![Token::Else, Token::SemiColon]);
         let result = Statement::new(&tokens);
         assert!(result.is_err());
         assert_eq!(
-            result.unwrap_err(),
-            "Syntax error, expected an assignment statement".to_string()
+            result.unwrap_err().message,
+            "Expected a statement, found 'else'".to_string()
+        );
+    }
+
+    #[test]
+    fn test_expression_statement() {
+        let (tokens, _) = run_lexer("log_value(x); x; -1;").unwrap();
+        let (call, consumed) = Statement::new(&tokens).unwrap();
+        assert_eq!(consumed, 5);
+        assert!(matches!(
+            call.kind,
+            StatementKind::ExpressionStatement {
+                expr: Expression {
+                    kind: ExpressionKind::FunctionCall { .. },
+                    ..
+                }
+            }
+        ));
+        assert_eq!(
+            Statement::new(&tokens[5..]),
+            Ok((
+                stmt(StatementKind::ExpressionStatement {
+                    expr: expr(ExpressionKind::Token(Token::Identifier("x".to_string()))),
+                }),
+                2
+            ))
         );
+        let (negation, _) = Statement::new(&tokens[7..]).unwrap();
+        assert!(matches!(
+            negation.kind,
+            StatementKind::ExpressionStatement { .. }
+        ));
+    }
+
+    #[test]
+    fn test_template_expression_statement() {
+        let (tokens, _) = run_lexer(r#""${log_value(x)}";"#).unwrap();
+        let (statement, consumed) = Statement::new(&tokens).unwrap();
+        assert_eq!(consumed, 2);
+        assert!(matches!(
+            statement.kind,
+            StatementKind::ExpressionStatement {
+                expr: Expression {
+                    kind: ExpressionKind::Format { .. },
+                    ..
+                }
+            }
+        ));
     }
     #[test]
     fn test_if_statement_without_else() {
@@ -418,7 +932,7 @@ mod tests {
         // Token layout:
         // [If, LeftParen, Identifier("x"), RightParen, LeftBrace,
         //  Return, Number(1), SemiColon, RightBrace]
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::If,
             Token::LeftParen,
             Token::Identifier("x".to_string()),
@@ -428,15 +942,15 @@ mod tests {
             Token::Integer(1),
             Token::SemiColon,
             Token::RightBrace,
-        ];
+        ]);
 
-        let expected = Statement::IfStatement {
-            cond: Expression::Token(Token::Identifier("x".to_string())),
-            if_then: vec![Statement::ReturnStatement {
-                value: Expression::Token(Token::Integer(1)),
-            }],
+        let expected = stmt(StatementKind::IfStatement {
+            cond: expr(ExpressionKind::Token(Token::Identifier("x".to_string()))),
+            if_then: vec![stmt(StatementKind::ReturnStatement {
+                value: expr(ExpressionKind::Token(Token::Integer(1))),
+            })],
             else_then: None,
-        };
+        });
 
         let result = Statement::new(&tokens);
         assert_eq!(result, Ok((expected, tokens.len())));
@@ -450,7 +964,7 @@ mod tests {
         // [If, LeftParen, Identifier("x"), RightParen, LeftBrace,
         //  Return, Number(1), SemiColon, RightBrace,
         //  Else, LeftBrace, Return, Number(2), SemiColon, RightBrace]
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::If,
             Token::LeftParen,
             Token::Identifier("x".to_string()),
@@ -466,17 +980,17 @@ mod tests {
             Token::Integer(2),
             Token::SemiColon,
             Token::RightBrace,
-        ];
-
-        let expected = Statement::IfStatement {
-            cond: Expression::Token(Token::Identifier("x".to_string())),
-            if_then: vec![Statement::ReturnStatement {
-                value: Expression::Token(Token::Integer(1)),
-            }],
-            else_then: Some(vec![Statement::ReturnStatement {
-                value: Expression::Token(Token::Integer(2)),
-            }]),
-        };
+        ]);
+
+        let expected = stmt(StatementKind::IfStatement {
+            cond: expr(ExpressionKind::Token(Token::Identifier("x".to_string()))),
+            if_then: vec![stmt(StatementKind::ReturnStatement {
+                value: expr(ExpressionKind::Token(Token::Integer(1))),
+            })],
+            else_then: Some(vec![stmt(StatementKind::ReturnStatement {
+                value: expr(ExpressionKind::Token(Token::Integer(2))),
+            })]),
+        });
 
         let result = Statement::new(&tokens);
         assert_eq!(result, Ok((expected, tokens.len())));
@@ -486,7 +1000,7 @@ mod tests {
     fn test_if_statement_missing_closing_brace() {
         // This represents an if-statement with a missing closing brace for the if-block:
         // if (x) { return 1;
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::If,
             Token::LeftParen,
             Token::Identifier("x".to_string()),
@@ -496,11 +1010,11 @@ mod tests {
             Token::Integer(1),
             Token::SemiColon,
             // Missing RightBrace here
-        ];
+        ]);
         let result = Statement::new(&tokens);
         assert!(result.is_err());
         assert_eq!(
-            result.unwrap_err(),
+            result.unwrap_err().message,
             "Expected '}' at end of block".to_string()
         );
     }
@@ -510,7 +1024,7 @@ mod tests {
         // while (x) { return 1; }
         // Token layout:
         // [While, LeftParen, Identifier("x"), RightParen, LeftBrace, Return, Number(1), SemiColon, RightBrace]
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::While,
             Token::LeftParen,
             Token::Identifier("x".to_string()),
@@ -520,14 +1034,14 @@ mod tests {
             Token::Integer(1),
             Token::SemiColon,
             Token::RightBrace,
-        ];
+        ]);
 
-        let expected = Statement::WhileStatement {
-            cond: Expression::Token(Token::Identifier("x".to_string())),
-            loop_stmt: vec![Statement::ReturnStatement {
-                value: Expression::Token(Token::Integer(1)),
-            }],
-        };
+        let expected = stmt(StatementKind::WhileStatement {
+            cond: expr(ExpressionKind::Token(Token::Identifier("x".to_string()))),
+            loop_stmt: vec![stmt(StatementKind::ReturnStatement {
+                value: expr(ExpressionKind::Token(Token::Integer(1))),
+            })],
+        });
 
         let result = Statement::new(&tokens);
         assert_eq!(result, Ok((expected, tokens.len())));
@@ -536,7 +1050,7 @@ mod tests {
     #[test]
     fn test_while_statement_missing_paren() {
         // Missing closing parenthesis: while (x { return 1; }
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::While,
             Token::LeftParen,
             Token::Identifier("x".to_string()),
@@ -546,11 +1060,11 @@ mod tests {
             Token::Integer(1),
             Token::SemiColon,
             Token::RightBrace,
-        ];
+        ]);
         let result = Statement::new(&tokens);
         assert!(result.is_err());
         assert_eq!(
-            result.unwrap_err(),
+            result.unwrap_err().message,
             "Syntax error, expected closing parenthesis for while statement".to_string()
         );
     }
@@ -558,7 +1072,7 @@ mod tests {
     #[test]
     fn test_while_statement_missing_brace() {
         // Missing left brace after condition: while (x) return 1; }
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::While,
             Token::LeftParen,
             Token::Identifier("x".to_string()),
@@ -568,11 +1082,11 @@ mod tests {
             Token::Integer(1),
             Token::SemiColon,
             Token::RightBrace,
-        ];
+        ]);
         let result = Statement::new(&tokens);
         assert!(result.is_err());
         assert_eq!(
-            result.unwrap_err(),
+            result.unwrap_err().message,
             "Syntax error, expected '{' after while condition".to_string()
         );
     }
@@ -581,7 +1095,7 @@ mod tests {
     fn test_while_statement_missing_body_end() {
         // While statement with missing closing brace for loop body:
         // while (x) { return 1;
-        let tokens = vec![
+        let tokens = spanned(vec![
             Token::While,
             Token::LeftParen,
             Token::Identifier("x".to_string()),
@@ -591,12 +1105,270 @@ mod tests {
             Token::Integer(1),
             Token::SemiColon,
             // Missing RightBrace here
-        ];
+        ]);
         let result = Statement::new(&tokens);
         assert!(result.is_err());
         assert_eq!(
-            result.unwrap_err(),
+            result.unwrap_err().message,
             "Expected '}' at end of block".to_string()
         );
     }
+
+    // ----- Error Recovery Tests -----
+
+    #[test]
+    fn test_recovery_reports_every_error() {
+        // Two broken declarations with a good one in between
+        let (tokens, _) = run_lexer("let = 1; let y = 2; let z = ;").unwrap();
+        let mut errors = Vec::new();
+        let (statements, consumed) = parse_statements(&tokens, &mut errors);
+
+        assert_eq!(consumed, tokens.len());
+        assert_eq!(errors.len(), 2);
+        assert_eq!(statements.len(), 1);
+        assert_eq!(
+            statements[0],
+            stmt(StatementKind::DeclareStatement {
+                identifier: Token::Identifier("y".to_string()),
+                value: Some(expr(ExpressionKind::Token(Token::Integer(2)))),
+            })
+        );
+    }
+
+    #[test]
+    fn test_recovery_inside_nested_block() {
+        // The error inside the `if` body must not swallow the statement after it.
+        let (tokens, _) = run_lexer("if (x) { let = 1; } let y = 2; }").unwrap();
+        let mut errors = Vec::new();
+        let (statements, consumed) = parse_statements(&tokens, &mut errors);
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(statements.len(), 2);
+        assert_eq!(tokens[consumed], Token::RightBrace);
+    }
+
+    #[test]
+    fn test_program_collects_errors_across_functions() {
+        let source = "\
+func first() {
+    let = 1;
+    return 1;
+}
+func second() {
+    return (2;
+}
+func main() {
+    return 0;
+}";
+        let (tokens, _) = run_lexer(source).unwrap();
+        let (program, errors) = Program::parse(&tokens);
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].span.line, 2);
+        assert_eq!(errors[1].span.line, 6);
+        assert_eq!(program.functions.len(), 3);
+        assert_eq!(program.functions[0].body.len(), 1);
+        assert!(Program::new(&tokens).is_err());
+    }
+
+    #[test]
+    fn test_program_skips_stray_tokens() {
+        let (tokens, _) = run_lexer("let x = 1; func main() { return 0; }").unwrap();
+        let (program, errors) = Program::parse(&tokens);
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, crate::diagnostic::codes::EXPECTED_FUNCTION);
+        assert_eq!(program.functions.len(), 1);
+    }
+
+    #[test]
+    fn test_program_doc_comments() {
+        let source = "\
+/// Adds one.
+///
+/// Used by main.
+func inc(x) { return x + 1; }
+// not a doc comment
+func broken( { }
+/// Entry point
+func main() { return inc(1); }";
+        let (tokens, _) = run_lexer(source).unwrap();
+        let (program, errors) = Program::parse(&tokens);
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(program.functions.len(), 2);
+        assert_eq!(
+            program.functions[0].doc.as_deref(),
+            Some("Adds one.\n\nUsed by main.")
+        );
+        assert_eq!(program.functions[1].doc.as_deref(), Some("Entry point"));
+    }
+
+    // ----- Print Statement Tests -----
+
+    #[test]
+    fn test_print_statement() {
+        let (tokens, _) = run_lexer("print(x, 1);").unwrap();
+        let expected = stmt(StatementKind::PrintStatement {
+            args: vec![
+                expr(ExpressionKind::Token(Token::Identifier("x".to_string()))),
+                expr(ExpressionKind::Token(Token::Integer(1))),
+            ],
+        });
+        assert_eq!(Statement::new(&tokens), Ok((expected, 7)));
+    }
+
+    #[test]
+    fn test_print_statement_trailing_tokens() {
+        let (tokens, _) = run_lexer("print(x) y;").unwrap();
+        let err = Statement::new(&tokens).unwrap_err();
+        assert_eq!(err.message, "Expected ';' after print statement");
+        assert_eq!(err.span.column, 10);
+    }
+
+    // ----- For Statement Tests -----
+
+    #[test]
+    fn test_for_statement() {
+        let (tokens, _) = run_lexer("for (let i = 0; i < 3; i = i + 1) { print(i); }").unwrap();
+        let i = || expr(ExpressionKind::Token(Token::Identifier("i".to_string())));
+        let int = |n| expr(ExpressionKind::Token(Token::Integer(n)));
+        let expected = stmt(StatementKind::ForStatement {
+            init: Some(Box::new(stmt(StatementKind::DeclareStatement {
+                identifier: Token::Identifier("i".to_string()),
+                value: Some(int(0)),
+            }))),
+            cond: Some(expr(ExpressionKind::Binary {
+                left: Box::new(i()),
+                operator: Token::Less,
+                right: Box::new(int(3)),
+            })),
+            step: Some(Box::new(stmt(StatementKind::AssignStatement {
+                target: i(),
+                value: expr(ExpressionKind::Binary {
+                    left: Box::new(i()),
+                    operator: Token::Add,
+                    right: Box::new(int(1)),
+                }),
+            }))),
+            body: vec![stmt(StatementKind::PrintStatement { args: vec![i()] })],
+        });
+        assert_eq!(Statement::new(&tokens), Ok((expected, tokens.len())));
+    }
+
+    #[test]
+    fn test_for_statement_empty_header() {
+        let (tokens, _) = run_lexer("for (;;) { }").unwrap();
+        let expected = stmt(StatementKind::ForStatement {
+            init: None,
+            cond: None,
+            step: None,
+            body: vec![],
+        });
+        assert_eq!(Statement::new(&tokens), Ok((expected, tokens.len())));
+    }
+
+    #[test]
+    fn test_for_in_statement() {
+        let (tokens, _) = run_lexer("for x in [1, 2] { }").unwrap();
+        let expected = stmt(StatementKind::ForInStatement {
+            variable: Spanned::new(Token::Identifier("x".to_string()), Span::default()),
+            iterable: expr(ExpressionKind::ArrayDec {
+                arr_expr: vec![
+                    expr(ExpressionKind::Token(Token::Integer(1))),
+                    expr(ExpressionKind::Token(Token::Integer(2))),
+                ],
+            }),
+            body: vec![],
+        });
+        assert_eq!(Statement::new(&tokens), Ok((expected, tokens.len())));
+    }
+
+    #[test]
+    fn test_for_header_error_recovery() {
+        // The semicolons inside the broken header must not be taken as
+        // statement ends, or the rest of the header turns into more errors
+        let (tokens, _) = run_lexer("for (let = 0; i < 3; i = i + 1) { } let y = 1;").unwrap();
+        let mut errors = Vec::new();
+        let (statements, _) = parse_statements(&tokens, &mut errors);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(statements.len(), 1);
+    }
+
+    #[test]
+    fn test_break_statement() {
+        let (tokens, _) = run_lexer("break;").unwrap();
+        assert_eq!(
+            Statement::new(&tokens),
+            Ok((stmt(StatementKind::BreakStatement), 2))
+        );
+
+        let (tokens, _) = run_lexer("continue 2;").unwrap();
+        let err = Statement::new(&tokens).unwrap_err();
+        assert_eq!(err.message, "Expected ';' after 'continue'");
+    }
+
+    // ----- Indexed Assignment Tests -----
+
+    #[test]
+    fn test_nested_index_assignment() {
+        let (tokens, _) = run_lexer("grid[i][j + 1] = 0;").unwrap();
+        let ident = |name: &str| expr(ExpressionKind::Token(Token::Identifier(name.to_string())));
+        let expected = stmt(StatementKind::AssignStatement {
+            target: expr(ExpressionKind::AccessIndex {
+                array: Box::new(expr(ExpressionKind::AccessIndex {
+                    array: Box::new(ident("grid")),
+                    index: Box::new(ident("i")),
+                })),
+                index: Box::new(expr(ExpressionKind::Binary {
+                    left: Box::new(ident("j")),
+                    operator: Token::Add,
+                    right: Box::new(expr(ExpressionKind::Token(Token::Integer(1)))),
+                })),
+            }),
+            value: expr(ExpressionKind::Token(Token::Integer(0))),
+        });
+        assert_eq!(Statement::new(&tokens), Ok((expected, tokens.len())));
+    }
+
+    #[test]
+    fn test_invalid_assignment_target() {
+        let (tokens, _) = run_lexer("x + 1 = 2;").unwrap();
+        let err = Statement::new(&tokens).unwrap_err();
+        assert_eq!(
+            err.message,
+            "Invalid assignment target, expected a variable or an array element"
+        );
+        assert_eq!((err.span.start, err.span.end), (0, 5));
+    }
+
+    #[test]
+    fn test_compound_assignment() {
+        let (tokens, _) = run_lexer("arr[i] -= 2;").unwrap();
+        let ident = |name: &str| expr(ExpressionKind::Token(Token::Identifier(name.to_string())));
+        let expected = stmt(StatementKind::CompoundAssign {
+            target: expr(ExpressionKind::AccessIndex {
+                array: Box::new(ident("arr")),
+                index: Box::new(ident("i")),
+            }),
+            operator: Token::Sub,
+            value: expr(ExpressionKind::Token(Token::Integer(2))),
+        });
+        assert_eq!(Statement::new(&tokens), Ok((expected, tokens.len())));
+
+        let (tokens, _) = run_lexer("for (let i = 0; i < 3; i += 1) {}").unwrap();
+        let (for_stmt, _) = Statement::new(&tokens).unwrap();
+        match for_stmt.kind {
+            StatementKind::ForStatement {
+                step: Some(step), ..
+            } => assert!(matches!(
+                step.kind,
+                StatementKind::CompoundAssign {
+                    operator: Token::Add,
+                    ..
+                }
+            )),
+            other => panic!("Expected a for statement with a step, got {:?}", other),
+        }
+    }
 }
@@ -1,31 +1,56 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
+
+use crate::{semantic::InferredType, span::Span};
 
 pub struct SymbolTable {
     scopes: Vec<HashMap<String, Symbol>>,
 }
 
 pub struct Symbol {
-    name: String,
-    symbol_type: SymbolType,
-    data_type: DataType,
+    pub name: String,
+    pub symbol_type: SymbolType,
+    // What inference knows about the symbol's type so far. For a function
+    // this is its return type.
+    pub data_type: InferredType,
+    // Parameter types, for functions only
+    pub params: Vec<InferredType>,
+    // Where the symbol was declared
+    pub span: Span,
 }
 
 impl Symbol {
-    pub fn new(name: String, symbol_type: SymbolType, data_type: DataType) -> Self {
+    pub fn new(name: String, symbol_type: SymbolType, data_type: InferredType, span: Span) -> Self {
         Symbol {
             name,
             symbol_type,
             data_type,
+            params: Vec::new(),
+            span,
+        }
+    }
+
+    pub fn function(
+        name: String,
+        params: Vec<InferredType>,
+        return_type: InferredType,
+        span: Span,
+    ) -> Self {
+        Symbol {
+            params,
+            ..Symbol::new(name, SymbolType::Function, return_type, span)
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SymbolType {
     Variable,
     Function,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum DataType {
+    /// A 64-bit signed integer; arithmetic that overflows it is an error.
     Integer,
     Float,
     Boolean,
@@ -34,6 +59,25 @@ pub enum DataType {
     Void,
 }
 
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataType::Integer => write!(f, "integer"),
+            DataType::Float => write!(f, "float"),
+            DataType::Boolean => write!(f, "boolean"),
+            DataType::String => write!(f, "string"),
+            DataType::Array(element) => write!(f, "[{}]", element),
+            DataType::Void => write!(f, "void"),
+        }
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SymbolTable {
     pub fn new() -> Self {
         let mut table = SymbolTable { scopes: Vec::new() };
@@ -71,4 +115,33 @@ impl SymbolTable {
         }
         None
     }
+
+    pub fn lookup_mut(&mut self, name: &str) -> Option<&mut Symbol> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+    }
+
+    /// The type of every symbol in scope, to tell whether analysis learned
+    /// anything new about them.
+    pub fn types(&self) -> Vec<InferredType> {
+        self.scopes
+            .iter()
+            .flat_map(|scope| scope.values())
+            .map(|symbol| symbol.data_type.clone())
+            .collect()
+    }
+
+    /// Looks a name up in the global scope only, where functions live, so a
+    /// local variable can't hide a function from a call.
+    pub fn lookup_global(&self, name: &str) -> Option<&Symbol> {
+        self.scopes.first().and_then(|scope| scope.get(name))
+    }
+
+    pub fn lookup_global_mut(&mut self, name: &str) -> Option<&mut Symbol> {
+        self.scopes
+            .first_mut()
+            .and_then(|scope| scope.get_mut(name))
+    }
 }
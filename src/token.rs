@@ -1,3 +1,12 @@
+use std::fmt;
+
+use crate::{
+    advance,
+    diagnostic::codes,
+    lex,
+    span::{Span, Spanned},
+};
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     // Delimiters
@@ -27,8 +36,19 @@ pub enum Token {
     Or,
     And,
     Dot,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
+    AddEqual,
+    SubEqual,
+    MulEqual,
+    DivEqual,
+    ModEqual,
     // Literals
-    Integer(i32),
+    Integer(i64),
     Float(f64),
     Boolean(bool),
     Identifier(String),
@@ -39,22 +59,471 @@ pub enum Token {
     If,
     Else,
     For,
+    In,
     While,
     Let,
     Return,
+    Break,
+    Continue,
     Print,
     Append,
     Length,
+    /// A string literal with `${...}` interpolations in it.
+    Template(Vec<TemplatePart>),
+    // Trivia
+    /// The `///` comment lines right before a `func`, joined by newlines.
+    DocComment(String),
+}
+
+/// A piece of an interpolated string literal: either text, or the tokens of
+/// an embedded expression.
+#[derive(Debug, PartialEq, Clone)]
+pub enum TemplatePart {
+    Text(String),
+    Code(Vec<Spanned<Token>>),
+}
+
+/// Prints a token the way it's written in source, for diagnostics. The code
+/// inside a template's `${...}` is elided, and doc comments print their
+/// first line only.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Token::Comma => ",",
+            Token::LeftParen => "(",
+            Token::RightParen => ")",
+            Token::LeftBrace => "{",
+            Token::RightBrace => "}",
+            Token::SemiColon => ";",
+            Token::DoubleQuote => "\"",
+            Token::LeftSquare => "[",
+            Token::RightSquare => "]",
+            Token::Add => "+",
+            Token::Sub => "-",
+            Token::Mul => "*",
+            Token::Div => "/",
+            Token::Mod => "%",
+            Token::NotEqual => "!=",
+            Token::EqualEqual => "==",
+            Token::Greater => ">",
+            Token::GreaterEqual => ">=",
+            Token::Less => "<",
+            Token::LessEqual => "<=",
+            Token::Equal => "=",
+            Token::Not => "!",
+            Token::Or => "||",
+            Token::And => "&&",
+            Token::Dot => ".",
+            Token::BitAnd => "&",
+            Token::BitOr => "|",
+            Token::BitXor => "^",
+            Token::BitNot => "~",
+            Token::ShiftLeft => "<<",
+            Token::ShiftRight => ">>",
+            Token::AddEqual => "+=",
+            Token::SubEqual => "-=",
+            Token::MulEqual => "*=",
+            Token::DivEqual => "/=",
+            Token::ModEqual => "%=",
+            Token::Integer(value) => return write!(f, "{}", value),
+            Token::Float(value) => return write!(f, "{:?}", value),
+            Token::Boolean(value) => return write!(f, "{}", value),
+            Token::Identifier(name) => name,
+            Token::String(text) => return write!(f, "\"{}\"", text.escape_debug()),
+            Token::Func => "func",
+            Token::Main => "main",
+            Token::If => "if",
+            Token::Else => "else",
+            Token::For => "for",
+            Token::In => "in",
+            Token::While => "while",
+            Token::Let => "let",
+            Token::Return => "return",
+            Token::Break => "break",
+            Token::Continue => "continue",
+            Token::Print => "print",
+            Token::Append => "append",
+            Token::Length => "length",
+            Token::Template(parts) => {
+                write!(f, "\"")?;
+                for part in parts {
+                    match part {
+                        TemplatePart::Text(text) => {
+                            let text = text.escape_debug().to_string();
+                            write!(f, "{}", text.replace("${", "\\${"))?
+                        }
+                        TemplatePart::Code(_) => write!(f, "${{...}}")?,
+                    }
+                }
+                return write!(f, "\"");
+            }
+            Token::DocComment(text) => {
+                return write!(f, "/// {}", text.lines().next().unwrap_or(""));
+            }
+        };
+        f.write_str(text)
+    }
+}
+
+/// An error from `Token::new`. It covers `len` bytes of the input starting
+/// `offset` bytes after the start of the failed token, so an error inside a
+/// string literal can point at the exact escape that caused it.
+#[derive(Debug, PartialEq)]
+pub struct LexError {
+    pub code: &'static str,
+    pub message: String,
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl LexError {
+    fn new(code: &'static str, message: impl Into<String>, len: usize) -> Self {
+        LexError {
+            code,
+            message: message.into(),
+            offset: 0,
+            len,
+        }
+    }
+
+    fn at(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+}
+
+/// Returns the token at `index` without its span, if there is one.
+pub fn token_at(tokens: &[Spanned<Token>], index: usize) -> Option<&Token> {
+    tokens.get(index).map(|token| &token.node)
+}
+
+/// Returns the length of the comment at the start of `input`, or `None` if
+/// it doesn't start with one. A line comment runs up to the newline; block
+/// comments nest, so `/* a /* b */ c */` is one comment.
+pub fn comment_len(input: &str) -> Result<Option<usize>, LexError> {
+    if input.starts_with("//") {
+        return Ok(Some(input.find('\n').unwrap_or(input.len())));
+    }
+    if !input.starts_with("/*") {
+        return Ok(None);
+    }
+
+    let mut depth = 0;
+    let mut idx = 0;
+    while idx < input.len() {
+        if input[idx..].starts_with("/*") {
+            depth += 1;
+            idx += 2;
+        } else if input[idx..].starts_with("*/") {
+            depth -= 1;
+            idx += 2;
+            if depth == 0 {
+                return Ok(Some(idx));
+            }
+        } else {
+            idx += input[idx..].chars().next().unwrap().len_utf8();
+        }
+    }
+    Err(LexError::new(
+        codes::UNTERMINATED_COMMENT,
+        "Unterminated block comment",
+        input.len(),
+    ))
+}
+
+// Length of the run of digits in `radix`, and `_` separators, at the start
+// of `input`.
+fn digits_len(input: &str, radix: u32) -> usize {
+    input
+        .find(|c: char| !(c.is_digit(radix) || c == '_'))
+        .unwrap_or(input.len())
+}
+
+// Reads the number literal at the start of `input`: a decimal integer, a
+// `0x`, `0o` or `0b` integer, or a float with a fraction and/or an exponent.
+// Any of them may use `_` to separate digits. Returns the token and its length.
+fn number_literal(input: &str) -> Result<(Token, usize), LexError> {
+    let prefix = input.get(..2).map(str::to_ascii_lowercase);
+    let radix = match prefix.as_deref() {
+        Some("0x") => Some((16, "hexadecimal")),
+        Some("0o") => Some((8, "octal")),
+        Some("0b") => Some((2, "binary")),
+        _ => None,
+    };
+    if let Some((radix, name)) = radix {
+        // Take every alphanumeric character, so a stray digit like the `2`
+        // in `0b102` is an error rather than the start of another token
+        let len = 2 + input[2..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(input.len() - 2);
+        let digits = &input[2..len];
+        if let Some(bad) = digits.find(|c: char| !(c.is_digit(radix) || c == '_')) {
+            let c = digits[bad..].chars().next().unwrap();
+            return Err(LexError::new(
+                codes::INVALID_NUMBER,
+                format!("Invalid digit '{}' in {} literal", c, name),
+                c.len_utf8(),
+            )
+            .at(2 + bad));
+        }
+        let digits = digits.replace('_', "");
+        if digits.is_empty() {
+            return Err(LexError::new(
+                codes::INVALID_NUMBER,
+                format!("Expected digits after '{}'", &input[..2]),
+                len,
+            ));
+        }
+        return match i64::from_str_radix(&digits, radix) {
+            Ok(number) => Ok((Token::Integer(number), len)),
+            Err(_) => Err(integer_too_large(len)),
+        };
+    }
+
+    let mut len = digits_len(input, 10);
+    let mut is_float = false;
+
+    // A decimal point only belongs to the number if a digit follows it, so
+    // `42.` is an integer followed by a dot
+    if input[len..].starts_with('.') && input[len + 1..].starts_with(|c: char| c.is_ascii_digit()) {
+        is_float = true;
+        len += 1 + digits_len(&input[len + 1..], 10);
+    }
+
+    if input[len..].starts_with(['e', 'E']) {
+        let sign = usize::from(input[len + 1..].starts_with(['+', '-']));
+        let exponent = len + 1 + sign;
+        if !input[exponent..].starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(LexError::new(
+                codes::INVALID_NUMBER,
+                "Expected digits in the exponent",
+                exponent - len,
+            )
+            .at(len));
+        }
+        is_float = true;
+        len = exponent + digits_len(&input[exponent..], 10);
+    }
+
+    let number = input[..len].replace('_', "");
+    if is_float {
+        let number = number.parse::<f64>().map_err(|e| {
+            LexError::new(
+                codes::INVALID_NUMBER,
+                format!("Float parse error: {}", e),
+                len,
+            )
+        })?;
+        Ok((Token::Float(number), len))
+    } else {
+        match number.parse::<i64>() {
+            Ok(number) => Ok((Token::Integer(number), len)),
+            Err(_) => Err(integer_too_large(len)),
+        }
+    }
+}
+
+fn integer_too_large(len: usize) -> LexError {
+    LexError::new(
+        codes::INVALID_NUMBER,
+        format!("Integer literal is larger than the maximum of {}", i64::MAX),
+        len,
+    )
+}
+
+// Decodes the string literal at the start of `input`, which begins with a
+// `"`. Returns the token and its length in the input, quotes included. The
+// literal may span several lines. A literal with `${...}` in it becomes a
+// template; the code inside is lexed with spans relative to the opening quote.
+fn string_literal(input: &str) -> Result<(Token, usize), LexError> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut idx = 1;
+    while let Some(c) = input[idx..].chars().next() {
+        match c {
+            '"' => {
+                if parts.is_empty() {
+                    return Ok((Token::String(literal), idx + 1));
+                }
+                if !literal.is_empty() {
+                    parts.push(TemplatePart::Text(literal));
+                }
+                return Ok((Token::Template(parts), idx + 1));
+            }
+            // A backslash right at the end leaves the string unterminated
+            '\\' if idx + 1 < input.len() => {
+                let (escape, escape_len) =
+                    escape_sequence(&input[idx..]).map_err(|err| err.at(idx))?;
+                literal.push(escape);
+                idx += escape_len;
+            }
+            '$' if input[idx + 1..].starts_with('{') => {
+                let start = idx + 2;
+                let end = interpolation_end(input, start)?;
+                let mut origin = Span::new(start, start, 1, 1);
+                advance(&input[..start], &mut origin.line, &mut origin.column);
+                let (tokens, _) = lex(&input[start..end], origin).map_err(|err| {
+                    LexError::new(err.code, err.message, err.span.end - err.span.start)
+                        .at(err.span.start)
+                })?;
+                if !literal.is_empty() {
+                    parts.push(TemplatePart::Text(std::mem::take(&mut literal)));
+                }
+                parts.push(TemplatePart::Code(tokens));
+                idx = end + 1;
+            }
+            _ => {
+                literal.push(c);
+                idx += c.len_utf8();
+            }
+        }
+    }
+    Err(LexError::new(
+        codes::UNTERMINATED_STRING,
+        "Unterminated string literal",
+        input.len(),
+    ))
+}
+
+// Index of the `}` closing the interpolation whose code starts at `start`.
+// Braces and string literals inside the code are skipped over.
+fn interpolation_end(input: &str, start: usize) -> Result<usize, LexError> {
+    let mut depth = 0;
+    let mut idx = start;
+    while let Some(c) = input[idx..].chars().next() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Ok(idx),
+            '}' => depth -= 1,
+            // A string that never ends most likely means the `}` is missing
+            // and this is the quote closing the outer literal
+            '"' => match string_literal(&input[idx..]) {
+                Ok((_, len)) => {
+                    idx += len;
+                    continue;
+                }
+                Err(err) if err.code == codes::UNTERMINATED_STRING => break,
+                Err(err) => {
+                    let offset = idx + err.offset;
+                    return Err(err.at(offset));
+                }
+            },
+            _ => {}
+        }
+        idx += c.len_utf8();
+    }
+    Err(LexError::new(
+        codes::UNTERMINATED_STRING,
+        "Unterminated '${' in string literal",
+        input.len() - start + 2,
+    )
+    .at(start - 2))
+}
+
+// Decodes the escape sequence at the start of `input`, which is a backslash
+// followed by at least one character. Returns the character and the length
+// of the sequence.
+fn escape_sequence(input: &str) -> Result<(char, usize), LexError> {
+    let c = input[1..]
+        .chars()
+        .next()
+        .expect("a backslash is always followed by a character");
+    let simple = match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '0' => Some('\0'),
+        '$' => Some('$'),
+        '\\' => Some('\\'),
+        '"' => Some('"'),
+        _ => None,
+    };
+    if let Some(escape) = simple {
+        return Ok((escape, 2));
+    }
+    if c != 'u' {
+        return Err(LexError::new(
+            codes::INVALID_ESCAPE,
+            format!("Unknown escape sequence '\\{}'", c),
+            1 + c.len_utf8(),
+        ));
+    }
+
+    // \u{XXXX}, with one to six hex digits naming a Unicode scalar value
+    let invalid = |len| {
+        LexError::new(
+            codes::INVALID_ESCAPE,
+            "Invalid unicode escape, expected \\u{...} with 1 to 6 hex digits",
+            len,
+        )
+    };
+    if !input[2..].starts_with('{') {
+        return Err(invalid(2));
+    }
+    let close = match input[3..].find(|c: char| !c.is_ascii_hexdigit()) {
+        Some(end) if input[3 + end..].starts_with('}') => 3 + end,
+        Some(end) => return Err(invalid(3 + end)),
+        None => return Err(invalid(input.len())),
+    };
+    let digits = &input[3..close];
+    if digits.is_empty() || digits.len() > 6 {
+        return Err(invalid(close + 1));
+    }
+    let value = u32::from_str_radix(digits, 16).map_err(|_| invalid(close + 1))?;
+    match char::from_u32(value) {
+        Some(escape) => Ok((escape, close + 1)),
+        None => Err(LexError::new(
+            codes::INVALID_ESCAPE,
+            format!("'{}' is not a valid unicode character", digits),
+            close + 1,
+        )),
+    }
+}
+
+// Reads a raw string literal, `r"..."` or `r#"..."#` with any number of `#`s,
+// at the start of `input`. Backslashes in it are kept as they are, and the
+// `#`s allow quotes inside. Returns `None` if `input` isn't a raw string, so
+// `r` can still start an identifier.
+fn raw_string_literal(input: &str) -> Result<Option<(String, usize)>, LexError> {
+    let hashes = input[1..].chars().take_while(|&c| c == '#').count();
+    let open = 1 + hashes;
+    if !input[open..].starts_with('"') {
+        return Ok(None);
+    }
+    let terminator = format!("\"{}", "#".repeat(hashes));
+    match input[open + 1..].find(&terminator) {
+        Some(end) => {
+            let literal = &input[open + 1..open + 1 + end];
+            Ok(Some((
+                literal.to_string(),
+                open + 1 + end + terminator.len(),
+            )))
+        }
+        None => Err(LexError::new(
+            codes::UNTERMINATED_STRING,
+            "Unterminated raw string literal",
+            input.len(),
+        )),
+    }
+}
+
+/// The text of a `///` doc comment, without the slashes and the space after
+/// them. `None` for any other comment, including `////` ones.
+pub fn doc_comment_text(comment: &str) -> Option<&str> {
+    let text = comment.strip_prefix("///")?;
+    if text.starts_with('/') {
+        return None;
+    }
+    Some(text.strip_prefix(' ').unwrap_or(text).trim_end())
 }
 
 impl Token {
     /// Attempts to parse a single token from the beginning of the input.
     /// Returns the token and the remaining input.
-
-    pub fn new(input: &str) -> Result<(Self, &str), String> {
+    pub fn new(input: &str) -> Result<(Self, &str), LexError> {
         let input = input.trim_start();
         if input.is_empty() {
-            return Err("No input".into());
+            return Err(LexError::new(codes::UNEXPECTED_EOF, "No input", 0));
         }
         let first = input.chars().next().unwrap();
 
@@ -69,21 +538,23 @@ impl Token {
             ';' => return Ok((Token::SemiColon, &input[1..])),
             ',' => return Ok((Token::Comma, &input[1..])),
             '"' => {
-                if let Some(end) = input[1..].find('"') {
-                    let literal = &input[1..1 + end];
-                    let rest = &input[1 + end + 1..];
-                    return Ok((Token::String(literal.to_string()), rest));
-                } else {
-                    return Err("Unterminated string literal".into());
+                let (token, len) = string_literal(input)?;
+                return Ok((token, &input[len..]));
+            }
+            'r' => {
+                if let Some((literal, len)) = raw_string_literal(input)? {
+                    return Ok((Token::String(literal), &input[len..]));
                 }
             }
             _ => {}
         }
 
         // Operators (check multi-character ones first)
-        let op_candidates = ["!=", "==", ">=", "<=", "||", "&&"];
+        let op_candidates = [
+            "!=", "==", ">=", "<=", "||", "&&", "<<", ">>", "+=", "-=", "*=", "/=", "%=",
+        ];
         for op in op_candidates.iter() {
-            if input.starts_with(op) {
+            if let Some(rest) = input.strip_prefix(op) {
                 let token = match *op {
                     "!=" => Token::NotEqual,
                     "==" => Token::EqualEqual,
@@ -91,51 +562,25 @@ impl Token {
                     "<=" => Token::LessEqual,
                     "||" => Token::Or,
                     "&&" => Token::And,
+                    "<<" => Token::ShiftLeft,
+                    ">>" => Token::ShiftRight,
+                    "+=" => Token::AddEqual,
+                    "-=" => Token::SubEqual,
+                    "*=" => Token::MulEqual,
+                    "/=" => Token::DivEqual,
+                    "%=" => Token::ModEqual,
                     _ => unreachable!(),
                 };
-                return Ok((token, &input[op.len()..]));
+                return Ok((token, rest));
             }
         }
 
-        // Number literals (both integer and float)
-        if first.is_digit(10) {
-            let mut idx = 0;
-            let mut has_decimal = false;
-
-            // Process all digits before potential decimal point
-            while idx < input.len() && input.chars().nth(idx).unwrap().is_digit(10) {
-                idx += 1;
-            }
-
-            // Check for decimal point followed by at least one digit
-            if idx < input.len() && input.chars().nth(idx).unwrap() == '.' {
-                if idx + 1 < input.len() && input.chars().nth(idx + 1).unwrap().is_digit(10) {
-                    // This is a float with digits after decimal
-                    has_decimal = true;
-                    idx += 1; // Move past the decimal point
-
-                    // Process all digits after decimal point
-                    while idx < input.len() && input.chars().nth(idx).unwrap().is_digit(10) {
-                        idx += 1;
-                    }
-                }
-            }
-
-            let number_str = &input[..idx];
-
-            if has_decimal {
-                // Parse as float
-                let number = number_str
-                    .parse::<f64>()
-                    .map_err(|e| format!("Float parse error: {}", e))?;
-                return Ok((Token::Float(number), &input[idx..]));
-            } else {
-                // Parse as integer
-                let number = number_str
-                    .parse::<i32>()
-                    .map_err(|e| format!("Integer parse error: {}", e))?;
-                return Ok((Token::Integer(number), &input[idx..]));
-            }
+        // Number literals (both integer and float). A float may start with
+        // its decimal point, as long as a digit follows it.
+        let leading_dot = first == '.' && input[1..].starts_with(|c: char| c.is_ascii_digit());
+        if first.is_ascii_digit() || leading_dot {
+            let (token, len) = number_literal(input)?;
+            return Ok((token, &input[len..]));
         }
 
         // Single-character operators
@@ -150,6 +595,10 @@ impl Token {
             '=' => return Ok((Token::Equal, &input[1..])),
             '!' => return Ok((Token::Not, &input[1..])),
             '.' => return Ok((Token::Dot, &input[1..])),
+            '&' => return Ok((Token::BitAnd, &input[1..])),
+            '|' => return Ok((Token::BitOr, &input[1..])),
+            '^' => return Ok((Token::BitXor, &input[1..])),
+            '~' => return Ok((Token::BitNot, &input[1..])),
             _ => {}
         }
 
@@ -171,9 +620,12 @@ impl Token {
                 "if" => Token::If,
                 "else" => Token::Else,
                 "for" => Token::For,
+                "in" => Token::In,
                 "while" => Token::While,
                 "let" => Token::Let,
                 "return" => Token::Return,
+                "break" => Token::Break,
+                "continue" => Token::Continue,
                 "print" => Token::Print,
                 "true" => Token::Boolean(true),
                 "false" => Token::Boolean(false),
@@ -184,7 +636,11 @@ impl Token {
             return Ok((token, &input[idx..]));
         }
 
-        Err(format!("Unknown token starting with '{}'", first))
+        Err(LexError::new(
+            codes::UNKNOWN_TOKEN,
+            format!("Unknown token starting with '{}'", first),
+            first.len_utf8(),
+        ))
     }
 }
 
@@ -231,6 +687,116 @@ mod tests {
         assert!(Token::new("\"hello").is_err());
     }
 
+    #[test]
+    fn test_template_literal() {
+        let (token, rest) = Token::new(r#""a ${x + 1}${f("}")} \${b}" tail"#).unwrap();
+        assert_eq!(rest, " tail");
+        let parts = match token {
+            Token::Template(parts) => parts,
+            other => panic!("Expected a template, got {:?}", other),
+        };
+        assert_eq!(parts.len(), 4);
+        assert_eq!(parts[0], TemplatePart::Text("a ".to_string()));
+        assert_eq!(parts[3], TemplatePart::Text(" ${b}".to_string()));
+        match &parts[1] {
+            TemplatePart::Code(tokens) => {
+                assert_eq!(
+                    tokens,
+                    &[
+                        Token::Identifier("x".to_string()),
+                        Token::Add,
+                        Token::Integer(1)
+                    ]
+                );
+                // Spans are relative to the opening quote
+                assert_eq!(tokens[0].span, Span::new(5, 6, 1, 6));
+            }
+            other => panic!("Expected code, got {:?}", other),
+        }
+        match &parts[2] {
+            TemplatePart::Code(tokens) => assert_eq!(tokens[2], Token::String("}".to_string())),
+            other => panic!("Expected code, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_template_errors() {
+        let err = Token::new(r#""a ${x + 1" "#).unwrap_err();
+        assert_eq!(err.code, codes::UNTERMINATED_STRING);
+        assert_eq!(err.message, "Unterminated '${' in string literal");
+        assert_eq!((err.offset, err.len), (3, 9));
+
+        let err = Token::new(r#""a ${x @ 1}""#).unwrap_err();
+        assert_eq!(err.code, codes::UNKNOWN_TOKEN);
+        assert_eq!((err.offset, err.len), (7, 1));
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        assert_eq!(
+            Token::new(r#""a\n\t\r\0\\\"b" rest"#),
+            Ok((Token::String("a\n\t\r\0\\\"b".to_string()), " rest"))
+        );
+        assert_eq!(
+            Token::new(r#""\u{1F600} \u{e9}""#),
+            Ok((Token::String("\u{1F600} \u{e9}".to_string()), ""))
+        );
+        assert_eq!(
+            Token::new("\"two\nlines\""),
+            Ok((Token::String("two\nlines".to_string()), ""))
+        );
+    }
+
+    #[test]
+    fn test_invalid_escapes() {
+        let err = Token::new(r#""ab\qc""#).unwrap_err();
+        assert_eq!(err.code, codes::INVALID_ESCAPE);
+        assert_eq!(err.message, "Unknown escape sequence '\\q'");
+        assert_eq!((err.offset, err.len), (3, 2));
+
+        let err = Token::new(r#""\u{110000}""#).unwrap_err();
+        assert_eq!(err.message, "'110000' is not a valid unicode character");
+        assert_eq!((err.offset, err.len), (1, 10));
+
+        for bad in [
+            r#""\u1F600""#,
+            r#""\u{}""#,
+            r#""\u{1234567}""#,
+            r#""\u{12x}""#,
+        ] {
+            let err = Token::new(bad).unwrap_err();
+            assert_eq!(err.code, codes::INVALID_ESCAPE, "{}", bad);
+            assert_eq!(err.offset, 1, "{}", bad);
+        }
+
+        // An escaped quote doesn't end the string
+        let err = Token::new(r#""abc\""#).unwrap_err();
+        assert_eq!(err.code, codes::UNTERMINATED_STRING);
+        assert_eq!(
+            Token::new("\"abc\\").unwrap_err().code,
+            codes::UNTERMINATED_STRING
+        );
+    }
+
+    #[test]
+    fn test_raw_strings() {
+        assert_eq!(
+            Token::new(r#"r"C:\path\n" x"#),
+            Ok((Token::String(r"C:\path\n".to_string()), " x"))
+        );
+        assert_eq!(
+            Token::new(r###"r##"say "#hi"#"##"###),
+            Ok((Token::String(r##"say "#hi"#"##.to_string()), ""))
+        );
+        assert_eq!(Token::new("return"), Ok((Token::Return, "")));
+        assert_eq!(
+            Token::new("r #"),
+            Ok((Token::Identifier("r".to_string()), " #"))
+        );
+        let err = Token::new(r##"r#"open""##).unwrap_err();
+        assert_eq!(err.code, codes::UNTERMINATED_STRING);
+    }
+
     // --- Operator Tests ---
     #[test]
     fn test_add_operator() {
@@ -312,6 +878,46 @@ mod tests {
         assert_eq!(Token::new("&&"), Ok((Token::And, "")));
     }
 
+    #[test]
+    fn test_compound_assignment_operators() {
+        assert_eq!(Token::new("+= 1"), Ok((Token::AddEqual, " 1")));
+        assert_eq!(Token::new("-="), Ok((Token::SubEqual, "")));
+        assert_eq!(Token::new("*="), Ok((Token::MulEqual, "")));
+        assert_eq!(Token::new("/="), Ok((Token::DivEqual, "")));
+        assert_eq!(Token::new("%="), Ok((Token::ModEqual, "")));
+        assert_eq!(Token::new("+ ="), Ok((Token::Add, " =")));
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        assert_eq!(Token::new("& b"), Ok((Token::BitAnd, " b")));
+        assert_eq!(Token::new("|"), Ok((Token::BitOr, "")));
+        assert_eq!(Token::new("^"), Ok((Token::BitXor, "")));
+        assert_eq!(Token::new("~x"), Ok((Token::BitNot, "x")));
+        assert_eq!(Token::new("<<"), Ok((Token::ShiftLeft, "")));
+        assert_eq!(Token::new(">>="), Ok((Token::ShiftRight, "=")));
+        assert_eq!(Token::new("&&"), Ok((Token::And, "")));
+        assert_eq!(Token::new("< <"), Ok((Token::Less, " <")));
+    }
+
+    // --- Comment Tests ---
+    #[test]
+    fn test_comment_len() {
+        assert_eq!(comment_len("// note\nx"), Ok(Some(7)));
+        assert_eq!(comment_len("// note"), Ok(Some(7)));
+        assert_eq!(comment_len("/* a /* b */ c */x"), Ok(Some(17)));
+        assert_eq!(comment_len("/ 2"), Ok(None));
+        assert_eq!(comment_len("/* a /* b */").unwrap_err().len, 12);
+    }
+
+    #[test]
+    fn test_doc_comment_text() {
+        assert_eq!(doc_comment_text("/// Adds one  "), Some("Adds one"));
+        assert_eq!(doc_comment_text("///tight"), Some("tight"));
+        assert_eq!(doc_comment_text("//// banner"), None);
+        assert_eq!(doc_comment_text("// plain"), None);
+    }
+
     // --- Number Literal Tests ---
     #[test]
     fn test_number_literal() {
@@ -344,6 +950,21 @@ mod tests {
         assert_eq!(Token::new("for"), Ok((Token::For, "")));
     }
 
+    #[test]
+    fn test_keyword_in() {
+        assert_eq!(Token::new("in"), Ok((Token::In, "")));
+        assert_eq!(
+            Token::new("index"),
+            Ok((Token::Identifier("index".to_string()), ""))
+        );
+    }
+
+    #[test]
+    fn test_keyword_break_continue() {
+        assert_eq!(Token::new("break;"), Ok((Token::Break, ";")));
+        assert_eq!(Token::new("continue"), Ok((Token::Continue, "")));
+    }
+
     #[test]
     fn test_keyword_while() {
         assert_eq!(Token::new("while"), Ok((Token::While, "")));
@@ -390,13 +1011,15 @@ mod tests {
         assert!(err.is_err());
     }
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_float_literal() {
         assert_eq!(Token::new("3.14"), Ok((Token::Float(3.14), "")));
     }
 
     #[test]
     fn test_float_with_leading_decimal() {
-        assert_eq!(Token::new(".25"), Ok((Token::Dot, "25")));
+        assert_eq!(Token::new(".25"), Ok((Token::Float(0.25), "")));
+        assert_eq!(Token::new(".length"), Ok((Token::Dot, "length")));
     }
 
     #[test]
@@ -405,7 +1028,99 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_float_with_whitespace() {
         assert_eq!(Token::new("  3.14  "), Ok((Token::Float(3.14), "  ")));
     }
+
+    #[test]
+    fn test_prefixed_integers() {
+        assert_eq!(Token::new("0xFF;"), Ok((Token::Integer(255), ";")));
+        assert_eq!(
+            Token::new("0XdEaD_bEeF"),
+            Ok((Token::Integer(0xdead_beef), ""))
+        );
+        assert_eq!(Token::new("0b1010"), Ok((Token::Integer(10), "")));
+        assert_eq!(Token::new("0o777"), Ok((Token::Integer(511), "")));
+        assert_eq!(
+            Token::new("0x7fff_ffff_ffff_ffff"),
+            Ok((Token::Integer(i64::MAX), ""))
+        );
+    }
+
+    #[test]
+    fn test_invalid_prefixed_integers() {
+        let err = Token::new("0b102").unwrap_err();
+        assert_eq!(err.code, codes::INVALID_NUMBER);
+        assert_eq!(err.message, "Invalid digit '2' in binary literal");
+        assert_eq!((err.offset, err.len), (4, 1));
+
+        let err = Token::new("0x;").unwrap_err();
+        assert_eq!(err.message, "Expected digits after '0x'");
+        assert_eq!((err.offset, err.len), (0, 2));
+
+        let err = Token::new("0x1_0000_0000_0000_0000").unwrap_err();
+        assert_eq!(err.len, 23);
+    }
+
+    #[test]
+    fn test_digit_separators() {
+        assert_eq!(Token::new("1_000_000"), Ok((Token::Integer(1_000_000), "")));
+        assert_eq!(Token::new("3_000.250_5"), Ok((Token::Float(3000.2505), "")));
+    }
+
+    #[test]
+    fn test_exponent_floats() {
+        assert_eq!(Token::new("1e-9"), Ok((Token::Float(1e-9), "")));
+        assert_eq!(Token::new("2.5E+3)"), Ok((Token::Float(2500.0), ")")));
+        assert_eq!(Token::new("1_0e2"), Ok((Token::Float(1000.0), "")));
+        let err = Token::new("1e+x").unwrap_err();
+        assert_eq!(err.message, "Expected digits in the exponent");
+        assert_eq!((err.offset, err.len), (1, 2));
+    }
+
+    #[test]
+    fn test_display_source_text() {
+        let source = r#"x . += * ! << 1.0 "a\"b" continue "a ${x + 1} \${b}""#;
+        let mut input = source;
+        let mut printed = Vec::new();
+        while !input.trim_start().is_empty() {
+            let (token, rest) = Token::new(input.trim_start()).unwrap();
+            printed.push(token.to_string());
+            input = rest;
+        }
+        assert_eq!(
+            printed,
+            [
+                "x",
+                ".",
+                "+=",
+                "*",
+                "!",
+                "<<",
+                "1.0",
+                r#""a\"b""#,
+                "continue",
+                r#""a ${...} \${b}""#
+            ]
+        );
+    }
+
+    #[test]
+    fn test_large_integers() {
+        assert_eq!(
+            Token::new("9223372036854775807"),
+            Ok((Token::Integer(i64::MAX), ""))
+        );
+        assert_eq!(
+            Token::new("3000000000"),
+            Ok((Token::Integer(3_000_000_000), ""))
+        );
+        let err = Token::new("9223372036854775808").unwrap_err();
+        assert_eq!(err.code, codes::INVALID_NUMBER);
+        assert_eq!(
+            err.message,
+            "Integer literal is larger than the maximum of 9223372036854775807"
+        );
+    }
 }
@@ -0,0 +1,556 @@
+use std::io::{self, Write};
+
+use crate::{
+    bytecode::{BinaryOp, CodeObject, Instruction, Module},
+    diagnostic::{codes, with_article, Diagnostic},
+    interpreter::{
+        arity_mismatch, binary_op, call_depth_exceeded, element, element_mut, operator_error,
+        type_error, unary_op, Value, MAX_CALL_DEPTH,
+    },
+    span::Span,
+};
+
+// Where to carry on in a function that is waiting on a call.
+struct CallFrame {
+    function: usize,
+    ip: usize,
+    base: usize,
+}
+
+/// A stack machine that runs a compiled `Module`. Each call's locals sit on
+/// the value stack, below the values its instructions work on.
+pub struct Vm<'a> {
+    module: &'a Module,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    output: Box<dyn Write + 'a>,
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(module: &'a Module) -> Self {
+        Vm::with_output(module, io::stdout())
+    }
+
+    /// Creates a VM whose `print` instructions write to `output`.
+    pub fn with_output(module: &'a Module, output: impl Write + 'a) -> Self {
+        Vm {
+            module,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            output: Box::new(output),
+        }
+    }
+
+    /// Runs the program starting from `main` and returns whatever `main` returns.
+    pub fn run(&mut self) -> Result<Value, Diagnostic> {
+        let main = self.module.function("main").ok_or_else(|| {
+            Diagnostic::error(
+                codes::UNDEFINED_FUNCTION,
+                "Undefined function 'main'",
+                Span::default(),
+            )
+        })?;
+        let code = &self.module.functions[main];
+        if code.arity != 0 {
+            return Err(arity_mismatch(
+                "main",
+                code.arity,
+                code.name_span,
+                0,
+                Span::default(),
+            ));
+        }
+
+        self.stack.clear();
+        self.frames.clear();
+        self.stack.resize(code.locals, Value::Void);
+        self.execute(main)
+    }
+
+    fn execute(&mut self, main: usize) -> Result<Value, Diagnostic> {
+        let module = self.module;
+        let mut function = main;
+        let mut code: &CodeObject = &module.functions[function];
+        let mut ip = 0;
+        let mut base = 0;
+
+        loop {
+            let at = ip;
+            ip += 1;
+            let span = || code.spans[at];
+            match code.code[at] {
+                Instruction::Constant(index) => {
+                    self.stack.push(module.constants[index as usize].clone());
+                }
+                Instruction::Void => self.stack.push(Value::Void),
+                Instruction::Pop => {
+                    self.pop();
+                }
+                Instruction::LoadLocal(slot) => {
+                    let value = self.stack[base + slot as usize].clone();
+                    self.stack.push(value);
+                }
+                Instruction::StoreLocal(slot) => {
+                    let value = self.pop();
+                    self.stack[base + slot as usize] = value;
+                }
+                Instruction::UpdateLocal(slot, operator) => {
+                    let value = self.pop();
+                    let slot = &mut self.stack[base + slot as usize];
+                    *slot = binary_op(&operator.token(), slot.clone(), value, span())?;
+                }
+                Instruction::LoadElement { local, path } => {
+                    let path = &code.paths[path as usize];
+                    let indices = self.stack.len() - path.len();
+                    let mut value = &self.stack[base + local as usize];
+                    for (index, &(index_span, access_span)) in
+                        self.stack[indices..].iter().zip(path)
+                    {
+                        value = element(value, index.clone(), index_span, access_span)?;
+                    }
+                    let value = value.clone();
+                    self.stack.truncate(indices);
+                    self.stack.push(value);
+                }
+                Instruction::StoreElement {
+                    local,
+                    path,
+                    operator,
+                } => {
+                    let value = self.pop();
+                    let path = &code.paths[path as usize];
+                    let indices = self.stack.len() - path.len();
+                    // The locals are below the indices, so both can be borrowed
+                    let (below, above) = self.stack.split_at_mut(indices);
+                    let mut slot = &mut below[base + local as usize];
+                    for (index, &(index_span, access_span)) in above.iter().zip(path) {
+                        slot = element_mut(slot, index.clone(), index_span, access_span)?;
+                    }
+                    *slot = match operator {
+                        Some(operator) => {
+                            binary_op(&operator.token(), slot.clone(), value, span())?
+                        }
+                        None => value,
+                    };
+                    self.stack.truncate(indices);
+                }
+                Instruction::Index(path) => {
+                    let (index_span, access_span) = code.paths[path as usize][0];
+                    let index = self.pop();
+                    let array = self.pop();
+                    let value = element(&array, index, index_span, access_span)?.clone();
+                    self.stack.push(value);
+                }
+                Instruction::Length(slot) => {
+                    let length = match &self.stack[base + slot as usize] {
+                        Value::Array(values) => values.len(),
+                        Value::String(s) => s.chars().count(),
+                        other => {
+                            return Err(type_error(
+                                format!(
+                                    "Cannot take the length of {}",
+                                    with_article(other.type_name())
+                                ),
+                                span(),
+                            ))
+                        }
+                    };
+                    self.stack.push(Value::Integer(length as i64));
+                }
+                Instruction::Append(slot) => {
+                    let value = self.pop();
+                    match &mut self.stack[base + slot as usize] {
+                        Value::Array(values) => values.push(value),
+                        other => {
+                            return Err(type_error(
+                                format!("Cannot append to {}", with_article(other.type_name())),
+                                span(),
+                            ))
+                        }
+                    }
+                    self.stack.push(Value::Void);
+                }
+                Instruction::MakeArray(count) => {
+                    let values = self.pop_many(count);
+                    self.stack.push(Value::Array(values));
+                }
+                Instruction::Format(count) => {
+                    let text: String = self
+                        .pop_many(count)
+                        .iter()
+                        .map(|value| value.to_string())
+                        .collect();
+                    self.stack.push(Value::String(text));
+                }
+                Instruction::Binary(operator) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let value = binary_op(&operator.token(), left, right, span())?;
+                    self.stack.push(value);
+                }
+                Instruction::Unary(operator) => {
+                    let operand = self.pop();
+                    let value = unary_op(&operator.token(), operand, span())?;
+                    self.stack.push(value);
+                }
+                Instruction::ShortCircuit { operator, target } => {
+                    let left = match self.pop() {
+                        Value::Boolean(b) => b,
+                        other => return Err(operator_error(&operator.token(), &other, span())),
+                    };
+                    let short_circuit = if operator == BinaryOp::And {
+                        !left
+                    } else {
+                        left
+                    };
+                    if short_circuit {
+                        self.stack.push(Value::Boolean(left));
+                        ip = target as usize;
+                    }
+                }
+                Instruction::CheckBool(operator) => {
+                    let right = self.stack.last().expect("an operand on the stack");
+                    if !matches!(right, Value::Boolean(_)) {
+                        return Err(operator_error(&operator.token(), right, span()));
+                    }
+                }
+                Instruction::Jump(target) => ip = target as usize,
+                Instruction::JumpIfFalse(target) => match self.pop() {
+                    Value::Boolean(true) => {}
+                    Value::Boolean(false) => ip = target as usize,
+                    other => {
+                        return Err(type_error(
+                            format!("Condition must be a boolean, found {}", other.type_name()),
+                            span(),
+                        ))
+                    }
+                },
+                Instruction::IntoIter(slot) => match self.pop() {
+                    array @ Value::Array(_) => {
+                        let slot = base + slot as usize;
+                        self.stack[slot] = array;
+                        self.stack[slot + 1] = Value::Integer(0);
+                    }
+                    other => {
+                        return Err(type_error(
+                            format!("Cannot iterate over {}", with_article(other.type_name())),
+                            span(),
+                        ))
+                    }
+                },
+                Instruction::IterNext { local, exit } => {
+                    let slot = base + local as usize;
+                    let (Value::Array(values), Value::Integer(next)) =
+                        (&self.stack[slot], &self.stack[slot + 1])
+                    else {
+                        unreachable!("IterNext without IntoIter");
+                    };
+                    let next = *next as usize;
+                    match values.get(next) {
+                        Some(value) => {
+                            let value = value.clone();
+                            self.stack[slot + 1] = Value::Integer(next as i64 + 1);
+                            self.stack.push(value);
+                        }
+                        None => ip = exit as usize,
+                    }
+                }
+                Instruction::Call {
+                    function: callee,
+                    argc,
+                } => {
+                    // `main` isn't in `frames`, so they hold one call fewer
+                    // than are running
+                    if self.frames.len() + 1 == MAX_CALL_DEPTH {
+                        return Err(call_depth_exceeded(span()));
+                    }
+                    self.frames.push(CallFrame { function, ip, base });
+                    function = callee as usize;
+                    code = &module.functions[function];
+                    ip = 0;
+                    base = self.stack.len() - argc as usize;
+                    self.stack.resize(base + code.locals, Value::Void);
+                }
+                Instruction::Return => {
+                    let value = self.pop();
+                    self.stack.truncate(base);
+                    match self.frames.pop() {
+                        Some(frame) => {
+                            function = frame.function;
+                            code = &module.functions[function];
+                            ip = frame.ip;
+                            base = frame.base;
+                            self.stack.push(value);
+                        }
+                        None => return Ok(value),
+                    }
+                }
+                Instruction::Print(count) => {
+                    let values: Vec<String> = self
+                        .pop_many(count)
+                        .iter()
+                        .map(|value| value.to_string())
+                        .collect();
+                    writeln!(self.output, "{}", values.join(" ")).map_err(|err| {
+                        Diagnostic::error(
+                            codes::OUTPUT_ERROR,
+                            format!("Failed to write output: {}", err),
+                            span(),
+                        )
+                    })?;
+                }
+                Instruction::Fail(index) => return Err(module.failures[index as usize].clone()),
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("an operand on the stack")
+    }
+
+    fn pop_many(&mut self, count: u32) -> Vec<Value> {
+        self.stack.split_off(self.stack.len() - count as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        get_program,
+        interpreter::{with_call_stack, Interpreter},
+        run_lexer,
+    };
+
+    // Runs `source` on both engines, checks they agree on the result and the
+    // output, and returns them.
+    fn run(source: &str) -> (Result<Value, Diagnostic>, String) {
+        let (tokens, _) = run_lexer(source).unwrap();
+        let program = get_program(&tokens).unwrap();
+
+        let mut expected_output = Vec::new();
+        let expected = Interpreter::with_output(&program, &mut expected_output).run();
+        let module = Module::compile(&program);
+        let mut output = Vec::new();
+        let result = Vm::with_output(&module, &mut output).run();
+
+        assert_eq!(result, expected);
+        assert_eq!(output, expected_output);
+        (result, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_arithmetic_and_locals() {
+        let source = "func main() {
+            let x = 2;
+            let y = x * 3 - 4 % 3;
+            let z = -(y << 2) ^ ~x;
+            return z / 2 + 1.5 > 0.0;
+        }";
+        assert!(run(source).0.is_err());
+        let source = "func main() { let x = 2; let y = x * 3 - 4 % 3; return -(y << 2) ^ ~x; }";
+        assert_eq!(run(source).0, Ok(Value::Integer(17)));
+    }
+
+    #[test]
+    fn test_shadowing_and_scopes() {
+        let source = "func main() {
+            let x = 1;
+            let total = 0;
+            if (true) {
+                let x = x + 10;
+                total += x;
+            }
+            let x = x + 100;
+            for (let i = 0; i < 2; i += 1) {
+                let x = i;
+                total += x;
+            }
+            return total + x;
+        }";
+        assert_eq!(run(source).0, Ok(Value::Integer(113)));
+    }
+
+    #[test]
+    fn test_loops_with_break_and_continue() {
+        let source = "func main() {
+            let total = 0;
+            let i = 0;
+            while (true) {
+                i += 1;
+                if (i % 2 == 0) { continue; }
+                if (i > 9) { break; }
+                total += i;
+            }
+            for (let j = 0; j < 10; j += 1) {
+                if (j == 3) { continue; }
+                if (j == 6) { break; }
+                total += j * 100;
+            }
+            for value in [1, 2, 3, 4] {
+                if (value == 2) { continue; }
+                for other in [10, 20] {
+                    if (other == 20) { break; }
+                    total += value * other * 1000;
+                }
+            }
+            return total;
+        }";
+        assert_eq!(run(source).0, Ok(Value::Integer(81225)));
+    }
+
+    #[test]
+    fn test_for_in_walks_a_snapshot() {
+        let source = "func main() {
+            let values = [1, 2, 3];
+            for value in values {
+                values.append(value);
+            }
+            return values.length;
+        }";
+        assert_eq!(run(source).0, Ok(Value::Integer(6)));
+    }
+
+    #[test]
+    fn test_recursion_and_calls() {
+        let source = "func fib(n) {
+            if (n < 2) { return n; }
+            return fib(n - 1) + fib(n - 2);
+        }
+        func nothing() {}
+        func main() {
+            print(nothing());
+            return fib(15);
+        }";
+        assert_eq!(run(source), (Ok(Value::Integer(610)), "void\n".to_string()));
+    }
+
+    #[test]
+    fn test_deep_recursion_stops_at_the_same_depth() {
+        let source = |n| {
+            format!(
+                "func f(n) {{ if (n == 0) {{ return 0; }} return f(n - 1) + 1; }}
+                func main() {{ return f({}); }}",
+                n
+            )
+        };
+        let depth = MAX_CALL_DEPTH - 2;
+        let (result, _) = with_call_stack(|| run(&source(depth)));
+        assert_eq!(result, Ok(Value::Integer(depth as i64)));
+        let (result, _) = with_call_stack(|| run(&source(depth + 1)));
+        assert_eq!(result.unwrap_err().code, codes::CALL_DEPTH_EXCEEDED);
+
+        // Without a base case neither engine runs out of memory
+        let source = "func f(n) { return f(n + 1); } func main() { return f(0); }";
+        let (result, _) = with_call_stack(|| run(source));
+        assert_eq!(result.unwrap_err().code, codes::CALL_DEPTH_EXCEEDED);
+    }
+
+    #[test]
+    fn test_nested_array_updates() {
+        let source = "func main() {
+            let grid = [[1, 2], [3, 4]];
+            grid[1][0] = 30;
+            grid[0][1] *= 5;
+            let row = grid[0];
+            row.append(7);
+            row[0] = 99;
+            print(grid, row, grid[1][0], grid.length);
+            return 0;
+        }";
+        let (result, output) = run(source);
+        assert_eq!(result, Ok(Value::Integer(0)));
+        assert_eq!(output, "[[1, 10], [30, 4]] [99, 10, 7] 30 2\n");
+    }
+
+    #[test]
+    fn test_strings_and_interpolation() {
+        let source = r#"func main() {
+            let name = "elden";
+            let parts = ["a", "b"];
+            print("${name} has ${name.length} letters and ${parts}", 1.0, true);
+            return name;
+        }"#;
+        let (result, output) = run(source);
+        assert_eq!(result, Ok(Value::String("elden".to_string())));
+        assert_eq!(output, "elden has 5 letters and [\"a\", \"b\"] 1.0 true\n");
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        let source = "func main() {
+            let values = [];
+            if (false && values.append(1)) {}
+            if (true || values.append(2)) {}
+            return values.length == 0 && !(true && false) || false;
+        }";
+        assert_eq!(run(source).0, Ok(Value::Boolean(true)));
+        let source = "func main() { return true && 1; }";
+        assert!(run(source).0.is_err());
+        let source = "func main() { return 1 || true; }";
+        assert!(run(source).0.is_err());
+    }
+
+    #[test]
+    fn test_runtime_errors_match() {
+        let sources = [
+            "func main() { return 1 / 0; }",
+            "func main() { let arr = [1]; return arr[0][0]; }",
+            "func main() { let grid = [[1]]; grid[0][3] = 1; return 0; }",
+            "func main() { let arr = [1]; arr[true] += 1; return 0; }",
+            "func main() { let x = 1; return x.length; }",
+            "func main() { let x = 1; x.append(2); return 0; }",
+            "func main() { if (1) {} return 0; }",
+            "func main() { for x in 5 {} return 0; }",
+            "func main() { return -(-9223372036854775807 - 1); }",
+            "func main() { let x = 9223372036854775807; x += 1; return x; }",
+            "func main() { return 1 << 64; }",
+            "func main() { return \"a\" - 1; }",
+        ];
+        for source in sources {
+            assert!(run(source).0.is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_compile_time_failures_only_fire_when_reached() {
+        let source = "func add(a, b) { return a + b; }
+        func main() {
+            if (false) {
+                print(missing, nothing(), add(1));
+            }
+            return 1;
+        }";
+        assert_eq!(run(source).0, Ok(Value::Integer(1)));
+
+        for call in [
+            "missing",
+            "nothing()",
+            "add(1)",
+            "y[0]",
+            "y = 2",
+            "y.append(1)",
+        ] {
+            let source = format!(
+                "func add(a, b) {{ return a + b; }} func main() {{ {}; return 0; }}",
+                call
+            );
+            assert!(run(&source).0.is_err(), "{}", call);
+        }
+    }
+
+    #[test]
+    fn test_missing_main() {
+        let (result, _) = run("func helper() { return 1; }");
+        assert_eq!(result.unwrap_err().code, codes::UNDEFINED_FUNCTION);
+        let (result, _) = run("func main(x) { return x; }");
+        assert_eq!(result.unwrap_err().code, codes::ARITY_MISMATCH);
+    }
+
+    #[test]
+    fn test_break_outside_a_loop_ends_the_function() {
+        let source = "func stop() { print(1); break; print(2); }
+        func main() { stop(); return 3; }";
+        assert_eq!(run(source), (Ok(Value::Integer(3)), "1\n".to_string()));
+    }
+}